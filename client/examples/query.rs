@@ -1,7 +1,5 @@
-use client::{CustomError, XtdbClient, XtqlQuery};
-use serde_json::{json, Value};
+use client::{Error, XtdbClient, XtqlQuery};
 use std::{env, fs, io, io::Read};
-use xtql::parse_xtql;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -11,41 +9,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let content = if args.len() == 2 {
         let filepath = &args[1];
-        fs::read_to_string(filepath).expect("Failed to read file")
+        fs::read_to_string(filepath)?
     } else {
         let mut buffer = String::new();
-        io::stdin()
-            .read_to_string(&mut buffer)
-            .expect("Failed to read from stdin");
+        io::stdin().read_to_string(&mut buffer)?;
         buffer
     };
 
-    let query = XtqlQuery {
-        query: parse_xtql(&content).unwrap(),
-        options: json!({}),
-    };
+    let query = XtqlQuery::parse(&content)?;
 
     match client.execute_query(query).await {
         Ok(response) => {
-            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+            println!("{}", serde_json::to_string_pretty(&response)?);
             Ok(())
         }
-        Err(e) => match e {
-            CustomError::XtdbError(ref err) => {
-                if let Ok(parsed_json) = serde_json::from_str::<Value>(err) {
-                    eprintln!(
-                        "Error: {}",
-                        serde_json::to_string_pretty(&parsed_json).unwrap()
-                    );
-                } else {
-                    eprintln!("Error: {}", err); // Fallback in case the error is not valid JSON
+        Err(e) => {
+            match e {
+                Error::Server(ref err) => {
+                    eprintln!("Error ({:?}): {}", err.kind, err);
+                    if !err.data.is_empty() {
+                        eprintln!("{}", serde_json::to_string_pretty(&err.data)?);
+                    }
                 }
-                Err(Box::new(e) as Box<dyn std::error::Error>)
-            }
-            _ => {
-                eprintln!("An error occurred: {}", e); // Print to stderr
-                Err(Box::new(e) as Box<dyn std::error::Error>)
+                _ => eprintln!("An error occurred: {}", e), // Print to stderr
             }
-        },
+            Err(e.into())
+        }
     }
 }
@@ -0,0 +1,277 @@
+use crate::{Error, QueryOptions, RetryPolicy, XtdbClient};
+use reqwest::{
+    header::{HeaderName, HeaderValue, USER_AGENT},
+    Certificate, Client, Url,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Auth {
+    Bearer(String),
+    Basic(String, Option<String>),
+}
+
+/// Configures an [`XtdbClient`]. Nothing is validated until [`build`](Self::build).
+pub struct XtdbClientBuilder {
+    base_url: String,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    default_options: QueryOptions,
+    auth: Option<Auth>,
+    headers: Vec<(String, String)>,
+    user_agent: Option<String>,
+    root_certificates: Vec<Certificate>,
+    client: Option<Client>,
+    retry: Option<RetryPolicy>,
+    policy: Option<xtql::Policy>,
+    row_filters: Option<xtql::RowFilters>,
+    fragments: Option<xtql::Fragments>,
+}
+
+impl XtdbClientBuilder {
+    pub fn new(base_url: &str) -> Self {
+        XtdbClientBuilder {
+            base_url: base_url.to_string(),
+            connect_timeout: None,
+            timeout: None,
+            default_options: QueryOptions::default(),
+            auth: None,
+            headers: vec![],
+            user_agent: None,
+            root_certificates: vec![],
+            client: None,
+            retry: None,
+            policy: None,
+            row_filters: None,
+            fragments: None,
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds each request, from sending it to reading the end of the body.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Options sent with every query. Options set on a query take precedence;
+    /// its `args` are added to the default ones, replacing those of the same name.
+    pub fn default_options(mut self, options: QueryOptions) -> Self {
+        self.default_options = options;
+        self
+    }
+
+    pub fn bearer_auth(mut self, token: &str) -> Self {
+        self.auth = Some(Auth::Bearer(token.to_string()));
+        self
+    }
+
+    pub fn basic_auth(mut self, username: &str, password: Option<&str>) -> Self {
+        self.auth = Some(Auth::Basic(
+            username.to_string(),
+            password.map(str::to_string),
+        ));
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Reuses an existing `reqwest::Client`, e.g. to share its connection pool.
+    /// Transport settings (connect timeout, root certificates) must then be
+    /// configured on that client instead.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Enables retries of transient failures. Off by default.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Checks every query against `policy` before sending it. Queries that
+    /// violate it, or that were not parsed from XTQL text and so cannot be
+    /// checked, fail with [`Error::Policy`].
+    pub fn policy(mut self, policy: xtql::Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Rewrites every query to apply `filters` before checking and sending
+    /// it. Queries that cannot be rewritten safely, or that were not parsed
+    /// from XTQL text, fail with [`Error::Policy`].
+    pub fn row_filters(mut self, filters: xtql::RowFilters) -> Self {
+        self.row_filters = Some(filters);
+        self
+    }
+
+    /// Expands the `(fragment ...)` clauses of every query with `fragments`
+    /// before anything else. Queries using unknown fragments, or using them
+    /// wrongly, fail with [`Error::Fragment`].
+    pub fn fragments(mut self, fragments: xtql::Fragments) -> Self {
+        self.fragments = Some(fragments);
+        self
+    }
+
+    pub fn build(self) -> Result<XtdbClient, Error> {
+        let url = Url::parse(&self.base_url)
+            .map_err(|e| Error::Config(format!("invalid base URL {}: {}", self.base_url, e)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::Config(format!(
+                "unsupported scheme in base URL {}",
+                self.base_url
+            )));
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(Error::Config(format!(
+                "base URL {} must not have a query or fragment",
+                self.base_url
+            )));
+        }
+
+        let mut headers = XtdbClient::default_headers();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::Config(format!("invalid header name {}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::Config(format!("invalid value for header {}: {}", name, e)))?;
+            headers.insert(name, value);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            let value = HeaderValue::from_str(user_agent)
+                .map_err(|e| Error::Config(format!("invalid user agent: {}", e)))?;
+            headers.insert(USER_AGENT, value);
+        }
+
+        let client = match self.client {
+            Some(client) => {
+                if self.connect_timeout.is_some() || !self.root_certificates.is_empty() {
+                    return Err(Error::Config(
+                        "connect timeout and root certificates cannot be applied to an existing client"
+                            .to_string(),
+                    ));
+                }
+                client
+            }
+            None => {
+                let mut builder = Client::builder();
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                for certificate in self.root_certificates {
+                    builder = builder.add_root_certificate(certificate);
+                }
+                builder.build()?
+            }
+        };
+
+        Ok(XtdbClient {
+            base_url: url.as_str().trim_end_matches('/').to_string(),
+            headers,
+            client,
+            auth: self.auth,
+            timeout: self.timeout,
+            default_options: self.default_options,
+            retry: self.retry,
+            policy: self.policy,
+            row_filters: self.row_filters,
+            fragments: self.fragments,
+            latest_transaction: Arc::new(RwLock::new(None)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed certificate, only used to exercise the builder.
+    const CERTIFICATE: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBfzCCASWgAwIBAgIUF+YeKuW7cufX1G0/ub5v/ijmm6IwCgYIKoZIzj0EAwIw\n\
+FDESMBAGA1UEAwwJeHRkYi10ZXN0MCAXDTI2MTAxOTA4MDczOVoYDzIxMjYwOTI1\n\
+MDgwNzM5WjAUMRIwEAYDVQQDDAl4dGRiLXRlc3QwWTATBgcqhkjOPQIBBggqhkjO\n\
+PQMBBwNCAARyuXeoxP9jmKKV18lgcgIj5Qu3viVtb/YsOELFNzIRWdflC7/5aCS8\n\
+ZWa/UxsWY1JmKsWTu+7LFAXaUQJCdy44o1MwUTAdBgNVHQ4EFgQUNhS6BJv5FyX1\n\
+hSirB4r9F04JU1IwHwYDVR0jBBgwFoAUNhS6BJv5FyX1hSirB4r9F04JU1IwDwYD\n\
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA+Ws4zgQMdLTI7WHDMpql\n\
+JQNijkejKdcyxiq5nZrbe9gCIAC0AHAWnfIGc4VSKWF7FkDIeQ5E+wazS+kFrVP5\n\
+fVdM\n\
+-----END CERTIFICATE-----\n";
+
+    fn config_error(builder: XtdbClientBuilder) -> String {
+        match builder.build() {
+            Err(Error::Config(message)) => message,
+            Err(other) => panic!("expected a config error, found {}", other),
+            Ok(_) => panic!("expected a config error"),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_schemes() {
+        assert_eq!(
+            config_error(XtdbClientBuilder::new("ftp://localhost:3000")),
+            "unsupported scheme in base URL ftp://localhost:3000"
+        );
+        assert!(config_error(XtdbClientBuilder::new("localhost:3000")).contains("scheme"));
+        assert!(config_error(XtdbClientBuilder::new("not a url")).starts_with("invalid base URL"));
+    }
+
+    #[test]
+    fn rejects_queries_and_fragments() {
+        for url in ["http://localhost:3000/?db=x", "http://localhost:3000/#top"] {
+            assert_eq!(
+                config_error(XtdbClientBuilder::new(url)),
+                format!("base URL {} must not have a query or fragment", url)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_transport_settings_with_an_existing_client() {
+        let message =
+            "connect timeout and root certificates cannot be applied to an existing client";
+        let builder = XtdbClientBuilder::new("http://localhost:3000")
+            .client(Client::new())
+            .connect_timeout(Duration::from_secs(1));
+        assert_eq!(config_error(builder), message);
+        let certificate = Certificate::from_pem(CERTIFICATE.as_bytes()).unwrap();
+        let builder = XtdbClientBuilder::new("http://localhost:3000")
+            .client(Client::new())
+            .add_root_certificate(certificate.clone());
+        assert_eq!(config_error(builder), message);
+        XtdbClientBuilder::new("https://localhost:3000")
+            .connect_timeout(Duration::from_secs(1))
+            .add_root_certificate(certificate)
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn trims_the_trailing_slash() {
+        let client = XtdbClientBuilder::new("http://localhost:3000/xtdb/")
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url, "http://localhost:3000/xtdb");
+    }
+}
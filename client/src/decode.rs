@@ -0,0 +1,133 @@
+use crate::Error;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use xtql::value::SET;
+use xtql::XtValue;
+
+/// Deserialises the `row`-th result row into `T`, after replacing XTDB's typed
+/// values with the plain representations chrono, time, uuid and std expect.
+pub(crate) fn from_row<T: DeserializeOwned>(row: usize, value: Value) -> Result<T, Error> {
+    serde_path_to_error::deserialize(plain(value)).map_err(|e| Error::RowDecode {
+        row,
+        column: e.path().to_string(),
+        error: e.into_inner(),
+    })
+}
+
+/// Replaces the typed values in `value` with plain JSON. Values whose
+/// `@type` is not one [`XtValue`] knows are left as they are, so rows
+/// holding types newer than this client still reach `T`.
+fn plain(value: Value) -> Value {
+    match value {
+        Value::Object(map) if map.len() == 2 && map.contains_key("@type") => {
+            if map.get("@type").and_then(Value::as_str) == Some(SET) {
+                if let Some(Value::Array(items)) = map.get("@value") {
+                    return Value::Array(items.iter().cloned().map(plain).collect());
+                }
+            }
+            let typed = Value::Object(map);
+            match XtValue::try_from(typed.clone()) {
+                Ok(value) => plain_value(value),
+                Err(_) => typed,
+            }
+        }
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| (k, plain(v)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(plain).collect()),
+        other => other,
+    }
+}
+
+/// Converts a typed value into plain JSON: sets become arrays, keywords,
+/// temporal values, uuids and decimals become their string form. Zone ids
+/// (`...Z[Europe/London]`) are dropped from zoned date-times since neither
+/// chrono nor time parse them.
+fn plain_value(value: XtValue) -> Value {
+    match value {
+        XtValue::Null => Value::Null,
+        XtValue::Bool(b) => json!(b),
+        XtValue::Int(i) => json!(i),
+        XtValue::Double(d) => json!(d),
+        XtValue::ZonedDateTime(s) => json!(strip_zone_id(&s)),
+        XtValue::Decimal(s)
+        | XtValue::String(s)
+        | XtValue::Keyword(s)
+        | XtValue::Uuid(s)
+        | XtValue::Instant(s)
+        | XtValue::Date(s)
+        | XtValue::DateTime(s)
+        | XtValue::Duration(s)
+        | XtValue::Period(s)
+        | XtValue::Time(s)
+        | XtValue::TimeZone(s) => json!(s),
+        XtValue::Set(values) | XtValue::List(values) => {
+            Value::Array(values.into_iter().map(plain_value).collect())
+        }
+        XtValue::Map(map) => {
+            Value::Object(map.into_iter().map(|(k, v)| (k, plain_value(v))).collect())
+        }
+    }
+}
+
+fn strip_zone_id(s: &str) -> &str {
+    match (s.find('['), s.ends_with(']')) {
+        (Some(i), true) => &s[..i],
+        _ => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Row {
+        d: String,
+        t: String,
+        tags: Vec<String>,
+        other: Value,
+    }
+
+    #[test]
+    fn typed_values_become_plain() {
+        let row = json!({
+            "d": {"@type": "xt:date", "@value": "2024-01-02"},
+            "t": {"@type": "xt:time", "@value": "12:34:56"},
+            "tags": {"@type": "xt:set", "@value": [{"@type": "xt:keyword", "@value": "a"}]},
+            "other": {"@type": "xt:timestamptz", "@value": "2024-01-02T00:00Z[Europe/London]"}
+        });
+        let row: Row = from_row(0, row).unwrap();
+        assert_eq!(row.d, "2024-01-02");
+        assert_eq!(row.t, "12:34:56");
+        assert_eq!(row.tags, vec!["a"]);
+        assert_eq!(row.other, json!("2024-01-02T00:00Z"));
+    }
+
+    #[test]
+    fn unknown_types_pass_through() {
+        let unknown = json!({"@type": "xt:something-new", "@value": {"a": 1}});
+        let row =
+            json!({"x": unknown, "xs": [unknown], "set": {"@type": "xt:set", "@value": [unknown]}});
+        let decoded: Value = from_row(0, row).unwrap();
+        assert_eq!(
+            decoded,
+            json!({"x": unknown, "xs": [unknown], "set": [unknown]})
+        );
+    }
+
+    #[test]
+    fn errors_name_the_column() {
+        let row = json!({"d": 1, "t": "x", "tags": [], "other": null});
+        match from_row::<Row>(3, row) {
+            Err(Error::RowDecode { row, column, .. }) => {
+                assert_eq!(row, 3);
+                assert_eq!(column, "d");
+            }
+            other => panic!("expected a row decode error, got {:?}", other),
+        }
+    }
+}
@@ -1,52 +1,154 @@
-use crate::xtql::parse::Rule;
-use std::fmt::{self, Debug};
+use crate::ServerError;
+use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    PestParse(Box<pest::error::Error<Rule>>),
-    IO(std::io::Error),
+    /// The client was configured with invalid settings.
+    Config(String),
+    /// The XTQL text could not be parsed.
+    Xtql(xtql::Error),
+    /// The bound parameters do not match the query's `$params`.
+    Params {
+        missing: Vec<String>,
+        unused: Vec<String>,
+    },
+    /// The query violates the client's policy, or its row filters cannot be
+    /// applied safely.
+    Policy(Vec<xtql::Diagnostic>),
+    /// The query uses fragments that could not be expanded.
+    Fragment(Vec<xtql::Diagnostic>),
+    /// The query has unbound variables, unknown functions or type errors.
+    Invalid(Vec<xtql::Diagnostic>),
+    /// Files of a [`QueryLibrary`](crate::QueryLibrary) could not be read,
+    /// parsed or checked.
+    Library(Vec<(std::path::PathBuf, Error)>),
+    /// The [`QueryLibrary`](crate::QueryLibrary) has no query by that name.
+    UnknownQuery(String),
+    /// The request could not be sent or the response body could not be read.
+    Transport(reqwest::Error),
+    /// The request timed out. Carries the transport error when the timeout
+    /// was detected by `reqwest` rather than by a client-side deadline.
+    Timeout(Option<reqwest::Error>),
+    /// The request was cancelled through its cancellation token.
+    Cancelled,
+    /// A response line was not valid JSON.
+    Decode(serde_json::Error),
+    /// A row could not be deserialised into the requested type.
+    RowDecode {
+        row: usize,
+        column: String,
+        error: serde_json::Error,
+    },
+    /// XTDB rejected the request.
+    Server(Box<ServerError>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Error::*;
         match self {
-            PestParse(err) => match err.line_col {
-                pest::error::LineColLocation::Pos(pos) => {
-                    write!(f, "Parse Error: at {}:{}", pos.0, pos.1)
+            Config(msg) => write!(f, "Configuration Error: {}", msg),
+            Xtql(err) => write!(f, "{}", err),
+            Params { missing, unused } => {
+                write!(f, "Parameter Error:")?;
+                if !missing.is_empty() {
+                    write!(f, " missing ${}", missing.join(", $"))?;
                 }
-                pest::error::LineColLocation::Span(start, end) => {
-                    write!(
-                        f,
-                        "Parse Error: at {}:{} to {}:{}",
-                        start.0, start.1, end.0, end.1
-                    )
+                if !missing.is_empty() && !unused.is_empty() {
+                    write!(f, ";")?;
                 }
-            },
-
-            IO(err) => write!(f, "IO Error: {}", err),
+                if !unused.is_empty() {
+                    write!(f, " unused ${}", unused.join(", $"))?;
+                }
+                Ok(())
+            }
+            Policy(violations) => {
+                write!(f, "Policy Error:")?;
+                diagnostics(f, violations)
+            }
+            Fragment(errors) => {
+                write!(f, "Fragment Error:")?;
+                diagnostics(f, errors)
+            }
+            Invalid(errors) => {
+                write!(f, "Invalid Query:")?;
+                diagnostics(f, errors)
+            }
+            Library(errors) => {
+                write!(f, "Library Error:")?;
+                for (i, (path, error)) in errors.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { "; " };
+                    write!(f, "{}{}: {}", sep, path.display(), error)?;
+                }
+                Ok(())
+            }
+            UnknownQuery(name) => write!(f, "Unknown Query: `{}`", name),
+            Transport(err) => write!(f, "Transport Error: {}", err),
+            Timeout(Some(err)) => write!(f, "Timeout: {}", err),
+            Timeout(None) => write!(f, "Timeout: deadline elapsed"),
+            Cancelled => write!(f, "Cancelled"),
+            Decode(err) => write!(f, "Decode Error: {}", err),
+            RowDecode { row, column, error } => {
+                write!(
+                    f,
+                    "Decode Error: row {}, column `{}`: {}",
+                    row, column, error
+                )
+            }
+            Server(err) => write!(f, "XTDB Error: {}", err),
         }
     }
 }
 
+fn diagnostics(f: &mut fmt::Formatter, diagnostics: &[xtql::Diagnostic]) -> fmt::Result {
+    for (i, diagnostic) in diagnostics.iter().enumerate() {
+        let sep = if i == 0 { " " } else { "; " };
+        write!(f, "{}{}: {}", sep, diagnostic.span, diagnostic.message)?;
+    }
+    Ok(())
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         use Error::*;
         match *self {
-            PestParse(ref err) => Some(err),
-            IO(ref err) => Some(err),
+            Config(_) | Params { .. } | Policy(_) | Fragment(_) | Invalid(_) => None,
+            Library(_) | UnknownQuery(_) => None,
+            Xtql(ref err) => Some(err),
+            Transport(ref err) => Some(err),
+            Timeout(Some(ref err)) => Some(err),
+            Timeout(None) | Cancelled => None,
+            Decode(ref err) => Some(err),
+            RowDecode { ref error, .. } => Some(error),
+            Server(ref err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<xtql::Error> for Error {
+    fn from(err: xtql::Error) -> Self {
+        Error::Xtql(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::Timeout(Some(err))
+        } else {
+            Error::Transport(err)
         }
     }
 }
 
-impl From<pest::error::Error<Rule>> for Error {
-    fn from(err: pest::error::Error<Rule>) -> Self {
-        Error::PestParse(Box::new(err))
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Decode(err)
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::IO(err)
+impl From<ServerError> for Error {
+    fn from(err: ServerError) -> Self {
+        Error::Server(Box::new(err))
     }
 }
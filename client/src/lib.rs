@@ -1,96 +1,310 @@
-//use futures_util::stream::StreamExt;
+use futures_util::stream::Stream;
 use reqwest::{
     header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE},
     Client,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::error::Error;
-use std::fmt;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 
-#[derive(Debug, Serialize, Deserialize)]
+mod builder;
+mod decode;
+mod error;
+mod library;
+mod options;
+mod request;
+mod retry;
+mod server_error;
+mod stream;
+#[cfg(test)]
+mod test_server;
+
+use builder::Auth;
+pub use builder::XtdbClientBuilder;
+pub use error::Error;
+pub use library::{LibraryChecks, QueryLibrary};
+pub use options::{Basis, KeyFn, QueryOptions, TxKey};
+pub use request::QueryRequest;
+pub use retry::RetryPolicy;
+pub use server_error::{ServerError, ServerErrorKind};
+pub use stream::RowStream;
+pub use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct XtqlQuery {
     pub query: Value,
-    pub options: Value,
+    pub options: QueryOptions,
+    /// The parsed query, when built from XTQL text; needed to check it
+    /// against the client's [`xtql::Policy`].
+    #[serde(skip)]
+    pub ast: Option<xtql::ast::Query>,
 }
 
-#[derive(Debug)]
-pub enum CustomError {
-    SerdeJsonError(serde_json::Error),
-    ReqwestError(reqwest::Error),
-    XtdbError(String),
+impl XtqlQuery {
+    pub fn new(query: Value) -> Self {
+        XtqlQuery {
+            query,
+            options: QueryOptions::default(),
+            ast: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: QueryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Encodes a parsed (and possibly rewritten) query, with default options.
+    pub fn from_ast(ast: xtql::ast::Query) -> Result<Self, xtql::Error> {
+        Ok(XtqlQuery {
+            query: ast.to_json()?,
+            options: QueryOptions::default(),
+            ast: Some(ast),
+        })
+    }
+
+    /// Parses XTQL EDN text into a query with default options. A query that
+    /// uses fragments is only encoded once the client has expanded them, so
+    /// its `query` is `null` until then.
+    pub fn parse(content: &str) -> Result<Self, xtql::Error> {
+        let ast = xtql::parse_query(content)?;
+        let query = if xtql::collect_fragments(&ast).is_empty() {
+            xtql::parse_xtql(content)?
+        } else {
+            Value::Null
+        };
+        Ok(XtqlQuery {
+            ast: Some(ast),
+            ..XtqlQuery::new(query)
+        })
+    }
 }
 
-impl Error for CustomError {}
+/// A transaction submission: XTDB JSON transaction operations sent to `/tx`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct XtqlTx {
+    pub tx_ops: Vec<Value>,
+    pub options: Value,
+    /// Allows the retry policy to resubmit this transaction, which is only
+    /// correct if applying it twice has the same effect as applying it once.
+    #[serde(skip)]
+    pub retry_safe: bool,
+}
 
-impl fmt::Display for CustomError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CustomError::SerdeJsonError(e) => e.fmt(f),
-            CustomError::ReqwestError(e) => e.fmt(f),
-            CustomError::XtdbError(e) => e.fmt(f),
+impl XtqlTx {
+    pub fn new(tx_ops: Vec<Value>) -> Self {
+        XtqlTx {
+            tx_ops,
+            options: json!({}),
+            retry_safe: false,
         }
     }
+
+    pub fn retry_safe(mut self) -> Self {
+        self.retry_safe = true;
+        self
+    }
 }
 
+/// Cheap to clone: clones share the HTTP connection pool and the latest
+/// transaction id.
+#[derive(Clone)]
 pub struct XtdbClient {
     base_url: String,
     headers: HeaderMap,
     client: Client,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    default_options: QueryOptions,
+    retry: Option<RetryPolicy>,
+    policy: Option<xtql::Policy>,
+    row_filters: Option<xtql::RowFilters>,
+    fragments: Option<xtql::Fragments>,
     latest_transaction: Arc<RwLock<Option<u64>>>,
 }
 
 impl XtdbClient {
     pub fn new(base_url: &str) -> Self {
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        headers.insert(ACCEPT, HeaderValue::from_static("application/jsonl"));
         XtdbClient {
             base_url: base_url.to_string(),
             client: Client::new(),
-            headers,
+            headers: Self::default_headers(),
+            auth: None,
+            timeout: None,
+            default_options: QueryOptions::default(),
+            retry: None,
+            policy: None,
+            row_filters: None,
+            fragments: None,
             latest_transaction: Arc::new(RwLock::new(None)),
         }
     }
-    pub async fn execute_query(&self, query: XtqlQuery) -> Result<Vec<Value>, CustomError> {
-        let url = format!("{}/query", self.base_url);
+
+    pub fn builder(base_url: &str) -> XtdbClientBuilder {
+        XtdbClientBuilder::new(base_url)
+    }
+
+    fn default_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/jsonl"));
+        headers
+    }
+    /// Prepares `query` for execution; await the result to collect all rows,
+    /// or configure a timeout or cancellation token first.
+    pub fn execute_query(&self, query: XtqlQuery) -> QueryRequest<'_> {
+        QueryRequest::new(self, query)
+    }
+
+    /// Executes `query` and yields result rows as the `application/jsonl` body
+    /// arrives, rather than buffering the whole response.
+    pub async fn query_stream(&self, query: XtqlQuery) -> Result<RowStream, Error> {
+        self.execute_query(query).stream().await
+    }
+
+    /// Executes `query` and deserialises every row into `T`.
+    pub async fn query_as<T: DeserializeOwned>(&self, query: XtqlQuery) -> Result<Vec<T>, Error> {
+        self.execute_query(query).collect_as().await
+    }
+
+    /// Streaming counterpart of [`XtdbClient::query_as`].
+    pub async fn query_stream_as<T: DeserializeOwned + Send + 'static>(
+        &self,
+        query: XtqlQuery,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>, Error> {
+        self.execute_query(query).stream_as().await
+    }
+
+    /// Submits a transaction and records its id as the latest transaction.
+    /// Returns the transaction key XTDB responds with.
+    pub async fn submit_tx(&self, tx: XtqlTx) -> Result<TxKey, Error> {
+        let body = json!({
+            "txOps": tx.tx_ops,
+            "opts": tx.options,
+        });
+        let resp = self.send("tx", &body, tx.retry_safe).await?;
+        let tx_key: TxKey = serde_json::from_slice(&resp.bytes().await?)?;
+        self.set_latest_transaction(tx_key.tx_id).await;
+        Ok(tx_key)
+    }
+
+    /// Fetches the tables and columns of the node's `public` schema from
+    /// `information_schema`, for checking queries with [`xtql::Schema::check`]
+    /// before sending them.
+    pub async fn fetch_schema(&self) -> Result<xtql::Schema, Error> {
+        #[derive(Deserialize)]
+        struct Column {
+            table_name: String,
+            column_name: String,
+            data_type: Option<String>,
+        }
+
+        let query = XtqlQuery::parse(
+            "(from :information_schema/columns \
+             [{:table-schema \"public\"} table-name column-name data-type])",
+        )?
+        .with_options(QueryOptions::new().key_fn(KeyFn::SnakeCaseString));
+        let columns: Vec<Column> = self.execute_query(query).unchecked().collect_as().await?;
+        let mut schema = xtql::Schema::new();
+        for column in columns {
+            let data_type = column.data_type.as_deref().unwrap_or_default();
+            schema.add_column(
+                &column.table_name,
+                &column.column_name,
+                xtql::schema::parse_type(data_type),
+            );
+        }
+        Ok(schema)
+    }
+
+    async fn send_query(
+        &self,
+        query: XtqlQuery,
+        deadline: Option<Instant>,
+    ) -> Result<reqwest::Response, Error> {
+        let mut options = query.options.or_defaults(&self.default_options).to_json();
+        // `txTimeout` only bounds the wait for `afterTx`, so it is only worth
+        // sending when there is one; the deadline itself is enforced here.
+        if let Some(deadline) = deadline.filter(|_| options.contains_key("afterTx")) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            options
+                .entry("txTimeout")
+                .or_insert_with(|| json!(options::iso_duration(remaining)));
+        }
         let query = json!({
             "query": query.query,
-            "queryOpts": query.options,
+            "queryOpts": options,
         });
+        self.send("query", &query, true).await
+    }
 
-        let response = self
+    /// POSTs `body` to `path`, retrying transient failures according to the
+    /// retry policy if the request is `idempotent`.
+    async fn send(
+        &self,
+        path: &str,
+        body: &Value,
+        idempotent: bool,
+    ) -> Result<reqwest::Response, Error> {
+        let mut attempt = 1;
+        loop {
+            match self.send_once(path, body).await {
+                Err(err) => match &self.retry {
+                    Some(policy)
+                        if idempotent
+                            && attempt < policy.max_attempts
+                            && policy.is_retryable(&err) =>
+                    {
+                        tokio::time::sleep(policy.delay(attempt)).await;
+                        attempt += 1;
+                    }
+                    _ => return Err(err),
+                },
+                ok => return ok,
+            }
+        }
+    }
+
+    async fn send_once(&self, path: &str, body: &Value) -> Result<reqwest::Response, Error> {
+        let url = format!("{}/{}", self.base_url, path);
+        let mut request = self
             .client
             .post(&url)
             .headers(self.headers.clone())
-            .json(&query)
-            .send()
-            .await;
+            .json(body);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        request = match &self.auth {
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            Some(Auth::Basic(username, password)) => {
+                request.basic_auth(username, password.as_ref())
+            }
+            None => request,
+        };
+
+        let response = request.send().await;
 
         match response {
             Ok(resp) => {
                 if resp.status().is_success() {
-                    let mut values = Vec::new();
-                    let body = resp.text().await.map_err(CustomError::ReqwestError)?;
-                    for line in body.lines() {
-                        let parsed_response: Value =
-                            serde_json::from_str(line).map_err(CustomError::SerdeJsonError)?;
-                        values.push(parsed_response);
-                    }
-                    Ok(values)
+                    Ok(resp)
                 } else {
+                    let status = resp.status();
                     // Get the error body text if available
                     let error_body = resp
                         .text()
                         .await
                         .unwrap_or_else(|_| String::from("Failed to retrieve error message"));
-                    Err(CustomError::XtdbError(error_body)) // directly return the error body as a string
+                    Err(ServerError::from_response(status, error_body).into())
                 }
             }
 
-            Err(err) => Err(CustomError::ReqwestError(err)),
+            Err(err) => Err(err.into()),
         }
     }
 
@@ -104,3 +318,53 @@ impl XtdbClient {
         *latest_transaction
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+    use xtql::ast::{Expr, TemporalFilter};
+    use xtql::types::ColumnTypes;
+    use xtql::Type;
+
+    #[test]
+    fn from_ast_encodes_temporal_rewrites() {
+        let ast = xtql::parse_query("(from :orders [o-orderkey])").unwrap();
+
+        let query = XtqlQuery::from_ast(xtql::as_of(&ast, Expr::param("as-of"))).unwrap();
+        assert_eq!(
+            query.query["forSystemTime"],
+            json!({"at": {"xt:param": "$as-of"}})
+        );
+
+        let all_time = xtql::TemporalRewrite::new()
+            .valid_time(TemporalFilter::AllTime)
+            .apply(&ast);
+        let query = XtqlQuery::from_ast(all_time).unwrap();
+        assert_eq!(query.query["forValidTime"], json!("allTime"));
+    }
+
+    #[tokio::test]
+    async fn fetches_the_schema_from_information_schema() {
+        let server = TestServer::start(Response::ok(vec![(
+            Duration::ZERO,
+            "{\"table_name\":\"orders\",\"column_name\":\"o_orderkey\",\"data_type\":\":i64\"}\n\
+             {\"table_name\":\"orders\",\"column_name\":\"o_comment\",\
+             \"data_type\":\"[:union #{:null :utf8}]\"}\n\
+             {\"table_name\":\"line_item\",\"column_name\":\"l_tax\",\"data_type\":null}\n",
+        )]))
+        .await;
+        let schema = XtdbClient::new(&server.url).fetch_schema().await.unwrap();
+        assert_eq!(schema.tables().collect::<Vec<_>>(), ["line_item", "orders"]);
+        assert_eq!(schema.column_type("orders", "o-orderkey"), Some(Type::Int));
+        assert_eq!(
+            schema.column_type("orders", "o-comment"),
+            Some(Type::String.nullable())
+        );
+        assert_eq!(schema.column_type("line-item", "l-tax"), Some(Type::Any));
+        assert_eq!(
+            server.requests()[0]["queryOpts"]["keyFn"],
+            "SNAKE_CASE_STRING"
+        );
+    }
+}
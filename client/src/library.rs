@@ -0,0 +1,441 @@
+use crate::{Error, QueryRequest, XtdbClient, XtqlQuery};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use xtql::ast::Statement;
+use xtql::types::ColumnTypes;
+use xtql::XtValue;
+
+/// Named queries loaded from a directory of `.edn` files.
+///
+/// A file holding a single query is named by its stem (`q5.edn` is `q5`); in
+/// a file holding several, each query is named by its `;; name: ...` comment
+/// or `^{:name ...}` metadata. Every query is parsed, has its fragments
+/// expanded and is checked when the library is loaded, so mistakes surface at
+/// startup rather than when the query is first run; [`LibraryChecks`] chooses
+/// which checks run.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), client::Error> {
+/// use client::{QueryLibrary, XtdbClient};
+///
+/// let client = XtdbClient::new("http://localhost:3000");
+/// let library = QueryLibrary::load(client, "queries")?;
+/// let rows = library.run("q5", [("region", "ASIA")])?.await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct QueryLibrary {
+    client: XtdbClient,
+    dir: PathBuf,
+    reload: bool,
+    checks: LibraryChecks,
+    state: RwLock<State>,
+}
+
+/// The checks a [`QueryLibrary`] runs on each query as it loads. Unbound
+/// variables are always reported; by default so are calls that do not match
+/// the [standard](xtql::Catalogue::standard) catalogue and type errors.
+#[derive(Debug, Clone)]
+pub struct LibraryChecks {
+    catalogue: Option<xtql::Catalogue>,
+    types: bool,
+    schema: Option<xtql::Schema>,
+}
+
+impl Default for LibraryChecks {
+    fn default() -> Self {
+        LibraryChecks {
+            catalogue: Some(xtql::Catalogue::standard().clone()),
+            types: true,
+            schema: None,
+        }
+    }
+}
+
+impl LibraryChecks {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Checks function calls against `catalogue` instead, e.g. the standard
+    /// one extended with functions a server extension provides.
+    pub fn catalogue(mut self, catalogue: xtql::Catalogue) -> Self {
+        self.catalogue = Some(catalogue);
+        self
+    }
+
+    /// Leaves function calls unchecked.
+    pub fn skip_functions(mut self) -> Self {
+        self.catalogue = None;
+        self
+    }
+
+    pub fn skip_types(mut self) -> Self {
+        self.types = false;
+        self
+    }
+
+    /// Also reports unknown tables and columns, and uses the schema's column
+    /// types when checking types.
+    pub fn schema(mut self, schema: xtql::Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    fn check(&self, ast: &xtql::ast::Query) -> Vec<xtql::Diagnostic> {
+        let mut diagnostics = xtql::check_scope(ast);
+        if let Some(catalogue) = &self.catalogue {
+            diagnostics.extend(catalogue.check(ast));
+        }
+        if let Some(schema) = &self.schema {
+            diagnostics.extend(schema.check(ast));
+        }
+        if self.types {
+            let columns = self.schema.as_ref().map(|s| s as &dyn ColumnTypes);
+            diagnostics.extend(xtql::check_types(ast, columns));
+        }
+        diagnostics.retain(|d| d.is_error());
+        diagnostics
+    }
+}
+
+struct State {
+    queries: BTreeMap<String, Entry>,
+    /// When each file was last modified, as of the last load.
+    files: BTreeMap<PathBuf, SystemTime>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    query: XtqlQuery,
+    params: BTreeSet<String>,
+}
+
+impl QueryLibrary {
+    /// Loads every `.edn` file in `dir` with the default [`LibraryChecks`].
+    /// Fails with [`Error::Library`], listing each file that cannot be read,
+    /// parsed or checked.
+    pub fn load(client: XtdbClient, dir: impl AsRef<Path>) -> Result<Self, Error> {
+        QueryLibrary::load_with(client, dir, LibraryChecks::default())
+    }
+
+    /// As [`QueryLibrary::load`], running `checks` on each query, then and
+    /// on every reload.
+    pub fn load_with(
+        client: XtdbClient,
+        dir: impl AsRef<Path>,
+        checks: LibraryChecks,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let state = load(&client, &checks, &dir)?;
+        Ok(QueryLibrary {
+            client,
+            dir,
+            reload: false,
+            checks,
+            state: RwLock::new(state),
+        })
+    }
+
+    /// Development mode: reloads the directory whenever a file has been
+    /// added, removed or modified since it was last loaded. A reload that
+    /// fails leaves the previous queries in place and fails the call that
+    /// triggered it.
+    pub fn reload_on_change(mut self) -> Self {
+        self.reload = true;
+        self
+    }
+
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        self.refresh()?;
+        Ok(self.read().queries.keys().cloned().collect())
+    }
+
+    /// The `$params` (without `$`) query `name` expects.
+    pub fn params(&self, name: &str) -> Result<BTreeSet<String>, Error> {
+        Ok(self.entry(name)?.params)
+    }
+
+    pub fn query(&self, name: &str) -> Result<XtqlQuery, Error> {
+        Ok(self.entry(name)?.query)
+    }
+
+    /// Prepares query `name` with `params` bound; await it to collect the
+    /// rows, as with [`XtdbClient::execute_query`].
+    pub fn run<K: AsRef<str>, V: Into<XtValue>>(
+        &self,
+        name: &str,
+        params: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<QueryRequest<'_>, Error> {
+        let query = self.entry(name)?.query;
+        Ok(params.into_iter().fold(
+            self.client.execute_query(query),
+            |request, (name, value)| request.bind(name.as_ref(), value),
+        ))
+    }
+
+    fn entry(&self, name: &str) -> Result<Entry, Error> {
+        self.refresh()?;
+        self.read()
+            .queries
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownQuery(name.to_string()))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        self.state
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn refresh(&self) -> Result<(), Error> {
+        if !self.reload {
+            return Ok(());
+        }
+        let files = edn_files(&self.dir).map_err(|error| library_error(&self.dir, error))?;
+        if files == self.read().files {
+            return Ok(());
+        }
+        let state = load(&self.client, &self.checks, &self.dir)?;
+        *self
+            .state
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = state;
+        Ok(())
+    }
+}
+
+fn library_error(path: &Path, error: impl Into<Error>) -> Error {
+    Error::Library(vec![(path.to_path_buf(), error.into())])
+}
+
+/// The `.edn` files in `dir` and when they were last modified.
+fn edn_files(dir: &Path) -> Result<BTreeMap<PathBuf, SystemTime>, xtql::Error> {
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("edn") {
+            files.insert(path.clone(), std::fs::metadata(&path)?.modified()?);
+        }
+    }
+    Ok(files)
+}
+
+fn load(client: &XtdbClient, checks: &LibraryChecks, dir: &Path) -> Result<State, Error> {
+    let files = edn_files(dir).map_err(|error| library_error(dir, error))?;
+    let mut queries = BTreeMap::new();
+    // The file each query was first defined in.
+    let mut origins: BTreeMap<String, &Path> = BTreeMap::new();
+    let mut errors = vec![];
+    for path in files.keys() {
+        match load_file(client, checks, path) {
+            Ok(loaded) => {
+                for (name, span, entry) in loaded {
+                    if let Some(origin) = origins.get(&name) {
+                        let message = format!(
+                            "query `{}` is already defined in {}",
+                            name,
+                            origin.display()
+                        );
+                        let diagnostic = xtql::Diagnostic::error("duplicate-query", span, message);
+                        errors.push((path.clone(), Error::Invalid(vec![diagnostic])));
+                    } else {
+                        origins.insert(name.clone(), path);
+                        queries.insert(name, entry);
+                    }
+                }
+            }
+            Err(error) => errors.push((path.clone(), error)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(State { queries, files })
+    } else {
+        Err(Error::Library(errors))
+    }
+}
+
+fn load_file(
+    client: &XtdbClient,
+    checks: &LibraryChecks,
+    path: &Path,
+) -> Result<Vec<(String, xtql::ast::Span, Entry)>, Error> {
+    let content = std::fs::read_to_string(path).map_err(xtql::Error::from)?;
+    let forms = xtql::parse_forms(&content)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut entries = vec![];
+    for form in &forms {
+        let Statement::Query(ast) = &form.statement else {
+            return Err(invalid(
+                form.span,
+                "query libraries only hold queries; submit DML with `submit_tx`",
+            ));
+        };
+        let name = match &form.name {
+            Some(name) => name.clone(),
+            None if forms.len() == 1 => stem.to_string(),
+            None => {
+                let message = "the queries of a file holding several must each be named";
+                return Err(invalid(form.span, message));
+            }
+        };
+        let ast = if xtql::collect_fragments(ast).is_empty() {
+            ast.clone()
+        } else {
+            let none = xtql::Fragments::new();
+            let fragments = client.fragments.as_ref().unwrap_or(&none);
+            fragments.expand(ast).map_err(Error::Fragment)?
+        };
+        let diagnostics = checks.check(&ast);
+        if !diagnostics.is_empty() {
+            return Err(Error::Invalid(diagnostics));
+        }
+        let query = XtqlQuery::from_ast(ast)?;
+        let params = xtql::collect_params(&query.query);
+        entries.push((name, form.span, Entry { query, params }));
+    }
+    Ok(entries)
+}
+
+fn invalid(span: xtql::ast::Span, message: &str) -> Error {
+    Error::Invalid(vec![xtql::Diagnostic::error("library", span, message)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtql::functions::{Arity, Function, FunctionKind, Returns, ValueKind};
+
+    fn library(dir: &Path) -> Result<QueryLibrary, Error> {
+        QueryLibrary::load(XtdbClient::new("http://localhost:3000"), dir)
+    }
+
+    /// A fresh directory holding `files`, removed when dropped.
+    struct Fixtures(PathBuf);
+
+    impl Fixtures {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "xtdb-rs-library-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, content) in files {
+                std::fs::write(dir.join(file), content).unwrap();
+            }
+            Fixtures(dir)
+        }
+    }
+
+    impl Drop for Fixtures {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn names_queries_by_file_or_comment() {
+        let fixtures = Fixtures::new(
+            "names",
+            &[
+                (
+                    "orders.edn",
+                    "(-> (from :orders [{:o-custkey $customer} o-totalprice]) (where (> o-totalprice $min)))",
+                ),
+                (
+                    "customers.edn",
+                    ";; name: by-segment\n\
+                     (from :customer [{:c-mktsegment $segment} c-name])\n\
+                     ;; name: all\n\
+                     (from :customer [c-name])",
+                ),
+                ("notes.txt", "not a query"),
+            ],
+        );
+        let library = library(&fixtures.0).unwrap();
+        assert_eq!(library.names().unwrap(), ["all", "by-segment", "orders"]);
+        assert_eq!(
+            library.params("orders").unwrap(),
+            BTreeSet::from(["customer".to_string(), "min".to_string()])
+        );
+        assert_eq!(
+            library.params("by-segment").unwrap(),
+            BTreeSet::from(["segment".to_string()])
+        );
+        assert!(library.params("all").unwrap().is_empty());
+        assert!(matches!(
+            library.query("missing"),
+            Err(Error::UnknownQuery(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn duplicate_names_are_invalid() {
+        let fixtures = Fixtures::new(
+            "duplicates",
+            &[
+                ("a.edn", ";; name: q\n(from :t [x])"),
+                ("b.edn", ";; name: q\n(from :u [y])"),
+            ],
+        );
+        let Err(Error::Library(errors)) = library(&fixtures.0) else {
+            panic!("expected a library error");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, fixtures.0.join("b.edn"));
+        let Error::Invalid(diagnostics) = &errors[0].1 else {
+            panic!("expected an invalid query, found {:?}", errors[0].1);
+        };
+        assert_eq!(diagnostics[0].code, "duplicate-query");
+        assert!(diagnostics[0].message.contains("a.edn"));
+    }
+
+    #[test]
+    fn checks_are_configurable() {
+        let fixtures = Fixtures::new(
+            "checks",
+            &[(
+                "q.edn",
+                "(-> (from :orders [o-comment]) (where (spam? o-comment)))",
+            )],
+        );
+        let Err(Error::Library(errors)) = library(&fixtures.0) else {
+            panic!("expected a library error");
+        };
+        let Error::Invalid(diagnostics) = &errors[0].1 else {
+            panic!("expected an invalid query, found {:?}", errors[0].1);
+        };
+        assert_eq!(diagnostics[0].code, "unknown-function");
+
+        let client = XtdbClient::new("http://localhost:3000");
+        let mut catalogue = xtql::Catalogue::standard().clone();
+        catalogue.insert(Function::new(
+            "spam?",
+            FunctionKind::Scalar,
+            Arity::Exact(1),
+            &[ValueKind::String],
+            Returns::Kind(ValueKind::Boolean),
+        ));
+        let checks = LibraryChecks::new().catalogue(catalogue);
+        assert!(QueryLibrary::load_with(client.clone(), &fixtures.0, checks).is_ok());
+        let checks = LibraryChecks::new().skip_functions();
+        assert!(QueryLibrary::load_with(client.clone(), &fixtures.0, checks).is_ok());
+
+        let mut schema = xtql::Schema::new();
+        schema.add_column("customer", "c-name", xtql::Type::String);
+        let checks = LibraryChecks::new().skip_functions().schema(schema);
+        let Err(Error::Library(errors)) = QueryLibrary::load_with(client, &fixtures.0, checks)
+        else {
+            panic!("expected a library error");
+        };
+        let Error::Invalid(diagnostics) = &errors[0].1 else {
+            panic!("expected an invalid query, found {:?}", errors[0].1);
+        };
+        assert_eq!(diagnostics[0].code, "unknown-table");
+    }
+}
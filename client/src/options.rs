@@ -0,0 +1,236 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use xtql::XtValue;
+
+/// Identifies a transaction, as returned by [`XtdbClient::submit_tx`](crate::XtdbClient::submit_tx).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxKey {
+    pub tx_id: u64,
+    /// The transaction's system time, as an `xt:instant`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_time: Option<XtValue>,
+}
+
+/// The database snapshot a query runs against.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Basis {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at_tx: Option<TxKey>,
+    /// Overrides the query's notion of "now", as an `xt:instant`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_time: Option<XtValue>,
+}
+
+/// How XTDB renders column names in result rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum KeyFn {
+    CamelCaseString,
+    SnakeCaseString,
+    KebabCaseString,
+}
+
+/// Options sent with a query as `queryOpts`. Unset fields are omitted so
+/// XTDB applies its own defaults; `extra` carries anything not modelled here.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryOptions {
+    /// Values for the query's `$params`, keyed by name without the `$`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub args: BTreeMap<String, XtValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_tx: Option<TxKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub basis: Option<Basis>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_tz: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_fn: Option<KeyFn>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<bool>,
+    /// How long the node waits for `after_tx` to be indexed.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_duration",
+        deserialize_with = "deserialize_duration",
+        default
+    )]
+    pub tx_timeout: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_all_valid_time: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn arg(mut self, name: &str, value: impl Into<XtValue>) -> Self {
+        self.args.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn after_tx(mut self, tx: TxKey) -> Self {
+        self.after_tx = Some(tx);
+        self
+    }
+
+    pub fn at_tx(mut self, tx: TxKey) -> Self {
+        self.basis.get_or_insert_with(Basis::default).at_tx = Some(tx);
+        self
+    }
+
+    pub fn default_tz(mut self, tz: &str) -> Self {
+        self.default_tz = Some(tz.to_string());
+        self
+    }
+
+    pub fn key_fn(mut self, key_fn: KeyFn) -> Self {
+        self.key_fn = Some(key_fn);
+        self
+    }
+
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.explain = Some(explain);
+        self
+    }
+
+    pub fn tx_timeout(mut self, timeout: Duration) -> Self {
+        self.tx_timeout = Some(timeout);
+        self
+    }
+
+    pub fn default_all_valid_time(mut self, all_valid_time: bool) -> Self {
+        self.default_all_valid_time = Some(all_valid_time);
+        self
+    }
+
+    /// Sets an option this struct does not model, sent verbatim.
+    pub fn raw(mut self, key: &str, value: Value) -> Self {
+        self.extra.insert(key.to_string(), value);
+        self
+    }
+
+    /// These options with `defaults` filling in what they leave unset.
+    /// `args` are merged name by name, with these taking precedence.
+    pub(crate) fn or_defaults(self, defaults: &QueryOptions) -> QueryOptions {
+        let mut args = defaults.args.clone();
+        args.extend(self.args);
+        let mut extra = defaults.extra.clone();
+        extra.extend(self.extra);
+        QueryOptions {
+            args,
+            after_tx: self.after_tx.or_else(|| defaults.after_tx.clone()),
+            basis: self.basis.or_else(|| defaults.basis.clone()),
+            default_tz: self.default_tz.or_else(|| defaults.default_tz.clone()),
+            key_fn: self.key_fn.or(defaults.key_fn),
+            explain: self.explain.or(defaults.explain),
+            tx_timeout: self.tx_timeout.or(defaults.tx_timeout),
+            default_all_valid_time: self
+                .default_all_valid_time
+                .or(defaults.default_all_valid_time),
+            extra,
+        }
+    }
+
+    pub fn to_json(&self) -> Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map,
+            _ => unreachable!("QueryOptions always serialises to an object"),
+        }
+    }
+}
+
+impl TryFrom<Value> for QueryOptions {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+/// Renders a duration as ISO-8601 (`PT1.5S`), the form XTDB expects.
+pub(crate) fn iso_duration(duration: Duration) -> String {
+    format!("PT{:.3}S", duration.as_secs_f64())
+}
+
+fn serialize_duration<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_str(&iso_duration(*duration)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let Some(s) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    s.strip_prefix("PT")
+        .and_then(|s| s.strip_suffix('S'))
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .map(Some)
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "unsupported txTimeout {}, expected PT<seconds>S",
+                s
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tx_key_from_submit_response() {
+        let response = json!({
+            "txId": 3,
+            "systemTime": {"@type": "xt:instant", "@value": "2024-01-02T03:04:05Z"}
+        });
+        let tx_key: TxKey = serde_json::from_value(response).unwrap();
+        assert_eq!(tx_key.tx_id, 3);
+        assert_eq!(
+            tx_key.system_time,
+            Some(XtValue::Instant("2024-01-02T03:04:05Z".to_string()))
+        );
+    }
+
+    #[test]
+    fn or_defaults_merges_args_by_name() {
+        let defaults = QueryOptions::new()
+            .arg("tenant", "acme")
+            .arg("limit", 10)
+            .default_tz("UTC")
+            .key_fn(KeyFn::SnakeCaseString)
+            .raw("custom", json!(1));
+        let options = QueryOptions::new()
+            .arg("limit", 20)
+            .arg("region", "ASIA")
+            .key_fn(KeyFn::CamelCaseString)
+            .raw("other", json!(2))
+            .or_defaults(&defaults);
+        assert_eq!(
+            Value::Object(options.to_json()),
+            json!({
+                "args": {"tenant": "acme", "limit": 20, "region": "ASIA"},
+                "defaultTz": "UTC",
+                "keyFn": "CAMEL_CASE_STRING",
+                "custom": 1,
+                "other": 2
+            })
+        );
+    }
+}
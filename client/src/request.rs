@@ -0,0 +1,393 @@
+use crate::{decode, stream, Error, QueryOptions, RowStream, XtdbClient, XtqlQuery};
+use futures_util::stream::{Stream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use xtql::XtValue;
+
+/// A query about to be sent, returned by [`XtdbClient::execute_query`].
+///
+/// Awaiting it collects all rows; [`stream`](Self::stream) yields them as they
+/// arrive. A timeout covers everything from sending the request (including
+/// retries) to reading the last row. Dropping the future or stream, hitting the
+/// timeout or cancelling the token all abort the underlying HTTP request.
+pub struct QueryRequest<'a> {
+    client: &'a XtdbClient,
+    query: XtqlQuery,
+    timeout: Option<Duration>,
+    cancel: Option<CancellationToken>,
+    checked: bool,
+}
+
+impl<'a> QueryRequest<'a> {
+    pub(crate) fn new(client: &'a XtdbClient, query: XtqlQuery) -> Self {
+        QueryRequest {
+            client,
+            query,
+            timeout: None,
+            cancel: None,
+            checked: true,
+        }
+    }
+
+    /// Skips the client's policy, for queries the client issues itself.
+    pub(crate) fn unchecked(mut self) -> Self {
+        self.checked = false;
+        self
+    }
+
+    /// Supplies the value of `$name`. Before anything is sent, the bound
+    /// parameters (including any already in the query's options or the
+    /// client's default options) are checked against those the query uses,
+    /// and [`Error::Params`] lists the missing ones and the unused ones bound
+    /// on this query. Unused default args are not an error.
+    pub fn bind(mut self, name: &str, value: impl Into<XtValue>) -> Self {
+        let name = name.trim_start_matches('$').to_string();
+        self.query.options.args.insert(name, value.into());
+        self
+    }
+
+    /// Fails the query with [`Error::Timeout`] if it has not completed within
+    /// `timeout`. The deadline is enforced by the client: XTDB has no query
+    /// timeout. When the query waits for `afterTx` and does not set
+    /// `txTimeout` itself, the remaining time is also sent as `txTimeout`,
+    /// so the node gives up waiting for the transaction when the client does.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails the query with [`Error::Cancelled`] once `token` is cancelled.
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub async fn stream(self) -> Result<RowStream, Error> {
+        let query = expand_fragments(self.query, self.client.fragments.as_ref())?;
+        let query = match &self.client.row_filters {
+            Some(filters) if self.checked => filter_rows(query, filters)?,
+            _ => query,
+        };
+        check_params(&query, &self.client.default_options)?;
+        match &self.client.policy {
+            Some(policy) if self.checked => check_policy(&query, policy)?,
+            _ => {}
+        }
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let guard = Guard {
+            deadline,
+            cancel: self.cancel,
+        };
+        let resp = guard.run(self.client.send_query(query, deadline)).await?;
+        Ok(guard.wrap(stream::json_lines(resp.bytes_stream())))
+    }
+
+    /// Deserialises every row into `T`.
+    pub async fn collect_as<T: DeserializeOwned>(self) -> Result<Vec<T>, Error> {
+        let rows = self.stream().await?;
+        rows.enumerate()
+            .map(|(i, row)| row.and_then(|value| decode::from_row(i, value)))
+            .try_collect()
+            .await
+    }
+
+    /// Streaming counterpart of [`collect_as`](Self::collect_as).
+    pub async fn stream_as<T: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>, Error> {
+        let rows = self.stream().await?;
+        Ok(Box::pin(rows.enumerate().map(|(i, row)| {
+            row.and_then(|value| decode::from_row(i, value))
+        })))
+    }
+}
+
+impl<'a> IntoFuture for QueryRequest<'a> {
+    type Output = Result<Vec<Value>, Error>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move { self.stream().await?.try_collect().await })
+    }
+}
+
+/// Checks the args bound on `query` and in `defaults` against the query's
+/// `$params`, as they will be merged when the query is sent.
+fn check_params(query: &XtqlQuery, defaults: &QueryOptions) -> Result<(), Error> {
+    let expected = xtql::collect_params(&query.query);
+    let own: BTreeSet<String> = query.options.args.keys().cloned().collect();
+    let bound: BTreeSet<String> = own.iter().chain(defaults.args.keys()).cloned().collect();
+    let missing: Vec<String> = expected.difference(&bound).cloned().collect();
+    let unused: Vec<String> = own.difference(&expected).cloned().collect();
+    if missing.is_empty() && unused.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Params { missing, unused })
+    }
+}
+
+fn expand_fragments(
+    query: XtqlQuery,
+    fragments: Option<&xtql::Fragments>,
+) -> Result<XtqlQuery, Error> {
+    let Some(ast) = query.ast.as_ref() else {
+        return Ok(query);
+    };
+    if xtql::collect_fragments(ast).is_empty() {
+        return Ok(query);
+    }
+    let none = xtql::Fragments::new();
+    let ast = fragments
+        .unwrap_or(&none)
+        .expand(ast)
+        .map_err(Error::Fragment)?;
+    Ok(XtqlQuery::from_ast(ast)?.with_options(query.options))
+}
+
+fn filter_rows(query: XtqlQuery, filters: &xtql::RowFilters) -> Result<XtqlQuery, Error> {
+    let ast = query.ast.as_ref().ok_or_else(unparsed)?;
+    let ast = filters.apply(ast).map_err(Error::Policy)?;
+    Ok(XtqlQuery::from_ast(ast)?.with_options(query.options))
+}
+
+fn check_policy(query: &XtqlQuery, policy: &xtql::Policy) -> Result<(), Error> {
+    let violations = match &query.ast {
+        Some(ast) => policy.check(ast),
+        None => return Err(unparsed()),
+    };
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Policy(violations))
+    }
+}
+
+/// Queries built from JSON cannot be checked or rewritten.
+fn unparsed() -> Error {
+    Error::Policy(vec![xtql::Diagnostic::error(
+        "unchecked-query",
+        Default::default(),
+        "query was not parsed from XTQL text, so it cannot be checked or rewritten",
+    )])
+}
+
+#[derive(Clone)]
+struct Guard {
+    deadline: Option<Instant>,
+    cancel: Option<CancellationToken>,
+}
+
+impl Guard {
+    /// Runs `fut` until it completes, the deadline passes or the token is
+    /// cancelled, whichever happens first. The losing future is dropped.
+    async fn run<T>(&self, fut: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let timed = async {
+            match self.deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, fut)
+                    .await
+                    .map_err(|_| Error::Timeout(None))?,
+                None => fut.await,
+            }
+        };
+        match &self.cancel {
+            Some(token) => tokio::select! {
+                biased;
+                _ = token.cancelled() => Err(Error::Cancelled),
+                result = timed => result,
+            },
+            None => timed.await,
+        }
+    }
+
+    /// Applies the guard to every row; the stream ends after the first
+    /// timeout or cancellation error.
+    fn wrap(self, rows: RowStream) -> RowStream {
+        if self.deadline.is_none() && self.cancel.is_none() {
+            return rows;
+        }
+        Box::pin(futures_util::stream::unfold(
+            Some((rows, self)),
+            |state| async move {
+                let (mut rows, guard) = state?;
+                match guard.run(async { Ok(rows.next().await) }).await {
+                    Ok(Some(row)) => Some((row, Some((rows, guard)))),
+                    Ok(None) => None,
+                    Err(err) => Some((Err(err), None)),
+                }
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+    use crate::TxKey;
+    use serde_json::json;
+
+    fn query() -> XtqlQuery {
+        XtqlQuery::parse("(from :t [x])").unwrap()
+    }
+
+    fn params(result: Result<(), Error>) -> (Vec<String>, Vec<String>) {
+        match result {
+            Err(Error::Params { missing, unused }) => (missing, unused),
+            other => panic!("expected a parameter error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_missing_and_unused_params() {
+        let query = XtqlQuery::parse("(from :t [{:x $x :y $y}])")
+            .unwrap()
+            .with_options(QueryOptions::new().arg("y", 1).arg("z", 2));
+        let (missing, unused) = params(check_params(&query, &QueryOptions::new()));
+        assert_eq!(missing, ["x"]);
+        assert_eq!(unused, ["z"]);
+    }
+
+    #[test]
+    fn default_args_count_as_bound() {
+        let query = XtqlQuery::parse("(from :t [{:tenant $tenant}])").unwrap();
+        let defaults = QueryOptions::new()
+            .arg("tenant", "acme")
+            .arg("region", "EU");
+        assert!(check_params(&query, &defaults).is_ok());
+        let (missing, unused) = params(check_params(&query, &QueryOptions::new()));
+        assert_eq!(missing, ["tenant"]);
+        assert!(unused.is_empty());
+    }
+
+    #[test]
+    fn bind_strips_the_dollar() {
+        let client = XtdbClient::new("http://localhost:3000");
+        let query = XtqlQuery::parse("(from :t [{:x $x}])").unwrap();
+        let request = client.execute_query(query).bind("$x", 1);
+        assert_eq!(
+            request.query.options.args,
+            std::collections::BTreeMap::from([("x".to_string(), XtValue::Int(1))])
+        );
+        assert!(check_params(&request.query, &QueryOptions::new()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_before_sending_when_params_are_missing() {
+        let server = TestServer::start(Response::ok(vec![])).await;
+        let client = XtdbClient::new(&server.url);
+        let query = XtqlQuery::parse("(from :t [{:x $x}])").unwrap();
+        let result = client.execute_query(query).await;
+        assert!(matches!(result, Err(Error::Params { .. })));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_tx_timeout_only_with_after_tx() {
+        let server = TestServer::start(Response::ok(vec![])).await;
+        let client = XtdbClient::new(&server.url);
+        client
+            .execute_query(query())
+            .timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        let after_tx = QueryOptions::new().after_tx(TxKey {
+            tx_id: 1,
+            system_time: None,
+        });
+        client
+            .execute_query(query().with_options(after_tx))
+            .timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0]["queryOpts"], json!({}));
+        assert_eq!(requests[1]["queryOpts"]["afterTx"], json!({"txId": 1}));
+        assert!(requests[1]["queryOpts"]["txTimeout"].is_string());
+    }
+
+    #[tokio::test]
+    async fn merges_bound_args_with_the_default_args() {
+        let server = TestServer::start(Response::ok(vec![])).await;
+        let defaults = QueryOptions::new().arg("tenant", "acme").arg("x", 0);
+        let client = XtdbClient::builder(&server.url)
+            .default_options(defaults)
+            .build()
+            .unwrap();
+        let query = XtqlQuery::parse("(from :t [{:x $x}])").unwrap();
+        client.execute_query(query).bind("x", 1).await.unwrap();
+        assert_eq!(
+            server.requests()[0]["queryOpts"]["args"],
+            json!({"tenant": "acme", "x": 1})
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_before_the_response() {
+        let response = Response {
+            delay: Duration::from_secs(5),
+            ..Response::ok(vec![])
+        };
+        let server = TestServer::start(response).await;
+        let client = XtdbClient::new(&server.url);
+        let result = client
+            .execute_query(query())
+            .timeout(Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(Error::Timeout(None))));
+    }
+
+    #[tokio::test]
+    async fn times_out_in_the_middle_of_the_stream() {
+        let chunks = vec![
+            (Duration::ZERO, "{\"x\": 1}\n"),
+            (Duration::from_secs(5), "{\"x\": 2}\n"),
+        ];
+        let server = TestServer::start(Response::ok(chunks)).await;
+        let client = XtdbClient::new(&server.url);
+        let mut rows = client
+            .execute_query(query())
+            .timeout(Duration::from_millis(200))
+            .stream()
+            .await
+            .unwrap();
+        assert_eq!(rows.next().await.unwrap().unwrap(), json!({"x": 1}));
+        assert!(matches!(rows.next().await, Some(Err(Error::Timeout(None)))));
+        assert!(rows.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn cancels_through_the_token() {
+        let chunks = vec![
+            (Duration::ZERO, "{\"x\": 1}\n"),
+            (Duration::from_secs(5), "{\"x\": 2}\n"),
+        ];
+        let server = TestServer::start(Response::ok(chunks)).await;
+        let client = XtdbClient::new(&server.url);
+        let token = CancellationToken::new();
+        let mut rows = client
+            .execute_query(query())
+            .cancel_on(token.clone())
+            .stream()
+            .await
+            .unwrap();
+        assert_eq!(rows.next().await.unwrap().unwrap(), json!({"x": 1}));
+        let cancel = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        });
+        assert!(matches!(rows.next().await, Some(Err(Error::Cancelled))));
+        assert!(rows.next().await.is_none());
+        cancel.await.unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = client.execute_query(query()).cancel_on(token).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+}
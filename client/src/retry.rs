@@ -0,0 +1,196 @@
+use crate::Error;
+use reqwest::StatusCode;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Retries requests that fail for transient reasons, with exponential backoff.
+///
+/// Queries are always considered safe to retry. Transaction submissions are
+/// only retried when marked with [`XtqlTx::retry_safe`](crate::XtqlTx::retry_safe),
+/// since a submission whose response was lost may already have been applied.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Randomises each delay between half and all of its computed value.
+    pub jitter: bool,
+    /// Response statuses worth retrying.
+    pub retry_statuses: Vec<StatusCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: true,
+            retry_statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn retry_on_status(mut self, status: StatusCode) -> Self {
+        self.retry_statuses.push(status);
+        self
+    }
+
+    /// Whether `err` is transient: connection failures, timeouts and the
+    /// configured response statuses. Decoding and client-side errors are not.
+    pub fn is_retryable(&self, err: &Error) -> bool {
+        match err {
+            Error::Transport(e) => e.is_connect() || e.is_request(),
+            Error::Timeout(Some(_)) => true,
+            Error::Server(e) => self.retry_statuses.contains(&e.status),
+            _ => false,
+        }
+    }
+
+    /// The delay before attempt `attempt + 1`, where `attempt` starts at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let secs = secs.min(self.max_backoff.as_secs_f64()).max(0.0);
+        let secs = if self.jitter {
+            secs * (0.5 + 0.5 * random_unit())
+        } else {
+            secs
+        };
+        Duration::from_secs_f64(secs)
+    }
+}
+
+/// A uniformly distributed value in `[0, 1)`, seeded from std's per-process
+/// random hasher keys so we don't need a dependency on `rand`.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+    use crate::{ServerError, XtdbClient, XtqlTx};
+    use serde_json::json;
+
+    fn server_error(status: StatusCode) -> Error {
+        ServerError::from_response(status, String::new()).into()
+    }
+
+    async fn builder_error() -> reqwest::Error {
+        reqwest::Client::new()
+            .get("not a url")
+            .send()
+            .await
+            .unwrap_err()
+    }
+
+    #[test]
+    fn delay_grows_up_to_the_maximum() {
+        let policy = RetryPolicy::new(10)
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .jitter(false);
+        let delays: Vec<u128> = [1, 2, 3, 4, 5, 100]
+            .iter()
+            .map(|attempt| policy.delay(*attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_all_of_the_delay() {
+        let policy =
+            RetryPolicy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_millis(200), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(400), "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors_only() {
+        let policy = RetryPolicy::default();
+        for status in [429, 502, 503, 504] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(policy.is_retryable(&server_error(status)), "{}", status);
+        }
+        for status in [400, 404, 409, 500] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(!policy.is_retryable(&server_error(status)), "{}", status);
+        }
+
+        // Nothing listens on port 1.
+        let connect = reqwest::Client::new()
+            .post("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(policy.is_retryable(&Error::Transport(connect)));
+        assert!(policy.is_retryable(&Error::Timeout(Some(builder_error().await))));
+
+        assert!(!policy.is_retryable(&Error::Transport(builder_error().await)));
+        assert!(!policy.is_retryable(&Error::Timeout(None)));
+        assert!(!policy.is_retryable(&Error::Cancelled));
+        assert!(!policy.is_retryable(&Error::Config("bad".to_string())));
+    }
+
+    #[tokio::test]
+    async fn never_retries_transactions_not_marked_safe() {
+        let policy =
+            RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let tx = || XtqlTx::new(vec![json!({"sql": "INSERT INTO t RECORDS {_id: 1}"})]);
+
+        let server = TestServer::start(Response::status(503)).await;
+        let client = XtdbClient::builder(&server.url)
+            .retry_policy(policy.clone())
+            .build()
+            .unwrap();
+        assert!(client.submit_tx(tx()).await.is_err());
+        assert_eq!(server.requests().len(), 1);
+
+        let server = TestServer::start(Response::status(503)).await;
+        let client = XtdbClient::builder(&server.url)
+            .retry_policy(policy)
+            .build()
+            .unwrap();
+        assert!(client.submit_tx(tx().retry_safe()).await.is_err());
+        assert_eq!(server.requests().len(), 3);
+    }
+}
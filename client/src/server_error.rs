@@ -0,0 +1,214 @@
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Broad category of an error reported by XTDB, used to decide how to react.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerErrorKind {
+    /// The query could not be parsed or planned (e.g. malformed XTQL/SQL).
+    Parse,
+    /// The request was well-formed but an argument was rejected.
+    IllegalArgument,
+    /// The operation conflicted with concurrent writes.
+    Conflict,
+    /// The node failed while handling the request.
+    Server,
+    /// Anything else, including bodies that are not XTDB errors at all.
+    Other,
+}
+
+/// An error response decoded from an XTDB node.
+#[derive(Debug, Clone)]
+pub struct ServerError {
+    pub kind: ServerErrorKind,
+    pub status: StatusCode,
+    pub message: String,
+    /// The Java class of the exception, e.g. `xtdb.IllegalArgumentException`.
+    pub class: Option<String>,
+    /// The `xtdb.error/error-key`, e.g. `xtql/malformed-table`.
+    pub error_key: Option<String>,
+    pub data: Map<String, Value>,
+    /// The raw response body, kept for errors that do not decode.
+    pub body: String,
+}
+
+impl ServerError {
+    /// Decodes an error body. XTDB renders errors as JSON-LD
+    /// (`{"@type": "xt:error", "@value": {"xtdb.error/message": ...}}`); bodies
+    /// that do not follow that shape are kept verbatim as the message.
+    pub fn from_response(status: StatusCode, body: String) -> Self {
+        let fields = match serde_json::from_str::<Value>(&body) {
+            Ok(Value::Object(mut map)) => match map.remove("@value") {
+                Some(Value::Object(inner)) => inner,
+                Some(other) => {
+                    map.insert("@value".to_string(), other);
+                    map
+                }
+                None => map,
+            },
+            _ => Map::new(),
+        };
+
+        let message = field(&fields, &["xtdb.error/message", "message"])
+            .unwrap_or_else(|| body.trim().to_string());
+        let class = field(&fields, &["xtdb.error/class", "class"]);
+        let error_key = field(&fields, &["xtdb.error/error-key", "error-key", "errorKey"]);
+        let data = ["xtdb.error/data", "data"]
+            .iter()
+            .find_map(|key| match fields.get(*key) {
+                Some(Value::Object(data)) => Some(data.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let kind = classify(status, class.as_deref(), error_key.as_deref());
+
+        ServerError {
+            kind,
+            status,
+            message,
+            class,
+            error_key,
+            data,
+            body,
+        }
+    }
+}
+
+/// Reads a string field, unwrapping tagged values such as keywords.
+fn field(fields: &Map<String, Value>, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| match fields.get(*key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(tagged) => tagged.get("@value")?.as_str().map(str::to_string),
+        _ => None,
+    })
+}
+
+fn classify(status: StatusCode, class: Option<&str>, error_key: Option<&str>) -> ServerErrorKind {
+    let class = class.unwrap_or_default();
+    let key = error_key.unwrap_or_default();
+    let namespace = key.split_once('/').map_or("", |(ns, _)| ns);
+
+    if status == StatusCode::CONFLICT || class.contains("Conflict") || key.contains("conflict") {
+        ServerErrorKind::Conflict
+    } else if matches!(namespace, "xtql" | "xtdb.sql" | "sql")
+        || ["parse", "malformed", "plan"]
+            .iter()
+            .any(|w| key.contains(w))
+    {
+        ServerErrorKind::Parse
+    } else if class.contains("IllegalArgument") || status == StatusCode::BAD_REQUEST {
+        ServerErrorKind::IllegalArgument
+    } else if status.is_server_error() || class.contains("RuntimeException") {
+        ServerErrorKind::Server
+    } else {
+        ServerErrorKind::Other
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(key) = &self.error_key {
+            write!(f, " ({})", key)?;
+        }
+        write!(f, " [HTTP {}]", self.status.as_u16())
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xtdb_error(class: &str, key: &str, message: &str) -> String {
+        serde_json::json!({
+            "@type": "xt:error",
+            "@value": {
+                "xtdb.error/message": message,
+                "xtdb.error/class": class,
+                "xtdb.error/error-key": {"@type": "xt:keyword", "@value": key},
+                "xtdb.error/data": {"table": "users"}
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn classifies_xtdb_errors() {
+        let cases = [
+            (
+                StatusCode::BAD_REQUEST,
+                xtdb_error(
+                    "xtdb.IllegalArgumentException",
+                    "xtql/malformed-table",
+                    "Malformed table",
+                ),
+                ServerErrorKind::Parse,
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                xtdb_error(
+                    "xtdb.IllegalArgumentException",
+                    "xtdb/unknown-arg",
+                    "Unknown arg",
+                ),
+                ServerErrorKind::IllegalArgument,
+            ),
+            (
+                StatusCode::CONFLICT,
+                xtdb_error("xtdb.RuntimeException", "xtdb/tx-conflict", "Conflict"),
+                ServerErrorKind::Conflict,
+            ),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                xtdb_error("xtdb.RuntimeException", "xtdb/node-failure", "Node failed"),
+                ServerErrorKind::Server,
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                "Not Found".to_string(),
+                ServerErrorKind::Other,
+            ),
+        ];
+        for (status, body, kind) in cases {
+            assert_eq!(
+                ServerError::from_response(status, body.clone()).kind,
+                kind,
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_the_error_fields() {
+        let error = ServerError::from_response(
+            StatusCode::BAD_REQUEST,
+            xtdb_error(
+                "xtdb.IllegalArgumentException",
+                "xtql/malformed-table",
+                "Malformed table",
+            ),
+        );
+        assert_eq!(error.message, "Malformed table");
+        assert_eq!(
+            error.class.as_deref(),
+            Some("xtdb.IllegalArgumentException")
+        );
+        assert_eq!(error.error_key.as_deref(), Some("xtql/malformed-table"));
+        assert_eq!(error.data["table"], "users");
+        assert_eq!(
+            error.to_string(),
+            "Malformed table (xtql/malformed-table) [HTTP 400]"
+        );
+    }
+
+    #[test]
+    fn keeps_bodies_that_are_not_xtdb_errors() {
+        let error = ServerError::from_response(StatusCode::BAD_GATEWAY, " upstream down\n".into());
+        assert_eq!(error.kind, ServerErrorKind::Server);
+        assert_eq!(error.message, "upstream down");
+        assert_eq!(error.class, None);
+    }
+}
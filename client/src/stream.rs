@@ -0,0 +1,126 @@
+use crate::Error;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde_json::Value;
+use std::pin::Pin;
+
+/// Rows decoded from an `application/jsonl` response body, one per line.
+pub type RowStream = Pin<Box<dyn Stream<Item = Result<Value, Error>> + Send>>;
+
+struct LineState<S> {
+    chunks: S,
+    buffer: Vec<u8>,
+    /// How far `buffer` has been searched for a newline.
+    scanned: usize,
+    done: bool,
+}
+
+/// Splits a stream of byte chunks into JSON lines, decoding each as it completes.
+/// Only the current partial line is held in memory; dropping the stream drops
+/// the underlying body, which aborts the HTTP transfer.
+pub(crate) fn json_lines<S, B>(chunks: S) -> RowStream
+where
+    S: Stream<Item = Result<B, reqwest::Error>> + Send + Unpin + 'static,
+    B: AsRef<[u8]>,
+{
+    let state = LineState {
+        chunks,
+        buffer: Vec::new(),
+        scanned: 0,
+        done: false,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            let unscanned = &state.buffer[state.scanned..];
+            if let Some(pos) = unscanned.iter().position(|b| *b == b'\n') {
+                let end = state.scanned + pos;
+                let line: Vec<u8> = state.buffer.drain(..=end).collect();
+                state.scanned = 0;
+                match decode_line(&line) {
+                    Some(row) => return Some((row, state)),
+                    None => continue,
+                }
+            }
+            state.scanned = state.buffer.len();
+            if state.done {
+                let line = std::mem::take(&mut state.buffer);
+                state.scanned = 0;
+                return decode_line(&line).map(|row| (row, state));
+            }
+            match state.chunks.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => {
+                    state.done = true;
+                    state.buffer.clear();
+                    state.scanned = 0;
+                    return Some((Err(e.into()), state));
+                }
+                None => state.done = true,
+            }
+        }
+    }))
+}
+
+fn decode_line(line: &[u8]) -> Option<Result<Value, Error>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    Some(serde_json::from_slice(line).map_err(Error::Decode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The rows decoded from `chunks`, with errors as their message.
+    async fn rows(chunks: Vec<Result<&'static str, reqwest::Error>>) -> Vec<Result<Value, String>> {
+        json_lines(stream::iter(chunks))
+            .map(|row| row.map_err(|e| e.to_string()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn joins_rows_split_across_chunks() {
+        let chunks = vec![Ok("{\"a\""), Ok(": 1"), Ok("}\n{\"a\": "), Ok("2}\n")];
+        assert_eq!(
+            rows(chunks).await,
+            [Ok(json!({"a": 1})), Ok(json!({"a": 2}))]
+        );
+    }
+
+    #[tokio::test]
+    async fn splits_several_rows_in_one_chunk() {
+        let chunks = vec![Ok("1\n2\n3\n")];
+        assert_eq!(
+            rows(chunks).await,
+            [Ok(json!(1)), Ok(json!(2)), Ok(json!(3))]
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_a_final_line_without_newline() {
+        let chunks = vec![Ok("1\n"), Ok("2")];
+        assert_eq!(rows(chunks).await, [Ok(json!(1)), Ok(json!(2))]);
+    }
+
+    #[tokio::test]
+    async fn skips_blank_lines() {
+        let chunks = vec![Ok("\n1\n  \r\n"), Ok("\n2\n\n")];
+        assert_eq!(rows(chunks).await, [Ok(json!(1)), Ok(json!(2))]);
+    }
+
+    #[tokio::test]
+    async fn transport_errors_end_the_stream() {
+        let error = reqwest::Client::new()
+            .get("not a url")
+            .send()
+            .await
+            .unwrap_err();
+        let chunks = vec![Ok("1\n2"), Err(error), Ok("3\n")];
+        let rows = rows(chunks).await;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], Ok(json!(1)));
+        assert!(rows[1].is_err());
+    }
+}
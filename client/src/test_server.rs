@@ -0,0 +1,117 @@
+//! A minimal HTTP/1.1 server for tests, serving canned responses and
+//! recording the JSON bodies it receives.
+
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A canned response, sent after `delay`. The body is written chunk by
+/// chunk, after each chunk's delay, and ends when the connection is closed.
+#[derive(Clone)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub delay: Duration,
+    pub chunks: Vec<(Duration, &'static str)>,
+}
+
+impl Response {
+    pub fn ok(chunks: Vec<(Duration, &'static str)>) -> Self {
+        Response {
+            status: 200,
+            delay: Duration::ZERO,
+            chunks,
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Response {
+            status,
+            delay: Duration::ZERO,
+            chunks: vec![],
+        }
+    }
+}
+
+pub(crate) struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl TestServer {
+    /// Serves every request with `response`.
+    pub async fn start(response: Response) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let response = response.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(serve(socket, response, recorded));
+            }
+        });
+        TestServer { url, requests }
+    }
+
+    /// The bodies of the requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(
+    mut socket: TcpStream,
+    response: Response,
+    recorded: Arc<Mutex<Vec<Value>>>,
+) -> Option<()> {
+    let body = read_body(&mut socket).await?;
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    recorded.lock().unwrap().push(body);
+    tokio::time::sleep(response.delay).await;
+    let head = format!(
+        "HTTP/1.1 {} Test\r\nContent-Type: application/jsonl\r\nConnection: close\r\n\r\n",
+        response.status
+    );
+    socket.write_all(head.as_bytes()).await.ok()?;
+    socket.flush().await.ok()?;
+    for (delay, chunk) in response.chunks {
+        tokio::time::sleep(delay).await;
+        if socket.write_all(chunk.as_bytes()).await.is_err() {
+            break;
+        }
+        socket.flush().await.ok()?;
+    }
+    Some(())
+}
+
+/// Reads a request and returns its body, sized by `Content-Length`.
+async fn read_body(socket: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut buffer = vec![];
+    let mut chunk = [0; 4096];
+    let head_end = loop {
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_ascii_lowercase();
+    let length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .map_or(0, |value| value.trim().parse().unwrap_or(0));
+    while buffer.len() < head_end + length {
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    Some(buffer[head_end..head_end + length].to_vec())
+}
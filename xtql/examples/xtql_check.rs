@@ -0,0 +1,43 @@
+// This example parses an XTQL query and reports problems found by static analysis.
+// Pass `--schema tables.toml` (or `.json`) to also check tables, columns and their types.
+use std::io::Read;
+use std::{env, fs, io};
+use xtql::{check_functions, check_scope, check_types, parse_query, Schema};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let schema = match args.iter().position(|arg| arg == "--schema") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(Schema::load(path)?)
+        }
+        _ => None,
+    };
+    let content = if let Some(filepath) = args.first() {
+        fs::read_to_string(filepath)?
+    } else {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    };
+
+    let query = parse_query(&content)?;
+    let mut diagnostics = check_scope(&query);
+    diagnostics.extend(check_functions(&query));
+    match &schema {
+        Some(schema) => {
+            diagnostics.extend(schema.check(&query));
+            diagnostics.extend(check_types(&query, Some(schema)));
+        }
+        None => diagnostics.extend(check_types(&query, None)),
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
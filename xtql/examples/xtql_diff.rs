@@ -0,0 +1,32 @@
+// This example prints the structural differences between two XTQL queries.
+// Pass `--json` to print them as a JSON array instead.
+use std::{env, fs};
+use xtql::{diff_queries, parse_query};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = match args.iter().position(|arg| arg == "--json") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    let [old, new] = args.as_slice() else {
+        eprintln!("usage: xtql_diff [--json] <old.edn> <new.edn>");
+        std::process::exit(2);
+    };
+
+    let old = parse_query(&fs::read_to_string(old)?)?;
+    let new = parse_query(&fs::read_to_string(new)?)?;
+    let diff = diff_queries(&old, &new);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff.to_json())?);
+    } else {
+        print!("{}", diff);
+    }
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
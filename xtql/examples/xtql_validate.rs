@@ -0,0 +1,37 @@
+// This example checks queries written directly in XTDB's JSON encoding and
+// prints each as XTQL. Pass `--schema` to print the JSON Schema instead.
+use std::io::Read;
+use std::{env, fs, io};
+use xtql::{json_schema, query_from_json};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--schema") {
+        println!("{}", serde_json::to_string_pretty(&json_schema())?);
+        return Ok(());
+    }
+    let inputs = if args.is_empty() {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        vec![("<stdin>".to_string(), buffer)]
+    } else {
+        args.iter()
+            .map(|path| Ok((path.clone(), fs::read_to_string(path)?)))
+            .collect::<Result<_, io::Error>>()?
+    };
+
+    let mut failed = false;
+    for (name, content) in inputs {
+        match query_from_json(&serde_json::from_str(&content)?) {
+            Ok(query) => println!("{}: {}", name, query),
+            Err(err) => {
+                println!("{}: {}", name, err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
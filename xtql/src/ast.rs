@@ -0,0 +1,819 @@
+//! Typed representation of XTQL queries.
+//!
+//! The AST is canonical rather than a faithful copy of the source: bind specs,
+//! grouping specs, return specs and `:args` are flattened into one entry per
+//! column, and namespaced maps (`#:xt{:id x}`) into qualified keys. Printing a
+//! query with `Display` produces EDN that parses back to the same AST, except
+//! for the [`Span`]s, which locate nodes in the printed text instead.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Location of a node in the source text. `line` and `col` are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A source operator followed by tail operators. A query without tail
+/// operators is printed as a bare source unless it was written as `(-> ...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub source: Source,
+    pub tail: Vec<TailOp>,
+    pub pipeline: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    From(From),
+    Rel(Rel),
+    Unify(Unify),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct From {
+    pub table: Ident,
+    pub bind: Vec<BindSpec>,
+    pub for_valid_time: Option<Box<TemporalFilter>>,
+    pub for_system_time: Option<Box<TemporalFilter>>,
+    pub span: Span,
+}
+
+/// Binds `column` to `expr`: a logic variable, or any other expression to
+/// filter on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindSpec {
+    pub column: String,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemporalFilter {
+    At(Expr),
+    From(Expr),
+    To(Expr),
+    In(Expr, Expr),
+    AllTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rel {
+    pub expr: Expr,
+    pub bind: Vec<BindSpec>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unify {
+    pub clauses: Vec<UnifyClause>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnifyClause {
+    From(From),
+    Rel(Rel),
+    With(With),
+    Unnest(Unnest),
+    Where(Where),
+    Join(Join),
+    LeftJoin(Join),
+    /// A reference to a fragment, replaced by its clauses when expanded.
+    Fragment(FragmentRef),
+}
+
+/// `(fragment name args...)`: the clauses of the fragment `name`, with its
+/// parameters bound to `args`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragmentRef {
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+/// `(deffragment name [params...] clauses...)`: unify clauses that queries
+/// can include by name, with `params` standing for the arguments they pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragmentDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub clauses: Vec<UnifyClause>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub query: Box<Query>,
+    pub args: Vec<Binding>,
+    pub bind: Vec<BindSpec>,
+    pub span: Span,
+}
+
+/// A name paired with an expression: `{:col expr}` entries, `:args` specs,
+/// `with`, `return` and `aggregate` specs. Bare symbols become `x => x`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Where {
+    pub exprs: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+    pub bindings: Vec<Binding>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unnest {
+    pub binding: Binding,
+    pub span: Span,
+}
+
+/// A top-level form of a file read with [`crate::parse_forms`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form {
+    /// From the metadata's `:name`, or else a `;; name: ...` comment line
+    /// just before the form.
+    pub name: Option<String>,
+    /// The entries of a `^{...}` map preceding the form.
+    pub metadata: Vec<Binding>,
+    pub statement: Statement,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Query(Query),
+    Dml(Dml),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dml {
+    InsertInto(InsertInto),
+    Update(Update),
+    DeleteFrom(Delete),
+    EraseFrom(Delete),
+    AssertExists(Assert),
+    AssertNotExists(Assert),
+}
+
+/// `(insert-into :table query)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertInto {
+    pub table: Ident,
+    pub query: Box<Query>,
+    pub span: Span,
+}
+
+/// `(update :table {:bind [...] :set {...} :for-valid-time ...} clauses...)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: Ident,
+    pub bind: Vec<BindSpec>,
+    pub set: Vec<Binding>,
+    pub for_valid_time: Option<Box<TemporalFilter>>,
+    pub clauses: Vec<UnifyClause>,
+    pub span: Span,
+}
+
+/// `(delete-from :table [...] clauses...)` and `(erase-from ...)`; only
+/// `delete-from` takes `:for-valid-time`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: Ident,
+    pub bind: Vec<BindSpec>,
+    pub for_valid_time: Option<Box<TemporalFilter>>,
+    pub clauses: Vec<UnifyClause>,
+    pub span: Span,
+}
+
+/// `(assert-exists query)` and `(assert-not-exists query)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assert {
+    pub query: Box<Query>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TailOp {
+    Aggregate(Aggregate),
+    Limit(Limit),
+    Offset(Limit),
+    OrderBy(OrderBy),
+    Return(Return),
+    Where(Where),
+    With(With),
+    Without(Without),
+    Unnest(Unnest),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub bindings: Vec<Binding>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub value: u64,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub specs: Vec<OrderSpec>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderSpec {
+    pub val: Option<Expr>,
+    pub dir: Option<Direction>,
+    pub nulls: Option<NullOrdering>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullOrdering {
+    First,
+    Last,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Return {
+    pub bindings: Vec<Binding>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Without {
+    pub columns: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Int(i64),
+    Float(f64),
+    /// String contents as written, escapes included.
+    String(String),
+    Bool(bool),
+    Nil,
+    Vector(Vec<Expr>),
+    Map(Vec<Binding>),
+    Set(Vec<Expr>),
+    /// A `$param`, without the `$`.
+    Param(String),
+    Var(String),
+    /// A function call. Keyword functions (`(:a/b x)`) keep their `:`.
+    Call {
+        function: String,
+        args: Vec<Expr>,
+    },
+    GetField {
+        expr: Box<Expr>,
+        field: String,
+    },
+    Subquery(Subquery),
+    /// A reader-tagged literal such as `#time/date "2020-01-01"`.
+    Tagged {
+        tag: String,
+        value: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubqueryKind {
+    Q,
+    Exists,
+    Pull,
+    PullMany,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subquery {
+    pub kind: SubqueryKind,
+    pub query: Box<Query>,
+    pub args: Vec<Binding>,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind) -> Self {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    pub fn var(name: &str) -> Self {
+        Expr::new(ExprKind::Var(name.to_string()))
+    }
+
+    pub fn param(name: &str) -> Self {
+        Expr::new(ExprKind::Param(name.trim_start_matches('$').to_string()))
+    }
+
+    /// A string literal; `value` is written as is, so any `"` or `\` in it
+    /// must already be escaped.
+    pub fn string(value: &str) -> Self {
+        Expr::new(ExprKind::String(value.to_string()))
+    }
+
+    /// A reader-tagged literal, e.g. `Expr::tagged("inst", Expr::string("2024-01-01"))`.
+    pub fn tagged(tag: &str, value: Expr) -> Self {
+        Expr::new(ExprKind::Tagged {
+            tag: tag.to_string(),
+            value: Box::new(value),
+        })
+    }
+
+    pub fn call(function: &str, args: Vec<Expr>) -> Self {
+        Expr::new(ExprKind::Call {
+            function: function.to_string(),
+            args,
+        })
+    }
+
+    /// The variable name if this is a bare logic variable.
+    pub fn as_var(&self) -> Option<&str> {
+        match &self.kind {
+            ExprKind::Var(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Query {
+    /// Encodes the query as XTDB's JSON query format, as [`crate::parse_xtql`]
+    /// would for the printed query. Fails if the query still refers to
+    /// fragments.
+    pub fn to_json(&self) -> Result<serde_json::Value, crate::Error> {
+        crate::encode::query(self)
+    }
+}
+
+impl Dml {
+    /// Encodes the statement as an XTDB JSON transaction operation, to submit
+    /// with the client's `XtqlTx`.
+    pub fn to_json(&self) -> Result<serde_json::Value, crate::Error> {
+        crate::encode::dml(self)
+    }
+}
+
+impl Statement {
+    pub fn to_json(&self) -> Result<serde_json::Value, crate::Error> {
+        match self {
+            Statement::Query(query) => query.to_json(),
+            Statement::Dml(dml) => dml.to_json(),
+        }
+    }
+}
+
+impl Binding {
+    pub fn new(name: &str, expr: Expr) -> Self {
+        Binding {
+            name: name.to_string(),
+            expr,
+            span: Span::default(),
+        }
+    }
+
+    /// True for `x => x`, which prints as a bare symbol.
+    pub fn is_var(&self) -> bool {
+        self.expr.as_var() == Some(self.name.as_str())
+    }
+}
+
+impl BindSpec {
+    pub fn new(column: &str, expr: Expr) -> Self {
+        BindSpec {
+            column: column.to_string(),
+            expr,
+            span: Span::default(),
+        }
+    }
+}
+
+struct Spaced<'a, T>(&'a [T]);
+
+impl<T: Display> Display for Spaced<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            item.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints bindings as bare symbols where possible and `{:name expr}` otherwise.
+struct Specs<'a>(&'a [Binding]);
+
+impl Display for Specs<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, binding) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            if binding.is_var() {
+                f.write_str(&binding.name)?;
+            } else {
+                write!(f, "{{:{} {}}}", binding.name, binding.expr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints bindings as a single map, with keyword (`:k`) or symbol keys.
+struct MapOf<'a>(&'a [Binding], bool);
+
+impl Display for MapOf<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        for (i, binding) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            let colon = if self.1 { ":" } else { "" };
+            write!(f, "{}{} {}", colon, binding.name, binding.expr)?;
+        }
+        f.write_str("}")
+    }
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.pipeline || !self.tail.is_empty() {
+            write!(f, "(-> {}", self.source)?;
+            for op in &self.tail {
+                write!(f, " {}", op)?;
+            }
+            f.write_str(")")
+        } else {
+            self.source.fmt(f)
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::From(from) => from.fmt(f),
+            Source::Rel(rel) => rel.fmt(f),
+            Source::Unify(unify) => unify.fmt(f),
+        }
+    }
+}
+
+impl Display for BindSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.expr.as_var() == Some(self.column.as_str()) {
+            f.write_str(&self.column)
+        } else {
+            write!(f, "{{:{} {}}}", self.column, self.expr)
+        }
+    }
+}
+
+impl Display for From {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.for_valid_time.is_none() && self.for_system_time.is_none() {
+            return write!(f, "(from :{} [{}])", self.table.name, Spaced(&self.bind));
+        }
+        write!(
+            f,
+            "(from :{} {{:bind [{}]",
+            self.table.name,
+            Spaced(&self.bind)
+        )?;
+        if let Some(filter) = &self.for_valid_time {
+            write!(f, " :for-valid-time {}", filter)?;
+        }
+        if let Some(filter) = &self.for_system_time {
+            write!(f, " :for-system-time {}", filter)?;
+        }
+        f.write_str("})")
+    }
+}
+
+impl Display for TemporalFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TemporalFilter::At(t) => write!(f, "(at {})", t),
+            TemporalFilter::From(t) => write!(f, "(from {})", t),
+            TemporalFilter::To(t) => write!(f, "(to {})", t),
+            TemporalFilter::In(from, to) => write!(f, "(in {} {})", from, to),
+            TemporalFilter::AllTime => f.write_str(":all-time"),
+        }
+    }
+}
+
+impl Display for Rel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "(rel {} [{}])", self.expr, Spaced(&self.bind))
+    }
+}
+
+impl Display for Unify {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "(unify {})", Spaced(&self.clauses))
+    }
+}
+
+impl Display for UnifyClause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UnifyClause::From(from) => from.fmt(f),
+            UnifyClause::Rel(rel) => rel.fmt(f),
+            UnifyClause::With(with) => write!(f, "(with {})", MapOf(&with.bindings, false)),
+            UnifyClause::Unnest(unnest) => {
+                write!(
+                    f,
+                    "(unnest {})",
+                    MapOf(std::slice::from_ref(&unnest.binding), false)
+                )
+            }
+            UnifyClause::Where(w) => w.fmt(f),
+            UnifyClause::Join(join) => write!(f, "(join {})", join),
+            UnifyClause::LeftJoin(join) => write!(f, "(left-join {})", join),
+            UnifyClause::Fragment(fragment) => fragment.fmt(f),
+        }
+    }
+}
+
+impl Display for FragmentRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            return write!(f, "(fragment {})", self.name);
+        }
+        write!(f, "(fragment {} {})", self.name, Spaced(&self.args))
+    }
+}
+
+impl Display for FragmentDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(deffragment {} [{}] {})",
+            self.name,
+            self.params.join(" "),
+            Spaced(&self.clauses)
+        )
+    }
+}
+
+/// Prints the operands of `join`/`left-join`, without the operator.
+impl Display for Join {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            return write!(f, "{} [{}]", self.query, Spaced(&self.bind));
+        }
+        write!(f, "{} {{", self.query)?;
+        if !self.bind.is_empty() {
+            write!(f, ":bind [{}] ", Spaced(&self.bind))?;
+        }
+        write!(f, ":args [{}]}}", Specs(&self.args))
+    }
+}
+
+impl Display for Where {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.exprs.is_empty() {
+            return f.write_str("(where)");
+        }
+        write!(f, "(where {})", Spaced(&self.exprs))
+    }
+}
+
+impl Display for Form {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.metadata.is_empty() {
+            write!(f, "^{} ", MapOf(&self.metadata, true))?;
+        }
+        self.statement.fmt(f)
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Query(query) => query.fmt(f),
+            Statement::Dml(dml) => dml.fmt(f),
+        }
+    }
+}
+
+/// Prints the clauses that follow a DML statement's options, if any.
+struct Clauses<'a>(&'a [UnifyClause]);
+
+impl Display for Clauses<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for clause in self.0 {
+            write!(f, " {}", clause)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Dml {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Dml::InsertInto(insert) => {
+                write!(f, "(insert-into :{} {})", insert.table.name, insert.query)
+            }
+            Dml::Update(update) => {
+                write!(f, "(update :{} {{", update.table.name)?;
+                let mut opts = vec![];
+                if !update.bind.is_empty() {
+                    opts.push(format!(":bind [{}]", Spaced(&update.bind)));
+                }
+                if !update.set.is_empty() {
+                    opts.push(format!(":set {}", MapOf(&update.set, true)));
+                }
+                if let Some(filter) = &update.for_valid_time {
+                    opts.push(format!(":for-valid-time {}", filter));
+                }
+                write!(f, "{}}}{})", opts.join(" "), Clauses(&update.clauses))
+            }
+            Dml::DeleteFrom(delete) | Dml::EraseFrom(delete) => {
+                let op = match self {
+                    Dml::DeleteFrom(_) => "delete-from",
+                    _ => "erase-from",
+                };
+                write!(f, "({} :{} ", op, delete.table.name)?;
+                match &delete.for_valid_time {
+                    None if !delete.bind.is_empty() => write!(f, "[{}]", Spaced(&delete.bind))?,
+                    filter => {
+                        let mut opts = vec![];
+                        if !delete.bind.is_empty() {
+                            opts.push(format!(":bind [{}]", Spaced(&delete.bind)));
+                        }
+                        if let Some(filter) = filter {
+                            opts.push(format!(":for-valid-time {}", filter));
+                        }
+                        write!(f, "{{{}}}", opts.join(" "))?;
+                    }
+                }
+                write!(f, "{})", Clauses(&delete.clauses))
+            }
+            Dml::AssertExists(assert) => write!(f, "(assert-exists {})", assert.query),
+            Dml::AssertNotExists(assert) => write!(f, "(assert-not-exists {})", assert.query),
+        }
+    }
+}
+
+impl Display for TailOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (op, specs) = match self {
+            TailOp::Aggregate(agg) => ("aggregate", &agg.bindings),
+            TailOp::Return(ret) => ("return", &ret.bindings),
+            TailOp::Limit(limit) => return write!(f, "(limit {})", limit.value),
+            TailOp::Offset(offset) => return write!(f, "(offset {})", offset.value),
+            TailOp::OrderBy(order_by) => {
+                return write!(f, "(order-by {})", Spaced(&order_by.specs))
+            }
+            TailOp::Where(w) => return w.fmt(f),
+            TailOp::With(with) if with.bindings.is_empty() => return f.write_str("(with)"),
+            TailOp::With(with) => return write!(f, "(with {})", MapOf(&with.bindings, true)),
+            TailOp::Without(without) => {
+                f.write_str("(without")?;
+                for column in &without.columns {
+                    write!(f, " :{}", column.name)?;
+                }
+                return f.write_str(")");
+            }
+            TailOp::Unnest(unnest) => {
+                return write!(
+                    f,
+                    "(unnest {})",
+                    MapOf(std::slice::from_ref(&unnest.binding), true)
+                )
+            }
+        };
+        if specs.is_empty() {
+            return write!(f, "({})", op);
+        }
+        write!(f, "({} {})", op, Specs(specs))
+    }
+}
+
+impl Display for OrderSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let (Some(val), None, None) = (&self.val, self.dir, self.nulls) {
+            if let Some(var) = val.as_var() {
+                return f.write_str(var);
+            }
+        }
+        let mut parts = vec![];
+        if let Some(val) = &self.val {
+            parts.push(format!(":val {}", val));
+        }
+        if let Some(dir) = self.dir {
+            parts.push(match dir {
+                Direction::Asc => ":dir :asc".to_string(),
+                Direction::Desc => ":dir :desc".to_string(),
+            });
+        }
+        if let Some(nulls) = self.nulls {
+            parts.push(match nulls {
+                NullOrdering::First => ":nulls :first".to_string(),
+                NullOrdering::Last => ":nulls :last".to_string(),
+            });
+        }
+        write!(f, "{{{}}}", parts.join(" "))
+    }
+}
+
+fn fmt_float(value: f64, f: &mut Formatter<'_>) -> fmt::Result {
+    if value.is_nan() {
+        return f.write_str("NaN");
+    }
+    if value.is_infinite() {
+        return f.write_str(if value > 0.0 { "Infinity" } else { "-Infinity" });
+    }
+    // The grammar requires a `.` in the mantissa of every float.
+    let s = format!("{:?}", value);
+    match s.split_once('e') {
+        Some((mantissa, exponent)) if !mantissa.contains('.') => {
+            write!(f, "{}.0e{}", mantissa, exponent)
+        }
+        _ => f.write_str(&s),
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Int(i) => write!(f, "{}", i),
+            ExprKind::Float(d) => fmt_float(*d, f),
+            ExprKind::String(s) => write!(f, "\"{}\"", s),
+            ExprKind::Bool(b) => write!(f, "{}", b),
+            ExprKind::Nil => f.write_str("nil"),
+            ExprKind::Vector(items) => write!(f, "[{}]", Spaced(items)),
+            ExprKind::Map(entries) => MapOf(entries, true).fmt(f),
+            ExprKind::Set(items) => write!(f, "#{{{}}}", Spaced(items)),
+            ExprKind::Param(name) => write!(f, "${}", name),
+            ExprKind::Var(name) => f.write_str(name),
+            ExprKind::Call { function, args } if args.is_empty() => write!(f, "({})", function),
+            ExprKind::Call { function, args } => write!(f, "({} {})", function, Spaced(args)),
+            ExprKind::GetField { expr, field } => write!(f, "(. {} {})", expr, field),
+            ExprKind::Subquery(subquery) => subquery.fmt(f),
+            ExprKind::Tagged { tag, value } => write!(f, "#{} {}", tag, value),
+        }
+    }
+}
+
+impl Display for Subquery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op = match self.kind {
+            SubqueryKind::Q => "q",
+            SubqueryKind::Exists => "exists?",
+            SubqueryKind::Pull => "pull",
+            SubqueryKind::PullMany => "pull*",
+        };
+        write!(f, "({} {}", op, self.query)?;
+        if !self.args.is_empty() {
+            write!(f, " {{:args [{}]}}", Specs(&self.args))?;
+        }
+        f.write_str(")")
+    }
+}
@@ -0,0 +1,582 @@
+//! Structural diff between two queries.
+//!
+//! Changes are located by a path through the query: `pipeline[2]` is the
+//! second tail operator of a pipeline (`pipeline[0]` being its source),
+//! `unify[1]` the second clause of a `unify`, `where[0]` the first predicate
+//! of a `where`, and a column or binding name a bind spec or `with`,
+//! `return`, `aggregate` or `:args` entry:
+//!
+//! ```
+//! use xtql::{diff_queries, parse_query};
+//!
+//! let old = parse_query("(-> (from :t [x y]) (aggregate x {:n (count y)}))").unwrap();
+//! let new = parse_query("(-> (from :t [x y]) (where (> y 0)) (aggregate x {:n (sum y)}))").unwrap();
+//! assert_eq!(
+//!     diff_queries(&old, &new).to_string(),
+//!     "+ pipeline[1]: (where (> y 0))\n~ pipeline[2].aggregate.n: (count y) -> (sum y)\n"
+//! );
+//! ```
+//!
+//! Tail operators, unify clauses and predicates are aligned before being
+//! compared, so inserting one does not report everything after it as changed.
+
+use crate::ast::*;
+use serde::Serialize;
+use serde_json::Value as JSONValue;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One difference. `old` and `new` hold the EDN of the node before and after,
+/// when it exists on that side.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "query"
+        } else {
+            &self.path
+        };
+        match (self.kind, &self.old, &self.new) {
+            (ChangeKind::Changed, Some(old), Some(new)) => {
+                write!(f, "~ {}: {} -> {}", path, old, new)
+            }
+            (ChangeKind::Added, _, Some(new)) => write!(f, "+ {}: {}", path, new),
+            (_, Some(old), _) => write!(f, "- {}: {}", path, old),
+            _ => write!(f, "~ {}", path),
+        }
+    }
+}
+
+/// The changes between two queries, in the order of the new query. Printing
+/// it gives one line per change.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes as a JSON array of `{"path", "kind", "old", "new"}` objects.
+    pub fn to_json(&self) -> JSONValue {
+        serde_json::to_value(&self.changes).expect("changes serialise to JSON")
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn diff_queries(old: &Query, new: &Query) -> Diff {
+    let mut differ = Differ::default();
+    differ.query("", old, new);
+    Diff {
+        changes: differ.changes,
+    }
+}
+
+fn join(path: &str, segment: &str) -> String {
+    if path.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
+
+fn index(path: &str, segment: &str, i: usize) -> String {
+    join(path, &format!("{}[{}]", segment, i))
+}
+
+/// An element of `old` and `new` aligned with each other.
+enum Step {
+    /// Elements with the same key.
+    Same(usize, usize),
+    /// Different elements in the same position.
+    Changed(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Aligns two sequences on the longest common subsequence of their keys,
+/// pairing up what is removed and added between two matches.
+fn align<T>(old: &[T], new: &[T], key: impl Fn(&T) -> String) -> Vec<Step> {
+    let old_keys: Vec<String> = old.iter().map(&key).collect();
+    let new_keys: Vec<String> = new.iter().map(&key).collect();
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_keys[i] == new_keys[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut steps = vec![];
+    let (mut removed, mut added) = (vec![], vec![]);
+    let flush = |steps: &mut Vec<Step>, removed: &mut Vec<usize>, added: &mut Vec<usize>| {
+        let paired = removed.len().min(added.len());
+        for k in 0..paired {
+            steps.push(Step::Changed(removed[k], added[k]));
+        }
+        steps.extend(removed.drain(..).skip(paired).map(Step::Removed));
+        steps.extend(added.drain(..).skip(paired).map(Step::Added));
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_keys[i] == new_keys[j] {
+            flush(&mut steps, &mut removed, &mut added);
+            steps.push(Step::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+    flush(&mut steps, &mut removed, &mut added);
+    steps
+}
+
+#[derive(Default)]
+struct Differ {
+    changes: Vec<Change>,
+}
+
+impl Differ {
+    fn changed(&mut self, path: String, old: &impl fmt::Display, new: &impl fmt::Display) {
+        let (old, new) = (old.to_string(), new.to_string());
+        if old != new {
+            self.changes.push(Change {
+                path,
+                kind: ChangeKind::Changed,
+                old: Some(old),
+                new: Some(new),
+            });
+        }
+    }
+
+    fn added(&mut self, path: String, new: &impl fmt::Display) {
+        self.changes.push(Change {
+            path,
+            kind: ChangeKind::Added,
+            old: None,
+            new: Some(new.to_string()),
+        });
+    }
+
+    fn removed(&mut self, path: String, old: &impl fmt::Display) {
+        self.changes.push(Change {
+            path,
+            kind: ChangeKind::Removed,
+            old: Some(old.to_string()),
+            new: None,
+        });
+    }
+
+    /// Diffs two sequences aligned on `key`, locating element `i` of the new
+    /// sequence (or the old one, if removed) at `at(i)`.
+    fn seq<T: fmt::Display>(
+        &mut self,
+        old: &[T],
+        new: &[T],
+        key: impl Fn(&T) -> String,
+        at: impl Fn(usize) -> String,
+        mut diff: impl FnMut(&mut Self, String, &T, &T),
+    ) {
+        for step in align(old, new, key) {
+            match step {
+                Step::Same(i, j) | Step::Changed(i, j) => diff(self, at(j), &old[i], &new[j]),
+                Step::Removed(i) => self.removed(at(i), &old[i]),
+                Step::Added(j) => self.added(at(j), &new[j]),
+            }
+        }
+    }
+
+    fn query(&mut self, path: &str, old: &Query, new: &Query) {
+        let indexed = old.pipeline || new.pipeline || !old.tail.is_empty() || !new.tail.is_empty();
+        let source = if indexed {
+            index(path, "pipeline", 0)
+        } else {
+            path.to_string()
+        };
+        self.source(&source, &old.source, &new.source);
+        self.seq(
+            &old.tail,
+            &new.tail,
+            |op| tail_op_name(op).to_string(),
+            |i| index(path, "pipeline", i + 1),
+            |differ, path, old, new| differ.tail_op(&path, old, new),
+        );
+    }
+
+    fn source(&mut self, path: &str, old: &Source, new: &Source) {
+        match (old, new) {
+            (Source::From(old), Source::From(new)) => self.from(&join(path, "from"), old, new),
+            (Source::Rel(old), Source::Rel(new)) => self.rel(&join(path, "rel"), old, new),
+            (Source::Unify(old), Source::Unify(new)) => {
+                self.seq(
+                    &old.clauses,
+                    &new.clauses,
+                    clause_key,
+                    |i| index(path, "unify", i),
+                    |differ, path, old, new| differ.clause(&path, old, new),
+                );
+            }
+            _ => self.changed(path.to_string(), old, new),
+        }
+    }
+
+    fn clause(&mut self, path: &str, old: &UnifyClause, new: &UnifyClause) {
+        match (old, new) {
+            (UnifyClause::From(old), UnifyClause::From(new)) => {
+                self.from(&join(path, "from"), old, new)
+            }
+            (UnifyClause::Rel(old), UnifyClause::Rel(new)) => {
+                self.rel(&join(path, "rel"), old, new)
+            }
+            (UnifyClause::With(old), UnifyClause::With(new)) => {
+                self.bindings(&join(path, "with"), &old.bindings, &new.bindings)
+            }
+            (UnifyClause::Unnest(old), UnifyClause::Unnest(new)) => self.bindings(
+                &join(path, "unnest"),
+                std::slice::from_ref(&old.binding),
+                std::slice::from_ref(&new.binding),
+            ),
+            (UnifyClause::Where(old), UnifyClause::Where(new)) => {
+                self.predicates(path, &old.exprs, &new.exprs)
+            }
+            (UnifyClause::Join(old), UnifyClause::Join(new)) => {
+                self.join(&join(path, "join"), old, new)
+            }
+            (UnifyClause::LeftJoin(old), UnifyClause::LeftJoin(new)) => {
+                self.join(&join(path, "left-join"), old, new)
+            }
+            _ => self.changed(path.to_string(), old, new),
+        }
+    }
+
+    fn from(&mut self, path: &str, old: &From, new: &From) {
+        if old.table.name != new.table.name {
+            self.changed(join(path, "table"), &old.table.name, &new.table.name);
+        }
+        self.bind_specs(&join(path, "bind"), &old.bind, &new.bind);
+        self.temporal(
+            &join(path, "for-valid-time"),
+            &old.for_valid_time,
+            &new.for_valid_time,
+        );
+        self.temporal(
+            &join(path, "for-system-time"),
+            &old.for_system_time,
+            &new.for_system_time,
+        );
+    }
+
+    fn temporal(
+        &mut self,
+        path: &str,
+        old: &Option<Box<TemporalFilter>>,
+        new: &Option<Box<TemporalFilter>>,
+    ) {
+        match (old, new) {
+            (Some(old), Some(new)) => self.changed(path.to_string(), old, new),
+            (Some(old), None) => self.removed(path.to_string(), old),
+            (None, Some(new)) => self.added(path.to_string(), new),
+            (None, None) => {}
+        }
+    }
+
+    fn rel(&mut self, path: &str, old: &Rel, new: &Rel) {
+        self.expr(&join(path, "expr"), &old.expr, &new.expr);
+        self.bind_specs(&join(path, "bind"), &old.bind, &new.bind);
+    }
+
+    fn join(&mut self, path: &str, old: &Join, new: &Join) {
+        self.query(&join(path, "query"), &old.query, &new.query);
+        self.bindings(&join(path, "args"), &old.args, &new.args);
+        self.bind_specs(&join(path, "bind"), &old.bind, &new.bind);
+    }
+
+    fn tail_op(&mut self, path: &str, old: &TailOp, new: &TailOp) {
+        match (old, new) {
+            (TailOp::Aggregate(old), TailOp::Aggregate(new)) => {
+                self.bindings(&join(path, "aggregate"), &old.bindings, &new.bindings)
+            }
+            (TailOp::Return(old), TailOp::Return(new)) => {
+                self.bindings(&join(path, "return"), &old.bindings, &new.bindings)
+            }
+            (TailOp::With(old), TailOp::With(new)) => {
+                self.bindings(&join(path, "with"), &old.bindings, &new.bindings)
+            }
+            (TailOp::Unnest(old), TailOp::Unnest(new)) => self.bindings(
+                &join(path, "unnest"),
+                std::slice::from_ref(&old.binding),
+                std::slice::from_ref(&new.binding),
+            ),
+            (TailOp::Where(old), TailOp::Where(new)) => {
+                self.predicates(path, &old.exprs, &new.exprs)
+            }
+            (TailOp::OrderBy(old), TailOp::OrderBy(new)) => self.seq(
+                &old.specs,
+                &new.specs,
+                |spec| spec.val.as_ref().map(Expr::to_string).unwrap_or_default(),
+                |i| index(path, "order-by", i),
+                |differ, path, old, new| differ.changed(path, old, new),
+            ),
+            (TailOp::Without(old), TailOp::Without(new)) => {
+                let names = |without: &Without| -> Vec<String> {
+                    without.columns.iter().map(|c| c.name.clone()).collect()
+                };
+                self.seq(
+                    &names(old),
+                    &names(new),
+                    String::clone,
+                    |i| index(path, "without", i),
+                    |differ, path, old, new| differ.changed(path, old, new),
+                )
+            }
+            (TailOp::Limit(old), TailOp::Limit(new)) => {
+                self.changed(join(path, "limit"), &old.value, &new.value)
+            }
+            (TailOp::Offset(old), TailOp::Offset(new)) => {
+                self.changed(join(path, "offset"), &old.value, &new.value)
+            }
+            _ => self.changed(path.to_string(), old, new),
+        }
+    }
+
+    /// Predicates are aligned on their text, so a changed predicate is one
+    /// removed and another added in its place.
+    fn predicates(&mut self, path: &str, old: &[Expr], new: &[Expr]) {
+        self.seq(
+            old,
+            new,
+            Expr::to_string,
+            |i| index(path, "where", i),
+            |differ, path, old, new| differ.expr(&path, old, new),
+        );
+    }
+
+    fn bind_specs(&mut self, path: &str, old: &[BindSpec], new: &[BindSpec]) {
+        for spec in old {
+            if !new.iter().any(|s| s.column == spec.column) {
+                self.removed(join(path, &spec.column), &spec.expr);
+            }
+        }
+        for spec in new {
+            match old.iter().find(|s| s.column == spec.column) {
+                Some(old) => self.expr(&join(path, &spec.column), &old.expr, &spec.expr),
+                None => self.added(join(path, &spec.column), &spec.expr),
+            }
+        }
+    }
+
+    fn bindings(&mut self, path: &str, old: &[Binding], new: &[Binding]) {
+        for binding in old {
+            if !new.iter().any(|b| b.name == binding.name) {
+                self.removed(join(path, &binding.name), &binding.expr);
+            }
+        }
+        for binding in new {
+            match old.iter().find(|b| b.name == binding.name) {
+                Some(old) => self.expr(&join(path, &binding.name), &old.expr, &binding.expr),
+                None => self.added(join(path, &binding.name), &binding.expr),
+            }
+        }
+    }
+
+    /// Descends into calls of the same function and subqueries of the same
+    /// kind; any other difference is reported as a whole.
+    fn expr(&mut self, path: &str, old: &Expr, new: &Expr) {
+        match (&old.kind, &new.kind) {
+            (
+                ExprKind::Call {
+                    function: old_function,
+                    args: old_args,
+                },
+                ExprKind::Call {
+                    function: new_function,
+                    args: new_args,
+                },
+            ) if old_function == new_function && old_args.len() == new_args.len() => {
+                for (i, (old, new)) in old_args.iter().zip(new_args).enumerate() {
+                    self.expr(&index(path, "args", i), old, new);
+                }
+            }
+            (ExprKind::Subquery(old), ExprKind::Subquery(new)) if old.kind == new.kind => {
+                self.query(&join(path, subquery_name(old.kind)), &old.query, &new.query);
+                self.bindings(&join(path, "args"), &old.args, &new.args);
+            }
+            _ => self.changed(path.to_string(), old, new),
+        }
+    }
+}
+
+fn tail_op_name(op: &TailOp) -> &'static str {
+    match op {
+        TailOp::Aggregate(_) => "aggregate",
+        TailOp::Limit(_) => "limit",
+        TailOp::Offset(_) => "offset",
+        TailOp::OrderBy(_) => "order-by",
+        TailOp::Return(_) => "return",
+        TailOp::Where(_) => "where",
+        TailOp::With(_) => "with",
+        TailOp::Without(_) => "without",
+        TailOp::Unnest(_) => "unnest",
+    }
+}
+
+/// Clauses on the same table, or of the same kind otherwise, are compared.
+fn clause_key(clause: &UnifyClause) -> String {
+    match clause {
+        UnifyClause::From(from) => format!("from :{}", from.table.name),
+        UnifyClause::Rel(_) => "rel".to_string(),
+        UnifyClause::With(_) => "with".to_string(),
+        UnifyClause::Unnest(_) => "unnest".to_string(),
+        UnifyClause::Where(_) => "where".to_string(),
+        UnifyClause::Join(_) => "join".to_string(),
+        UnifyClause::LeftJoin(_) => "left-join".to_string(),
+        UnifyClause::Fragment(fragment) => format!("fragment {}", fragment.name),
+    }
+}
+
+fn subquery_name(kind: SubqueryKind) -> &'static str {
+    match kind {
+        SubqueryKind::Q => "q",
+        SubqueryKind::Exists => "exists?",
+        SubqueryKind::Pull => "pull",
+        SubqueryKind::PullMany => "pull*",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    fn diff(old: &str, new: &str) -> String {
+        diff_queries(&parse_query(old).unwrap(), &parse_query(new).unwrap()).to_string()
+    }
+
+    #[test]
+    fn aligns_unify_clauses() {
+        assert_eq!(
+            diff(
+                "(unify (from :a [x]) (from :b [x]) (where (> x 1)))",
+                "(unify (from :z [x]) (from :a [x]) (from :b [x y]) (where (> x 1)))"
+            ),
+            "+ unify[0]: (from :z [x])\n+ unify[2].from.bind.y: y\n"
+        );
+    }
+
+    #[test]
+    fn locates_removals_by_their_old_index() {
+        assert_eq!(
+            diff(
+                "(unify (from :a [x]) (from :b [x]) (from :c [x]))",
+                "(unify (from :a [x]) (from :c [x]))"
+            ),
+            "- unify[1]: (from :b [x])\n"
+        );
+        assert_eq!(
+            diff(
+                "(-> (from :t [x]) (where (> x 1)) (limit 5) (offset 1))",
+                "(-> (from :t [x]) (limit 5))"
+            ),
+            "- pipeline[1]: (where (> x 1))\n- pipeline[3]: (offset 1)\n"
+        );
+    }
+
+    #[test]
+    fn diffs_bind_specs_by_column() {
+        assert_eq!(
+            diff("(from :t [x {:y 1} {:z a}])", "(from :t [x {:y 2} {:w b}])"),
+            "- from.bind.z: a\n~ from.bind.y: 1 -> 2\n+ from.bind.w: b\n"
+        );
+        assert_eq!(
+            diff(
+                "(unify (from :t [x]) (join (from :u [x y]) [x]))",
+                "(unify (from :t [x]) (join (from :u [x z]) [x z]))"
+            ),
+            "- unify[1].join.query.from.bind.y: y\n\
+             + unify[1].join.query.from.bind.z: z\n\
+             + unify[1].join.bind.z: z\n"
+        );
+    }
+
+    #[test]
+    fn descends_into_calls_and_subqueries() {
+        assert_eq!(
+            diff(
+                "(-> (from :t [x]) (where (and (> x 1) (< x 5))))",
+                "(-> (from :t [x]) (where (and (> x 2) (< x 5))))"
+            ),
+            "~ pipeline[1].where[0].args[0].args[1]: 1 -> 2\n"
+        );
+        assert_eq!(
+            diff(
+                "(-> (from :t [x]) (where (exists? (from :u [{:x $x} y]) {:args [x]})))",
+                "(-> (from :t [x]) (where (exists? (from :u [{:x $x} z]) {:args [{:x (+ x 1)}]})))"
+            ),
+            "- pipeline[1].where[0].exists?.from.bind.y: y\n\
+             + pipeline[1].where[0].exists?.from.bind.z: z\n\
+             ~ pipeline[1].where[0].args.x: x -> (+ x 1)\n"
+        );
+        // Subqueries of different kinds, and different sources, are replaced whole.
+        assert_eq!(
+            diff(
+                "(-> (from :t [x]) (where (exists? (from :u [y]))))",
+                "(-> (from :t [x]) (where (q (from :u [y]))))"
+            ),
+            "~ pipeline[1].where[0]: (exists? (from :u [y])) -> (q (from :u [y]))\n"
+        );
+        assert_eq!(
+            diff("(from :t [x])", "(rel [{:x 1}] [x])"),
+            "~ query: (from :t [x]) -> (rel [{:x 1}] [x])\n"
+        );
+    }
+
+    #[test]
+    fn encodes_changes_as_json() {
+        let old = parse_query("(from :t [x {:y 1} {:z a}])").unwrap();
+        let new = parse_query("(from :t [x {:y 2} {:w b}])").unwrap();
+        assert_eq!(
+            diff_queries(&old, &new).to_json(),
+            serde_json::json!([
+                {"path": "from.bind.z", "kind": "removed", "old": "a"},
+                {"path": "from.bind.y", "kind": "changed", "old": "1", "new": "2"},
+                {"path": "from.bind.w", "kind": "added", "new": "b"}
+            ])
+        );
+        assert_eq!(diff_queries(&old, &old).to_json(), serde_json::json!([]));
+        assert!(diff_queries(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+//! Encodes the typed AST as XTDB's JSON, the inverse of [`crate::json`].
+//!
+//! This is the only encoder: [`crate::parse_xtql`] parses to the AST and
+//! encodes it here.
+
+use crate::ast::*;
+use crate::value::{self, XtValue};
+use crate::Error;
+use serde_json::{json, Map, Value as JSONValue};
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub(crate) fn query(query: &Query) -> Result<JSONValue> {
+    let source = match &query.source {
+        Source::From(from) => self::from(from)?,
+        Source::Rel(rel) => self::rel(rel)?,
+        Source::Unify(unify) => json!({ "unify": clauses(&unify.clauses)? }),
+    };
+    if !query.pipeline && query.tail.is_empty() {
+        return Ok(source);
+    }
+    let mut ops = vec![source];
+    for op in &query.tail {
+        ops.push(tail_op(op)?);
+    }
+    Ok(JSONValue::Array(ops))
+}
+
+pub(crate) fn dml(dml: &Dml) -> Result<JSONValue> {
+    Ok(match dml {
+        Dml::InsertInto(insert) => {
+            json!({ "insertInto": insert.table.name, "query": query(&insert.query)? })
+        }
+        Dml::Update(update) => {
+            let mut map = Map::new();
+            map.insert("update".to_string(), json!(update.table.name));
+            if !update.bind.is_empty() {
+                map.insert("bind".to_string(), bind_specs(&update.bind)?);
+            }
+            if !update.set.is_empty() {
+                map.insert("set".to_string(), object(&update.set)?);
+            }
+            if let Some(filter) = &update.for_valid_time {
+                map.insert("forValidTime".to_string(), temporal_filter(filter)?);
+            }
+            unify_clauses(&mut map, &update.clauses)?;
+            JSONValue::Object(map)
+        }
+        Dml::DeleteFrom(delete) | Dml::EraseFrom(delete) => {
+            let op = match dml {
+                Dml::DeleteFrom(_) => "deleteFrom",
+                _ => "eraseFrom",
+            };
+            let mut map = Map::new();
+            map.insert(op.to_string(), json!(delete.table.name));
+            if !delete.bind.is_empty() {
+                map.insert("bind".to_string(), bind_specs(&delete.bind)?);
+            }
+            if let Some(filter) = &delete.for_valid_time {
+                map.insert("forValidTime".to_string(), temporal_filter(filter)?);
+            }
+            unify_clauses(&mut map, &delete.clauses)?;
+            JSONValue::Object(map)
+        }
+        Dml::AssertExists(assert) => json!({ "assertExists": query(&assert.query)? }),
+        Dml::AssertNotExists(assert) => json!({ "assertNotExists": query(&assert.query)? }),
+    })
+}
+
+/// Adds the clauses following a DML statement's options as `unify`.
+fn unify_clauses(map: &mut Map<String, JSONValue>, clauses: &[UnifyClause]) -> Result<()> {
+    if !clauses.is_empty() {
+        map.insert("unify".to_string(), self::clauses(clauses)?);
+    }
+    Ok(())
+}
+
+fn from(from: &From) -> Result<JSONValue> {
+    let mut map = Map::new();
+    map.insert("from".to_string(), json!(from.table.name));
+    map.insert("bind".to_string(), bind_specs(&from.bind)?);
+    if let Some(filter) = &from.for_valid_time {
+        map.insert("forValidTime".to_string(), temporal_filter(filter)?);
+    }
+    if let Some(filter) = &from.for_system_time {
+        map.insert("forSystemTime".to_string(), temporal_filter(filter)?);
+    }
+    Ok(JSONValue::Object(map))
+}
+
+fn temporal_filter(filter: &TemporalFilter) -> Result<JSONValue> {
+    Ok(match filter {
+        TemporalFilter::At(t) => json!({ "at": expr(t)? }),
+        TemporalFilter::From(t) => json!({ "from": expr(t)? }),
+        TemporalFilter::To(t) => json!({ "to": expr(t)? }),
+        TemporalFilter::In(from, to) => json!({ "in": [expr(from)?, expr(to)?] }),
+        TemporalFilter::AllTime => json!("allTime"),
+    })
+}
+
+fn rel(rel: &Rel) -> Result<JSONValue> {
+    Ok(json!({ "rel": expr(&rel.expr)?, "bind": bind_specs(&rel.bind)? }))
+}
+
+/// One object per column: `[{"col": expr}, ...]`.
+fn bind_specs(specs: &[BindSpec]) -> Result<JSONValue> {
+    let specs = specs
+        .iter()
+        .map(|spec| Ok(json!({ &spec.column: expr(&spec.expr)? })))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(JSONValue::Array(specs))
+}
+
+/// One object per binding: `[{"name": expr}, ...]`.
+fn bindings(bindings: &[Binding]) -> Result<JSONValue> {
+    let bindings = bindings
+        .iter()
+        .map(|binding| Ok(json!({ &binding.name: expr(&binding.expr)? })))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(JSONValue::Array(bindings))
+}
+
+/// All bindings in a single object: `{"a": expr, "b": expr}`.
+fn object(bindings: &[Binding]) -> Result<JSONValue> {
+    let mut map = Map::new();
+    for binding in bindings {
+        map.insert(binding.name.clone(), expr(&binding.expr)?);
+    }
+    Ok(JSONValue::Object(map))
+}
+
+fn clauses(clauses: &[UnifyClause]) -> Result<JSONValue> {
+    let clauses = clauses.iter().map(clause).collect::<Result<Vec<_>>>()?;
+    Ok(JSONValue::Array(clauses))
+}
+
+fn clause(clause: &UnifyClause) -> Result<JSONValue> {
+    Ok(match clause {
+        UnifyClause::From(from) => self::from(from)?,
+        UnifyClause::Rel(rel) => self::rel(rel)?,
+        UnifyClause::With(with) => json!({ "with": [object(&with.bindings)?] }),
+        UnifyClause::Unnest(unnest) => self::unnest(unnest)?,
+        UnifyClause::Where(w) => json!({ "where": exprs(&w.exprs)? }),
+        UnifyClause::Join(j) => join("join", j)?,
+        UnifyClause::LeftJoin(j) => join("leftJoin", j)?,
+        UnifyClause::Fragment(fragment) => {
+            return Err(Error::Encode(format!(
+                "fragment `{}` must be expanded before encoding",
+                fragment.name
+            )))
+        }
+    })
+}
+
+fn join(op: &str, join: &Join) -> Result<JSONValue> {
+    let mut map = Map::new();
+    map.insert(op.to_string(), query(&join.query)?);
+    if !join.bind.is_empty() || join.args.is_empty() {
+        map.insert("bind".to_string(), bind_specs(&join.bind)?);
+    }
+    if !join.args.is_empty() {
+        map.insert("args".to_string(), args(&join.args)?);
+    }
+    Ok(JSONValue::Object(map))
+}
+
+/// Arguments passed under their own name are written as that name, the
+/// others as `{"name": expr}`.
+fn args(args: &[Binding]) -> Result<JSONValue> {
+    let args = args
+        .iter()
+        .map(|arg| {
+            if arg.is_var() {
+                Ok(json!(arg.name))
+            } else {
+                Ok(json!({ &arg.name: expr(&arg.expr)? }))
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(JSONValue::Array(args))
+}
+
+fn unnest(unnest: &Unnest) -> Result<JSONValue> {
+    Ok(json!({ "unnest": object(std::slice::from_ref(&unnest.binding))? }))
+}
+
+fn tail_op(op: &TailOp) -> Result<JSONValue> {
+    Ok(match op {
+        TailOp::Aggregate(aggregate) => {
+            let specs = aggregate
+                .bindings
+                .iter()
+                .map(|binding| {
+                    if binding.is_var() {
+                        Ok(json!({ "xt:lvar": binding.name }))
+                    } else {
+                        Ok(json!({ &binding.name: expr(&binding.expr)? }))
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?;
+            json!({ "aggregate": specs })
+        }
+        TailOp::Limit(limit) => json!({ "limit": limit.value }),
+        TailOp::Offset(offset) => json!({ "offset": offset.value }),
+        TailOp::OrderBy(order_by) => {
+            let specs = order_by
+                .specs
+                .iter()
+                .map(order_spec)
+                .collect::<Result<Vec<_>>>()?;
+            json!({ "orderBy": specs })
+        }
+        TailOp::Return(ret) => json!({ "return": bindings(&ret.bindings)? }),
+        TailOp::Where(w) => json!({ "where": exprs(&w.exprs)? }),
+        TailOp::With(with) if with.bindings.is_empty() => json!({ "with": [] }),
+        TailOp::With(with) => json!({ "with": [object(&with.bindings)?] }),
+        TailOp::Without(without) => {
+            let columns: Vec<&str> = without.columns.iter().map(|c| c.name.as_str()).collect();
+            json!({ "without": columns })
+        }
+        TailOp::Unnest(unnest) => self::unnest(unnest)?,
+    })
+}
+
+/// A bare logic variable sorts ascending and is written as its name.
+fn order_spec(spec: &OrderSpec) -> Result<JSONValue> {
+    if let (Some(val), None, None) = (&spec.val, spec.dir, spec.nulls) {
+        if let Some(var) = val.as_var() {
+            return Ok(json!(var));
+        }
+    }
+    let mut map = Map::new();
+    if let Some(val) = &spec.val {
+        map.insert("val".to_string(), expr(val)?);
+    }
+    if let Some(dir) = spec.dir {
+        let dir = match dir {
+            Direction::Asc => "asc",
+            Direction::Desc => "desc",
+        };
+        map.insert("dir".to_string(), json!(dir));
+    }
+    if let Some(nulls) = spec.nulls {
+        let nulls = match nulls {
+            NullOrdering::First => "first",
+            NullOrdering::Last => "last",
+        };
+        map.insert("nulls".to_string(), json!(nulls));
+    }
+    Ok(JSONValue::Object(map))
+}
+
+fn exprs(exprs: &[Expr]) -> Result<JSONValue> {
+    Ok(JSONValue::Array(
+        exprs.iter().map(expr).collect::<Result<Vec<_>>>()?,
+    ))
+}
+
+fn expr(expr: &Expr) -> Result<JSONValue> {
+    Ok(match &expr.kind {
+        ExprKind::Int(i) => json!(i),
+        ExprKind::Float(d) => json!(d),
+        ExprKind::String(s) => json!(s),
+        ExprKind::Bool(b) => json!(b),
+        ExprKind::Nil => JSONValue::Null,
+        ExprKind::Vector(items) => exprs(items)?,
+        ExprKind::Map(entries) => object(entries)?,
+        ExprKind::Set(items) => value::tagged(value::SET, exprs(items)?),
+        ExprKind::Param(name) => json!({ "xt:param": format!("${}", name) }),
+        ExprKind::Var(name) => json!({ "xt:lvar": name }),
+        ExprKind::Call { function, args } => json!({
+            "xt:call": function.trim_start_matches(':'),
+            "args": exprs(args)?,
+        }),
+        ExprKind::GetField { expr: e, field } => {
+            json!({ "xt:get": self::expr(e)?, "field": field })
+        }
+        ExprKind::Subquery(subquery) => {
+            let key = match subquery.kind {
+                SubqueryKind::Q => "xt:q",
+                SubqueryKind::Exists => "xt:exists",
+                SubqueryKind::Pull => "xt:pull",
+                SubqueryKind::PullMany => "xt:pullMany",
+            };
+            let mut map = Map::new();
+            map.insert(key.to_string(), query(&subquery.query)?);
+            if !subquery.args.is_empty() {
+                map.insert("args".to_string(), args(&subquery.args)?);
+            }
+            JSONValue::Object(map)
+        }
+        ExprKind::Tagged { tag, value } => {
+            let value = self::expr(value)?;
+            match value.as_str().and_then(|v| XtValue::from_edn_tag(tag, v)) {
+                Some(xt_value) => xt_value.to_json(),
+                None => value::tagged(tag, value),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_query, query_from_json};
+    use serde_json::json;
+
+    const QUERIES: &[&str] = &[
+        "(from :t [x {:y 1} {:xt/id $id}])",
+        "(-> (from :t [x]))",
+        "(from :t {:bind [x] :for-valid-time (at #inst \"2020-01-01\") :for-system-time :all-time})",
+        "(from :t {:bind [x] :for-valid-time (in #time/date \"2020-01-01\" \"2021-01-01\")})",
+        "(rel [{:a 1} {:a 2}] [a])",
+        "(rel $rows [a])",
+        "(unify (from :t [x]) (with {y (+ x 1)}) (unnest {z y}) (where (> z 1)) \
+         (join (from :u [x]) [x]) (left-join (from :v [x w]) {:bind [w] :args [x {:k 1}]}))",
+        "(-> (from :t [x y]) (aggregate x {:n (count y)}) (order-by n {:val x :dir :desc :nulls :first}) \
+         (limit 10) (offset 5) (return n {:m (* n 2)}) (with {:k 1}) (with) (without :k) (unnest {:u n}))",
+        "(-> (from :t [x]) (where (exists? (from :u [x]) {:args [x]}) (q (from :u [x])) \
+         (pull (from :u [y])) (pull* (from :u [y]) {:args [{:z x}]})))",
+        "(-> (from :t [x]) (return {:a [1 2.5 \"s\" true nil] :b #{1 2} :c #{} :d {:e x} \
+         :f (. x g) :h (:k x) :i #uuid \"00000000-0000-0000-0000-000000000000\" :j #foo/bar x}))",
+    ];
+
+    const DML: &[&str] = &[
+        "(insert-into :t (from :u [x]))",
+        "(update :t {:bind [{:xt/id $id}] :set {:a 1} :for-valid-time (from #inst \"2020-01-01\")} \
+         (from :u [x]))",
+        "(update :t {:set {:a 1}})",
+        "(delete-from :t [{:xt/id $id}] (from :u [x]))",
+        "(delete-from :t {:bind [x] :for-valid-time :all-time})",
+        "(erase-from :t [{:xt/id $id}])",
+        "(assert-exists (from :t [x]))",
+        "(assert-not-exists (from :t [x]))",
+    ];
+
+    /// `to_json` is the inverse of the JSON decoder.
+    #[test]
+    fn round_trips_through_the_decoder() {
+        for text in QUERIES {
+            let encoded = parse_query(text).unwrap().to_json().unwrap();
+            let decoded = query_from_json(&encoded).unwrap();
+            assert_eq!(decoded.to_json().unwrap(), encoded, "{}", text);
+        }
+    }
+
+    #[test]
+    fn encodes_dml() {
+        let id = json!([{"xt/id": {"xt:param": "$id"}}]);
+        let u = json!([{"from": "u", "bind": [{"x": {"xt:lvar": "x"}}]}]);
+        let t = json!({"from": "t", "bind": [{"x": {"xt:lvar": "x"}}]});
+        let expected = [
+            json!({"insertInto": "t", "query": u[0]}),
+            json!({
+                "update": "t", "bind": id, "set": {"a": 1}, "unify": u,
+                "forValidTime": {"from": {"@type": "xt:instant", "@value": "2020-01-01"}}
+            }),
+            json!({"update": "t", "set": {"a": 1}}),
+            json!({"deleteFrom": "t", "bind": id, "unify": u}),
+            json!({"deleteFrom": "t", "bind": [{"x": {"xt:lvar": "x"}}], "forValidTime": "allTime"}),
+            json!({"eraseFrom": "t", "bind": id}),
+            json!({"assertExists": t}),
+            json!({"assertNotExists": t}),
+        ];
+        for (text, expected) in DML.iter().zip(expected) {
+            let dml = crate::parse_forms(text).unwrap().remove(0).statement;
+            assert_eq!(dml.to_json().unwrap(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn fragments_are_not_encodable() {
+        let query = parse_query("(unify (from :t [x]) (fragment f x))").unwrap();
+        assert_eq!(
+            query.to_json().unwrap_err().to_string(),
+            "Encode Error: fragment `f` must be expanded before encoding"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::Rule;
+use std::fmt::{self, Debug};
+
+#[derive(Debug)]
+pub enum Error {
+    PestParse(Box<pest::error::Error<Rule>>),
+    IO(std::io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    /// An AST that has no JSON encoding, such as one with unexpanded fragments.
+    Encode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            PestParse(err) => match err.line_col {
+                pest::error::LineColLocation::Pos(pos) => {
+                    write!(
+                        f,
+                        "Parse Error: at {}:{}: {}",
+                        pos.0,
+                        pos.1,
+                        err.variant.message()
+                    )
+                }
+                pest::error::LineColLocation::Span(start, end) => {
+                    write!(
+                        f,
+                        "Parse Error: at {}:{} to {}:{}: {}",
+                        start.0,
+                        start.1,
+                        end.0,
+                        end.1,
+                        err.variant.message()
+                    )
+                }
+            },
+
+            IO(err) => write!(f, "IO Error: {}", err),
+            Json(err) => write!(f, "JSON Error: {}", err),
+            Toml(err) => write!(f, "TOML Error: {}", err),
+            Encode(message) => write!(f, "Encode Error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+        match *self {
+            PestParse(ref err) => Some(err),
+            IO(ref err) => Some(err),
+            Json(ref err) => Some(err),
+            Toml(ref err) => Some(err),
+            Encode(_) => None,
+        }
+    }
+}
+
+impl From<pest::error::Error<Rule>> for Error {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        Error::PestParse(Box::new(err))
+    }
+}
+
+impl From<Box<pest::error::Error<Rule>>> for Error {
+    fn from(err: Box<pest::error::Error<Rule>>) -> Self {
+        Error::PestParse(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::IO(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Toml(err)
+    }
+}
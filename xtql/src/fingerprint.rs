@@ -0,0 +1,146 @@
+//! Literal redaction and fingerprints, for logging queries and grouping them
+//! in metrics without recording the values they were run with.
+//!
+//! ```
+//! use xtql::{fingerprint, parse_query};
+//!
+//! let a = fingerprint(&parse_query(r#"(from :users [{:email "ann@example.com"} id])"#).unwrap());
+//! let b = fingerprint(&parse_query(r#"(from :users [{:email "bob@example.com"} id])"#).unwrap());
+//! assert_eq!(a.redacted, "(from :users [{:email $?} id])");
+//! assert_eq!(a.hash, b.hash);
+//! ```
+
+use crate::ast::*;
+use crate::visit::{fold_expr, Fold};
+use std::fmt;
+
+/// The placeholder literals are replaced with: a parameter named `?`, so
+/// that redacted queries still parse.
+const PLACEHOLDER: &str = "?";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The query as EDN, with every literal replaced by `$?`.
+    pub redacted: String,
+    /// 64-bit FNV-1a hash of `redacted`; stable across platforms and releases.
+    pub hash: u64,
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.hash)
+    }
+}
+
+/// Redacts `query` and hashes the result. Queries that differ only in their
+/// literals, or in how many literals a vector or set of them holds, have the
+/// same fingerprint.
+pub fn fingerprint(query: &Query) -> Fingerprint {
+    let redacted = redact(query).to_string();
+    Fingerprint {
+        hash: fnv1a(redacted.as_bytes()),
+        redacted,
+    }
+}
+
+/// Replaces strings, numbers and tagged values (`#inst "..."`, `#uuid "..."`)
+/// with the placeholder `$?`, and vectors and sets of them with a single one.
+/// Booleans, `nil`, parameters, and `limit` and `offset` counts are kept.
+pub fn redact(query: &Query) -> Query {
+    Redactor.fold_query(query.clone())
+}
+
+struct Redactor;
+
+impl Fold for Redactor {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let redacted = match &expr.kind {
+            ExprKind::Vector(items) | ExprKind::Set(items) => {
+                !items.is_empty() && items.iter().all(is_redacted)
+            }
+            _ => is_redacted(&expr),
+        };
+        if redacted {
+            Expr {
+                kind: ExprKind::Param(PLACEHOLDER.to_string()),
+                ..expr
+            }
+        } else {
+            fold_expr(self, expr)
+        }
+    }
+}
+
+fn is_redacted(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::String(_) | ExprKind::Tagged { .. }
+    )
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    fn redacted(text: &str) -> String {
+        redact(&parse_query(text).unwrap()).to_string()
+    }
+
+    #[test]
+    fn redacts_literals_inside_collections_and_tagged_values() {
+        assert_eq!(
+            redacted(
+                "(-> (from :t [x y]) (where (in? x [1 2 3]) (in? x #{\"a\" \"b\"}) \
+                 (= x #inst \"2020-01-01\") (= x [1 y]) (= y {:a 1.5}) (in? x []) \
+                 (= x #uuid \"00000000-0000-0000-0000-000000000000\")))"
+            ),
+            "(-> (from :t [x y]) (where (in? x $?) (in? x $?) (= x $?) (= x [$? y]) \
+             (= y {:a $?}) (in? x []) (= x $?)))"
+        );
+    }
+
+    #[test]
+    fn keeps_counts_booleans_nil_and_params() {
+        let text = "(-> (from :t [{:active true} {:deleted nil} {:id $id} x]) \
+                    (where (= x false)) (limit 10) (offset 5))";
+        assert_eq!(redacted(text), text);
+    }
+
+    #[test]
+    fn redacted_queries_parse_again() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/q-tpch");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let redacted = redacted(&text);
+            let reparsed = parse_query(&redacted).unwrap();
+            assert_eq!(reparsed.to_string(), redacted, "{}", path.display());
+            assert_eq!(
+                fingerprint(&reparsed).redacted,
+                redacted,
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn hashes_with_fnv_1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        let ann =
+            fingerprint(&parse_query(r#"(from :users [{:email "ann@example.com"} id])"#).unwrap());
+        let bob =
+            fingerprint(&parse_query(r#"(from :users [{:email "bob@example.com"} id])"#).unwrap());
+        assert_eq!(ann.hash, 0xe20e344a80bd4560);
+        assert_eq!(ann.to_string(), "e20e344a80bd4560");
+        assert_eq!(ann, bob);
+    }
+}
@@ -0,0 +1,461 @@
+//! Fragments: named, parameterised unify clauses that queries include with
+//! `(fragment name args...)`.
+//!
+//! Fragments are defined with `deffragment`, in a file or in code, and
+//! expanded on the client before the query is encoded:
+//!
+//! ```
+//! use xtql::{parse_query, Fragments};
+//!
+//! let fragments = Fragments::parse(
+//!     "(deffragment customer-nation [c nation]
+//!        (from :customer [{:xt/id c} c-nationkey])
+//!        (from :nation [{:xt/id c-nationkey} {:n-name nation}]))",
+//! )
+//! .unwrap();
+//! let query = parse_query(
+//!     "(unify (from :orders [o-custkey]) (fragment customer-nation o-custkey \"FRANCE\"))",
+//! )
+//! .unwrap();
+//! assert_eq!(
+//!     fragments.expand(&query).unwrap().to_string(),
+//!     "(unify (from :orders [o-custkey]) \
+//!      (from :customer [{:xt/id o-custkey} {:c-nationkey c-nationkey__1}]) \
+//!      (from :nation [{:xt/id c-nationkey__1} {:n-name \"FRANCE\"}]))"
+//! );
+//! ```
+//!
+//! Expansion is hygienic: the logic variables a fragment binds for itself are
+//! renamed apart, so they never unify with the including query's variables
+//! or with those of another use of the same fragment. Only the parameters
+//! connect a fragment to the query. Fragments can include other fragments,
+//! but not themselves, directly or indirectly.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::parse::fragment_defs;
+use crate::suggest;
+use crate::visit::{
+    walk_expr, walk_expr_mut, walk_unify_clause, walk_unify_clause_mut, Visitor, VisitorMut,
+};
+use crate::Error;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fragments {
+    defs: BTreeMap<String, FragmentDef>,
+}
+
+impl Fragments {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parses a sequence of `deffragment` forms.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        Fragments::new().define(content)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Fragments::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Adds the `deffragment` forms in `content`, replacing any fragment
+    /// already defined with the same name.
+    pub fn define(mut self, content: &str) -> Result<Self, Error> {
+        for def in fragment_defs(content)? {
+            self.defs.insert(def.name.clone(), def);
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FragmentDef> {
+        self.defs.get(name)
+    }
+
+    /// Returns `query` with every fragment replaced by its clauses, or the
+    /// fragments that could not be expanded: unknown ones, those given the
+    /// wrong number of arguments and those that include themselves.
+    pub fn expand(&self, query: &Query) -> Result<Query, Vec<Diagnostic>> {
+        let mut names = Names::default();
+        names.visit_query(query);
+        for def in self.defs.values() {
+            for clause in &def.clauses {
+                names.visit_unify_clause(clause);
+            }
+        }
+        let mut expander = Expander {
+            fragments: self,
+            used: names.0,
+            instances: 0,
+            stack: vec![],
+            diagnostics: vec![],
+        };
+        let mut query = query.clone();
+        expander.visit_query_mut(&mut query);
+        if expander.diagnostics.is_empty() {
+            Ok(query)
+        } else {
+            Err(expander.diagnostics)
+        }
+    }
+
+    /// Checks every definition as [`expand`](Self::expand) would check a
+    /// query, so that broken fragments are found before any query uses them.
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for def in self.defs.values() {
+            let query = Query {
+                source: Source::Unify(Unify {
+                    clauses: vec![UnifyClause::Fragment(FragmentRef {
+                        name: def.name.clone(),
+                        args: def.params.iter().map(|param| Expr::var(param)).collect(),
+                        span: def.span,
+                    })],
+                    span: def.span,
+                }),
+                tail: vec![],
+                pipeline: false,
+                span: def.span,
+            };
+            if let Err(errors) = self.expand(&query) {
+                diagnostics.extend(errors);
+            }
+        }
+        diagnostics
+    }
+}
+
+/// The names of the fragments a query uses, without expanding them.
+pub fn collect_fragments(query: &Query) -> BTreeSet<String> {
+    let mut refs = Refs::default();
+    refs.visit_query(query);
+    refs.0
+}
+
+#[derive(Default)]
+struct Refs(BTreeSet<String>);
+
+impl Visitor for Refs {
+    fn visit_unify_clause(&mut self, clause: &UnifyClause) {
+        if let UnifyClause::Fragment(fragment) = clause {
+            self.0.insert(fragment.name.clone());
+        }
+        walk_unify_clause(self, clause);
+    }
+}
+
+/// Every logic variable named anywhere, so fresh names can avoid them.
+#[derive(Default)]
+struct Names(BTreeSet<String>);
+
+impl Visitor for Names {
+    fn visit_unify_clause(&mut self, clause: &UnifyClause) {
+        match clause {
+            UnifyClause::With(with) => self
+                .0
+                .extend(with.bindings.iter().map(|binding| binding.name.clone())),
+            UnifyClause::Unnest(unnest) => {
+                self.0.insert(unnest.binding.name.clone());
+            }
+            _ => {}
+        }
+        walk_unify_clause(self, clause);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Var(name) = &expr.kind {
+            self.0.insert(name.clone());
+        }
+        walk_expr(self, expr);
+    }
+}
+
+struct Expander<'a> {
+    fragments: &'a Fragments,
+    used: BTreeSet<String>,
+    /// Fragments instantiated so far, numbering their fresh variables.
+    instances: usize,
+    /// The fragments being expanded, outermost first, with the span of the
+    /// reference in the query that led to them.
+    stack: Vec<(String, Span)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl VisitorMut for Expander<'_> {
+    fn visit_unify_mut(&mut self, unify: &mut Unify) {
+        let mut clauses = vec![];
+        for mut clause in std::mem::take(&mut unify.clauses) {
+            let UnifyClause::Fragment(fragment) = &clause else {
+                self.visit_unify_clause_mut(&mut clause);
+                clauses.push(clause);
+                continue;
+            };
+            let Some(body) = self.instantiate(fragment) else {
+                continue;
+            };
+            let span = self.stack.first().map_or(fragment.span, |(_, span)| *span);
+            self.stack.push((fragment.name.clone(), span));
+            let mut body = Unify {
+                clauses: body,
+                span: unify.span,
+            };
+            self.visit_unify_mut(&mut body);
+            self.stack.pop();
+            clauses.extend(body.clauses);
+        }
+        unify.clauses = clauses;
+    }
+}
+
+impl Expander<'_> {
+    /// The clauses of the fragment `fragment` refers to, with its arguments
+    /// substituted and its own variables renamed apart.
+    fn instantiate(&mut self, fragment: &FragmentRef) -> Option<Vec<UnifyClause>> {
+        let span = self.stack.first().map_or(fragment.span, |(_, span)| *span);
+        if self.stack.iter().any(|(name, _)| *name == fragment.name) {
+            let mut cycle: Vec<&str> = self.stack.iter().map(|(name, _)| name.as_str()).collect();
+            cycle.push(&fragment.name);
+            self.diagnostics.push(Diagnostic::error(
+                "fragment-cycle",
+                span,
+                format!(
+                    "fragment `{}` includes itself: {}",
+                    fragment.name,
+                    cycle.join(" -> ")
+                ),
+            ));
+            return None;
+        }
+        let Some(def) = self.fragments.get(&fragment.name) else {
+            let mut message = format!("unknown fragment `{}`", fragment.name);
+            let names = self.fragments.defs.keys().map(String::as_str);
+            if let Some(closest) = suggest::closest(&fragment.name, names) {
+                message.push_str(&format!("; did you mean `{}`?", closest));
+            }
+            self.diagnostics
+                .push(Diagnostic::error("unknown-fragment", span, message));
+            return None;
+        };
+        if def.params.len() != fragment.args.len() {
+            self.diagnostics.push(Diagnostic::error(
+                "fragment-arity",
+                span,
+                format!(
+                    "fragment `{}` takes {} argument{}, got {}",
+                    def.name,
+                    def.params.len(),
+                    if def.params.len() == 1 { "" } else { "s" },
+                    fragment.args.len()
+                ),
+            ));
+            return None;
+        }
+        self.instances += 1;
+        let mut instance = Instance {
+            name: &def.name,
+            args: def
+                .params
+                .iter()
+                .cloned()
+                .zip(fragment.args.clone())
+                .collect(),
+            renames: BTreeMap::new(),
+            used: &mut self.used,
+            suffix: self.instances,
+            span,
+            diagnostics: &mut self.diagnostics,
+        };
+        let mut clauses = def.clauses.clone();
+        for clause in &mut clauses {
+            instance.visit_unify_clause_mut(clause);
+        }
+        Some(clauses)
+    }
+}
+
+/// Substitutes arguments for parameters in one use of a fragment and renames
+/// its other variables. Nested queries are left alone: their variables are
+/// their own, and they only see the fragment's through `:args`.
+struct Instance<'a> {
+    name: &'a str,
+    args: BTreeMap<String, Expr>,
+    renames: BTreeMap<String, String>,
+    used: &'a mut BTreeSet<String>,
+    suffix: usize,
+    span: Span,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl VisitorMut for Instance<'_> {
+    fn visit_query_mut(&mut self, _query: &mut Query) {}
+
+    fn visit_unify_clause_mut(&mut self, clause: &mut UnifyClause) {
+        match clause {
+            UnifyClause::With(with) => {
+                for binding in &mut with.bindings {
+                    binding.name = self.bound(&binding.name);
+                }
+            }
+            UnifyClause::Unnest(unnest) => unnest.binding.name = self.bound(&unnest.binding.name),
+            _ => {}
+        }
+        walk_unify_clause_mut(self, clause);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let ExprKind::Var(name) = &expr.kind {
+            match self.args.get(name) {
+                Some(arg) => *expr = arg.clone(),
+                None => expr.kind = ExprKind::Var(self.rename(name)),
+            }
+            return;
+        }
+        walk_expr_mut(self, expr);
+    }
+}
+
+impl Instance<'_> {
+    fn rename(&mut self, var: &str) -> String {
+        if let Some(fresh) = self.renames.get(var) {
+            return fresh.clone();
+        }
+        let mut fresh = format!("{}__{}", var, self.suffix);
+        while self.used.contains(&fresh) {
+            fresh.push('_');
+        }
+        self.used.insert(fresh.clone());
+        self.renames.insert(var.to_string(), fresh.clone());
+        fresh
+    }
+
+    /// The variable `with` or `unnest` binds to `var`: the argument for a
+    /// parameter, which must then be a variable itself.
+    fn bound(&mut self, var: &str) -> String {
+        match self.args.get(var) {
+            Some(arg) => match arg.as_var() {
+                Some(arg) => arg.to_string(),
+                None => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "fragment-argument",
+                        self.span,
+                        format!(
+                            "fragment `{}` binds its parameter `{}`, so it must be passed a logic variable, not {}",
+                            self.name, var, arg
+                        ),
+                    ));
+                    var.to_string()
+                }
+            },
+            None => self.rename(var),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    const DEFS: &str = "
+        (deffragment active [u] (from :users [{:xt/id u} {:active true}]))
+        (deffragment orders-of [u total]
+          (from :orders [{:customer u} amount])
+          (where (> amount 0))
+          (with {total amount}))
+        (deffragment active-orders [u total] (fragment active u) (fragment orders-of u total))
+        (deffragment loop-a [] (fragment loop-b))
+        (deffragment loop-b [] (fragment loop-a))";
+
+    fn expanded(query: &str) -> Result<String, Vec<String>> {
+        Fragments::parse(DEFS)
+            .unwrap()
+            .expand(&parse_query(query).unwrap())
+            .map(|query| query.to_string())
+            .map_err(|diagnostics| diagnostics.iter().map(|d| d.message.clone()).collect())
+    }
+
+    #[test]
+    fn substitutes_arguments() {
+        assert_eq!(
+            expanded("(unify (from :t [u]) (fragment active u))"),
+            Ok("(unify (from :t [u]) (from :users [{:xt/id u} {:active true}]))".to_string())
+        );
+        assert_eq!(
+            expanded("(unify (fragment active $id))"),
+            Ok("(unify (from :users [{:xt/id $id} {:active true}]))".to_string())
+        );
+    }
+
+    #[test]
+    fn renames_private_variables_apart() {
+        assert_eq!(
+            expanded(
+                "(unify (from :t [amount]) (fragment orders-of 1 a) (fragment orders-of 2 b))"
+            ),
+            Ok("(unify (from :t [amount]) \
+                (from :orders [{:customer 1} {:amount amount__1}]) (where (> amount__1 0)) \
+                (with {a amount__1}) \
+                (from :orders [{:customer 2} {:amount amount__2}]) (where (> amount__2 0)) \
+                (with {b amount__2}))"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn expands_nested_fragments() {
+        assert_eq!(
+            expanded("(unify (fragment active-orders u t))"),
+            Ok("(unify (from :users [{:xt/id u} {:active true}]) \
+                (from :orders [{:customer u} {:amount amount__3}]) (where (> amount__3 0)) \
+                (with {t amount__3}))"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn reports_unusable_fragments() {
+        assert_eq!(
+            expanded("(unify (fragment activ u))"),
+            Err(vec![
+                "unknown fragment `activ`; did you mean `active`?".to_string()
+            ])
+        );
+        assert_eq!(
+            expanded("(unify (fragment active u v))"),
+            Err(vec!["fragment `active` takes 1 argument, got 2".to_string()])
+        );
+        assert_eq!(
+            expanded("(unify (fragment orders-of u 1))"),
+            Err(vec![
+                "fragment `orders-of` binds its parameter `total`, so it must be passed a logic \
+                 variable, not 1"
+                    .to_string()
+            ])
+        );
+        assert_eq!(
+            expanded("(unify (fragment loop-a))"),
+            Err(vec![
+                "fragment `loop-a` includes itself: loop-a -> loop-b -> loop-a".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn check_reports_broken_definitions() {
+        let messages: Vec<String> = Fragments::parse(DEFS)
+            .unwrap()
+            .check()
+            .iter()
+            .map(|d| d.message.clone())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "fragment `loop-a` includes itself: loop-a -> loop-b -> loop-a",
+                "fragment `loop-b` includes itself: loop-b -> loop-a -> loop-b",
+            ]
+        );
+    }
+}
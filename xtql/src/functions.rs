@@ -0,0 +1,570 @@
+//! Catalogue of XTDB's built-in functions and aggregates, and a pass checking
+//! calls against it.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::suggest;
+use crate::visit::{walk_expr, walk_query, walk_subquery, walk_tail_op, Visitor};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Scalar,
+    /// Only valid within an `aggregate` spec.
+    Aggregate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(k) => n == k,
+            Arity::AtLeast(min) => n >= min,
+            Arity::Range(min, max) => (min..=max).contains(&n),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} {}", n, plural(n)),
+            Arity::AtLeast(n) => write!(f, "at least {} {}", n, plural(n)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+/// The broad kind of value a function takes or returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Any,
+    Numeric,
+    String,
+    Boolean,
+    /// Dates, times and timestamps.
+    Temporal,
+    /// Durations, intervals and periods.
+    Interval,
+    /// Vectors and sets.
+    Collection,
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValueKind::Any => "any value",
+            ValueKind::Numeric => "a number",
+            ValueKind::String => "a string",
+            ValueKind::Boolean => "a boolean",
+            ValueKind::Temporal => "a date or timestamp",
+            ValueKind::Interval => "an interval",
+            ValueKind::Collection => "a collection",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Returns {
+    Kind(ValueKind),
+    /// The common type of the arguments, e.g. `+`, `coalesce` or `max`.
+    Args,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub arity: Arity,
+    /// The kind of each argument; the last one applies to any further
+    /// arguments of a variadic function.
+    pub params: Vec<ValueKind>,
+    pub returns: Returns,
+}
+
+impl Function {
+    pub fn new(
+        name: &str,
+        kind: FunctionKind,
+        arity: Arity,
+        params: &[ValueKind],
+        returns: Returns,
+    ) -> Self {
+        Function {
+            name: name.to_string(),
+            kind,
+            arity,
+            params: params.to_vec(),
+            returns,
+        }
+    }
+
+    /// The expected kind of the `i`th argument.
+    pub fn param(&self, i: usize) -> ValueKind {
+        self.params
+            .get(i)
+            .or(self.params.last())
+            .copied()
+            .unwrap_or(ValueKind::Any)
+    }
+}
+
+/// The functions a query may call, keyed by name.
+#[derive(Debug, Clone, Default)]
+pub struct Catalogue {
+    functions: BTreeMap<String, Function>,
+}
+
+impl Catalogue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// XTDB's standard library.
+    pub fn standard() -> &'static Catalogue {
+        static STANDARD: OnceLock<Catalogue> = OnceLock::new();
+        STANDARD.get_or_init(|| {
+            let mut catalogue = Catalogue::new();
+            for function in standard_functions() {
+                catalogue.insert(function);
+            }
+            catalogue
+        })
+    }
+
+    /// Adds or replaces a function, e.g. one provided by a server extension.
+    pub fn insert(&mut self, function: Function) {
+        self.functions.insert(function.name.clone(), function);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Function> {
+        self.functions.values()
+    }
+
+    /// Reports calls to unknown functions, calls with the wrong number of
+    /// arguments, and aggregates used outside an `aggregate` spec or nested
+    /// inside another aggregate.
+    pub fn check(&self, query: &Query) -> Vec<Diagnostic> {
+        let mut checker = Checker {
+            catalogue: self,
+            context: Context::Scalar,
+            diagnostics: vec![],
+        };
+        checker.visit_query(query);
+        checker.diagnostics.sort_by_key(|d| d.span.start);
+        checker.diagnostics
+    }
+}
+
+/// Checks `query` against the [standard](Catalogue::standard) catalogue.
+pub fn check_functions(query: &Query) -> Vec<Diagnostic> {
+    Catalogue::standard().check(query)
+}
+
+#[rustfmt::skip]
+fn standard_functions() -> Vec<Function> {
+    use Arity::*;
+    use FunctionKind::*;
+    use ValueKind::*;
+
+    let f = Function::new;
+    let kind = Returns::Kind;
+    let args = Returns::Args;
+    let mut functions = vec![
+        // comparison and logic
+        f("=", Scalar, AtLeast(2), &[Any], kind(Boolean)),
+        f("<>", Scalar, Exact(2), &[Any], kind(Boolean)),
+        f("<", Scalar, AtLeast(2), &[Any], kind(Boolean)),
+        f(">", Scalar, AtLeast(2), &[Any], kind(Boolean)),
+        f("<=", Scalar, AtLeast(2), &[Any], kind(Boolean)),
+        f(">=", Scalar, AtLeast(2), &[Any], kind(Boolean)),
+        f("between", Scalar, Exact(3), &[Any], kind(Boolean)),
+        f("not", Scalar, Exact(1), &[Boolean], kind(Boolean)),
+        f("and", Scalar, AtLeast(0), &[Boolean], kind(Boolean)),
+        f("or", Scalar, AtLeast(0), &[Boolean], kind(Boolean)),
+        f("nil?", Scalar, Exact(1), &[Any], kind(Boolean)),
+        f("true?", Scalar, Exact(1), &[Any], kind(Boolean)),
+        f("false?", Scalar, Exact(1), &[Any], kind(Boolean)),
+        f("in?", Scalar, Exact(2), &[Any, Collection], kind(Boolean)),
+        // control flow
+        f("if", Scalar, Range(2, 3), &[Boolean, Any], kind(Any)),
+        f("case", Scalar, AtLeast(2), &[Any], kind(Any)),
+        f("cond", Scalar, AtLeast(2), &[Any], kind(Any)),
+        f("coalesce", Scalar, AtLeast(1), &[Any], args),
+        f("nullif", Scalar, Exact(2), &[Any], args),
+        f("greatest", Scalar, AtLeast(1), &[Any], args),
+        f("least", Scalar, AtLeast(1), &[Any], args),
+        // arithmetic; `+` and `-` also apply to temporal values and intervals
+        f("+", Scalar, AtLeast(1), &[Any], args),
+        f("-", Scalar, AtLeast(1), &[Any], args),
+        f("*", Scalar, AtLeast(1), &[Numeric], args),
+        f("/", Scalar, AtLeast(2), &[Numeric], args),
+        f("mod", Scalar, Exact(2), &[Numeric], args),
+        f("abs", Scalar, Exact(1), &[Numeric], args),
+        f("ceil", Scalar, Exact(1), &[Numeric], args),
+        f("floor", Scalar, Exact(1), &[Numeric], args),
+        f("round", Scalar, Range(1, 2), &[Numeric], args),
+        f("power", Scalar, Exact(2), &[Numeric], kind(Numeric)),
+        f("log", Scalar, Exact(2), &[Numeric], kind(Numeric)),
+        // strings; `lower` and `upper` also take the bounds of a period
+        f("str", Scalar, AtLeast(0), &[Any], kind(String)),
+        f("lower", Scalar, Exact(1), &[Any], kind(Any)),
+        f("upper", Scalar, Exact(1), &[Any], kind(Any)),
+        f("trim", Scalar, Range(1, 2), &[String], kind(String)),
+        f("trim-leading", Scalar, Range(1, 2), &[String], kind(String)),
+        f("trim-trailing", Scalar, Range(1, 2), &[String], kind(String)),
+        f("char-length", Scalar, Exact(1), &[String], kind(Numeric)),
+        f("octet-length", Scalar, Exact(1), &[String], kind(Numeric)),
+        f("length", Scalar, Exact(1), &[Any], kind(Numeric)),
+        f("substring", Scalar, Range(2, 3), &[String, Numeric], kind(String)),
+        f("position", Scalar, Exact(2), &[String], kind(Numeric)),
+        f("overlay", Scalar, Range(3, 4), &[String, String, Numeric], kind(String)),
+        f("replace", Scalar, Exact(3), &[String], kind(String)),
+        f("like", Scalar, Exact(2), &[String], kind(Boolean)),
+        f("like-regex", Scalar, Range(2, 3), &[String], kind(Boolean)),
+        // temporal
+        f("extract", Scalar, Exact(2), &[String, Temporal], kind(Numeric)),
+        f("date-trunc", Scalar, Range(2, 3), &[String, Temporal, String], kind(Temporal)),
+        f("date-bin", Scalar, Range(2, 3), &[Interval, Temporal], kind(Temporal)),
+        f("age", Scalar, Exact(2), &[Temporal], kind(Interval)),
+        f("period", Scalar, Exact(2), &[Temporal], kind(Interval)),
+        // collections
+        f("cardinality", Scalar, Exact(1), &[Collection], kind(Numeric)),
+        f("trim-array", Scalar, Exact(2), &[Collection, Numeric], kind(Collection)),
+        // aggregates
+        f("row-count", Aggregate, Exact(0), &[], kind(Numeric)),
+        f("count", Aggregate, Exact(1), &[Any], kind(Numeric)),
+        f("count-distinct", Aggregate, Exact(1), &[Any], kind(Numeric)),
+        f("sum", Aggregate, Exact(1), &[Numeric], args),
+        f("sum-distinct", Aggregate, Exact(1), &[Numeric], args),
+        f("avg", Aggregate, Exact(1), &[Numeric], kind(Numeric)),
+        f("avg-distinct", Aggregate, Exact(1), &[Numeric], kind(Numeric)),
+        f("min", Aggregate, Exact(1), &[Any], args),
+        f("max", Aggregate, Exact(1), &[Any], args),
+        f("array-agg", Aggregate, Exact(1), &[Any], kind(Collection)),
+        f("array-agg-distinct", Aggregate, Exact(1), &[Any], kind(Collection)),
+        f("every", Aggregate, Exact(1), &[Boolean], kind(Boolean)),
+        f("bool-and", Aggregate, Exact(1), &[Boolean], kind(Boolean)),
+        f("bool-or", Aggregate, Exact(1), &[Boolean], kind(Boolean)),
+    ];
+    for name in [
+        "sqrt", "exp", "ln", "log10", "sin", "cos", "tan", "asin", "acos", "atan", "sinh", "cosh",
+        "tanh",
+    ] {
+        functions.push(f(name, Scalar, Exact(1), &[Numeric], kind(Numeric)));
+    }
+    for name in [
+        "current-date",
+        "current-time",
+        "current-timestamp",
+        "local-time",
+        "local-timestamp",
+    ] {
+        functions.push(f(name, Scalar, Range(0, 1), &[Numeric], kind(Temporal)));
+    }
+    for name in [
+        "overlaps?",
+        "contains?",
+        "equals?",
+        "precedes?",
+        "succeeds?",
+        "meets?",
+        "immediately-precedes?",
+        "immediately-succeeds?",
+        "lags?",
+        "leads?",
+        "strictly-precedes?",
+        "strictly-succeeds?",
+        "strictly-overlaps?",
+        "strictly-contains?",
+        "strictly-lags?",
+        "strictly-leads?",
+        "immediately-lags?",
+        "immediately-leads?",
+    ] {
+        functions.push(f(name, Scalar, Exact(2), &[Any], kind(Boolean)));
+    }
+    for name in ["stddev-pop", "stddev-samp", "var-pop", "var-samp"] {
+        functions.push(f(name, Aggregate, Exact(1), &[Numeric], kind(Numeric)));
+    }
+    functions
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Scalar,
+    /// An `aggregate` spec, where aggregates may be called.
+    AggregateSpec,
+    /// The arguments of an aggregate call.
+    AggregateArg,
+}
+
+struct Checker<'a> {
+    catalogue: &'a Catalogue,
+    context: Context,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_query(&mut self, query: &Query) {
+        self.within(Context::Scalar, |checker| walk_query(checker, query));
+    }
+
+    fn visit_subquery(&mut self, subquery: &Subquery) {
+        self.within(Context::Scalar, |checker| walk_subquery(checker, subquery));
+    }
+
+    fn visit_tail_op(&mut self, op: &TailOp) {
+        match op {
+            TailOp::Aggregate(_) => {
+                self.within(Context::AggregateSpec, |checker| walk_tail_op(checker, op))
+            }
+            _ => walk_tail_op(self, op),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Call { function, args } => {
+                let context = self.call(expr.span, function, args.len(), self.context);
+                self.within(context, |checker| walk_expr(checker, expr));
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+impl Checker<'_> {
+    /// Runs `visit` in `context`, restoring the current one afterwards.
+    fn within(&mut self, context: Context, visit: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.context, context);
+        visit(self);
+        self.context = outer;
+    }
+
+    /// Checks a call and returns the context its arguments are in.
+    fn call(&mut self, span: Span, name: &str, n_args: usize, context: Context) -> Context {
+        // `(:field x)` reads a field of a struct.
+        if name.starts_with(':') {
+            return context;
+        }
+        let Some(function) = self.catalogue.get(name) else {
+            let mut message = format!("unknown function `{}`", name);
+            let names = self.catalogue.functions.keys().map(String::as_str);
+            if let Some(suggestion) = suggest::closest(name, names) {
+                message += &format!("; did you mean `{}`?", suggestion);
+            }
+            self.diagnostics
+                .push(Diagnostic::error("unknown-function", span, message));
+            return context;
+        };
+        if !function.arity.accepts(n_args) {
+            self.diagnostics.push(Diagnostic::error(
+                "wrong-arity",
+                span,
+                format!("`{}` expects {}, got {}", name, function.arity, n_args),
+            ));
+        }
+        if function.kind != FunctionKind::Aggregate {
+            return context;
+        }
+        let problem = match context {
+            Context::AggregateSpec => return Context::AggregateArg,
+            Context::AggregateArg => "cannot be nested inside another aggregate",
+            Context::Scalar => "can only be used in `aggregate`",
+        };
+        self.diagnostics.push(Diagnostic::error(
+            "misplaced-aggregate",
+            span,
+            format!("aggregate `{}` {}", name, problem),
+        ));
+        Context::AggregateArg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    fn check(text: &str) -> Vec<(String, &str)> {
+        check_functions(&parse_query(text).unwrap())
+            .iter()
+            .map(|d| (d.to_string(), &text[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    fn error(at: &str, code: &str, message: &str) -> String {
+        format!("{}: error[{}]: {}", at, code, message)
+    }
+
+    #[test]
+    fn accepts_known_calls() {
+        assert_eq!(
+            check(
+                "(-> (from :t [x s]) (where (= x 1) (between x 1 2) (if true x)) \
+                 (return {:y (round x)} {:z (trim s \" \")} {:k (:k x)}))"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn checks_arity() {
+        assert_eq!(
+            check("(-> (from :t [x]) (where (<> x 1 2) (not) (round x 1 2)))"),
+            vec![
+                (
+                    error("1:26", "wrong-arity", "`<>` expects 2 arguments, got 3"),
+                    "(<> x 1 2)"
+                ),
+                (
+                    error("1:37", "wrong-arity", "`not` expects 1 argument, got 0"),
+                    "(not)"
+                ),
+                (
+                    error(
+                        "1:43",
+                        "wrong-arity",
+                        "`round` expects 1 to 2 arguments, got 3"
+                    ),
+                    "(round x 1 2)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn suggests_close_function_names() {
+        assert_eq!(
+            check("(-> (from :t [x]) (where (uper x) (lenght x) (frobnicate x)))"),
+            vec![
+                (
+                    error(
+                        "1:26",
+                        "unknown-function",
+                        "unknown function `uper`; did you mean `upper`?"
+                    ),
+                    "(uper x)"
+                ),
+                (
+                    error(
+                        "1:35",
+                        "unknown-function",
+                        "unknown function `lenght`; did you mean `length`?"
+                    ),
+                    "(lenght x)"
+                ),
+                (
+                    error("1:46", "unknown-function", "unknown function `frobnicate`"),
+                    "(frobnicate x)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_against_a_custom_catalogue() {
+        let query = parse_query("(-> (from :t [x]) (where (frobnicate x)))").unwrap();
+        let mut catalogue = Catalogue::standard().clone();
+        catalogue.insert(Function::new(
+            "frobnicate",
+            FunctionKind::Scalar,
+            Arity::Exact(1),
+            &[ValueKind::Any],
+            Returns::Kind(ValueKind::Boolean),
+        ));
+        assert_eq!(catalogue.check(&query), vec![]);
+    }
+
+    #[test]
+    fn aggregates_belong_in_aggregate_specs() {
+        assert_eq!(
+            check(
+                "(-> (from :t [x]) (aggregate {:n (row-count)} {:s (sum (+ x 1))} {:m (max x)}))"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            check("(-> (from :t [x]) (where (> (sum x) 1)) (return {:c (count x)}))"),
+            vec![
+                (
+                    error(
+                        "1:29",
+                        "misplaced-aggregate",
+                        "aggregate `sum` can only be used in `aggregate`"
+                    ),
+                    "(sum x)"
+                ),
+                (
+                    error(
+                        "1:53",
+                        "misplaced-aggregate",
+                        "aggregate `count` can only be used in `aggregate`"
+                    ),
+                    "(count x)"
+                ),
+            ]
+        );
+        // A subquery starts a new context, wherever it appears.
+        assert_eq!(
+            check(
+                "(-> (from :t [x]) (aggregate {:n (count (q (-> (from :u [y]) \
+                 (aggregate {:s (sum y)}))))}))"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            check(
+                "(-> (from :t [x]) (aggregate {:n (count x)}) \
+                 (where (exists? (-> (from :u [y]) (return {:s (sum y)})))))"
+            ),
+            vec![(
+                error(
+                    "1:92",
+                    "misplaced-aggregate",
+                    "aggregate `sum` can only be used in `aggregate`"
+                ),
+                "(sum y)"
+            )]
+        );
+    }
+
+    #[test]
+    fn aggregates_do_not_nest() {
+        assert_eq!(
+            check("(-> (from :t [x]) (aggregate {:n (sum (count x))} {:a (avg (+ (max x) 1))}))"),
+            vec![
+                (
+                    error(
+                        "1:39",
+                        "misplaced-aggregate",
+                        "aggregate `count` cannot be nested inside another aggregate"
+                    ),
+                    "(count x)"
+                ),
+                (
+                    error(
+                        "1:63",
+                        "misplaced-aggregate",
+                        "aggregate `max` cannot be nested inside another aggregate"
+                    ),
+                    "(max x)"
+                ),
+            ]
+        );
+    }
+}
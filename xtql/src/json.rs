@@ -0,0 +1,948 @@
+//! Reading queries written directly in XTDB's JSON encoding.
+//!
+//! [`query_from_json`] decodes JSON into the typed AST, so that hand-written
+//! JSON queries get the same checks as XTQL text, and reports the first
+//! problem with its JSONPath:
+//!
+//! ```
+//! use serde_json::json;
+//! use xtql::query_from_json;
+//!
+//! let query = query_from_json(&json!([
+//!     {"from": "orders", "bind": [{"o-custkey": {"xt:lvar": "c"}}]},
+//!     {"limit": 10}
+//! ]))
+//! .unwrap();
+//! assert_eq!(query.to_string(), "(-> (from :orders [{:o-custkey c}]) (limit 10))");
+//!
+//! let err = query_from_json(&json!({"from": "orders", "bind": [{"o-custkey": {"xt:var": "c"}}]}))
+//!     .unwrap_err();
+//! assert_eq!(
+//!     err.to_string(),
+//!     "$.bind[0].o-custkey.xt:var: unknown key `xt:var` in an expression; did you mean `xt:lvar`?"
+//! );
+//! ```
+//!
+//! Older encodings, such as the files in `client/resources/tpch-json`, are
+//! accepted too: join options wrapped in `"bind": [{...}]`, subquery
+//! arguments in `"bind": [[...]]` and parameters written `{"p": {"xt:param":
+//! "p"}}`. Re-encoding the AST with [`Query::to_json`] produces the current
+//! encoding, which [`json_schema`] describes.
+
+use crate::ast::*;
+use crate::suggest;
+use crate::value;
+use serde_json::{Map, Value as JSONValue};
+use std::fmt;
+
+/// A query that does not follow XTDB's JSON encoding, with the JSONPath
+/// (`$[1].unify[0].bind[2]`) of the offending value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub path: String,
+    pub message: String,
+}
+
+impl JsonError {
+    fn new(message: impl Into<String>) -> Self {
+        JsonError {
+            path: String::new(),
+            message: message.into(),
+        }
+    }
+
+    fn index(mut self, index: usize) -> Self {
+        self.path.insert_str(0, &format!("[{}]", index));
+        self
+    }
+
+    fn key(mut self, key: &str) -> Self {
+        let plain = !key.is_empty()
+            && !key
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, '.' | '[' | ']' | '"' | '\''));
+        if plain {
+            self.path.insert_str(0, &format!(".{}", key));
+        } else {
+            self.path.insert_str(0, &format!("[{:?}]", key));
+        }
+        self
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+type Result<T> = std::result::Result<T, JsonError>;
+
+/// The JSON Schema (draft 2020-12) of XTDB's JSON query encoding, as
+/// [`Query::to_json`] produces it, for editors and for clients written in
+/// other languages.
+pub fn json_schema() -> JSONValue {
+    serde_json::from_str(include_str!("xtql.schema.json")).expect("xtql.schema.json is valid JSON")
+}
+
+/// Decodes a query in XTDB's JSON encoding: an operator object, or an array
+/// of operators for a pipeline.
+pub fn query_from_json(value: &JSONValue) -> std::result::Result<Query, JsonError> {
+    query(value)
+}
+
+const SOURCE_OPS: &[&str] = &["from", "rel", "unify"];
+const TAIL_OPS: &[&str] = &[
+    "aggregate",
+    "limit",
+    "offset",
+    "orderBy",
+    "return",
+    "where",
+    "with",
+    "without",
+    "unnest",
+];
+const UNIFY_CLAUSES: &[&str] = &["from", "rel", "with", "unnest", "where", "join", "leftJoin"];
+const EXPR_KEYS: &[&str] = &[
+    "xt:lvar",
+    "xt:param",
+    "xt:call",
+    "xt:get",
+    "xt:q",
+    "xt:exists",
+    "xt:pull",
+    "xt:pullMany",
+    "@type",
+];
+const SUBQUERIES: &[(&str, SubqueryKind)] = &[
+    ("xt:q", SubqueryKind::Q),
+    ("xt:exists", SubqueryKind::Exists),
+    ("xt:pull", SubqueryKind::Pull),
+    ("xt:pullMany", SubqueryKind::PullMany),
+];
+
+fn describe(value: &JSONValue) -> String {
+    match value {
+        JSONValue::Null => "null".to_string(),
+        JSONValue::Bool(b) => b.to_string(),
+        JSONValue::Number(n) => format!("the number {}", n),
+        JSONValue::String(s) => format!("the string {:?}", s),
+        JSONValue::Array(_) => "an array".to_string(),
+        JSONValue::Object(_) => "an object".to_string(),
+    }
+}
+
+fn expected(what: &str, value: &JSONValue) -> JsonError {
+    JsonError::new(format!("expected {}, found {}", what, describe(value)))
+}
+
+fn one_of(keys: &[&str]) -> String {
+    keys.iter()
+        .map(|key| format!("`{}`", key))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn object<'a>(value: &'a JSONValue, what: &str) -> Result<&'a Map<String, JSONValue>> {
+    value.as_object().ok_or_else(|| expected(what, value))
+}
+
+fn array<'a>(value: &'a JSONValue, what: &str) -> Result<&'a Vec<JSONValue>> {
+    value.as_array().ok_or_else(|| expected(what, value))
+}
+
+fn string<'a>(value: &'a JSONValue, what: &str) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| expected(what, value))
+}
+
+/// Fails on any key of `map` not in `allowed`.
+fn only(map: &Map<String, JSONValue>, allowed: &[&str], what: &str) -> Result<()> {
+    match map.keys().find(|key| !allowed.contains(&key.as_str())) {
+        Some(key) => Err(unknown_key(key, allowed, what)),
+        None => Ok(()),
+    }
+}
+
+fn unknown_key(key: &str, allowed: &[&str], what: &str) -> JsonError {
+    let message = match suggest::closest(key, allowed.iter().copied()) {
+        Some(closest) => format!(
+            "unknown key `{}` in {}; did you mean `{}`?",
+            key, what, closest
+        ),
+        None => format!(
+            "unknown key `{}` in {}; expected {}",
+            key,
+            what,
+            one_of(allowed)
+        ),
+    };
+    JsonError::new(message).key(key)
+}
+
+/// `map[key]`, decoded by `decode`, with errors located under `key`.
+fn field<'a, T>(
+    map: &'a Map<String, JSONValue>,
+    key: &str,
+    what: &str,
+    decode: impl FnOnce(&'a JSONValue) -> Result<T>,
+) -> Result<T> {
+    match map.get(key) {
+        Some(value) => decode(value).map_err(|err| err.key(key)),
+        None => Err(JsonError::new(format!("{} is missing `{}`", what, key))),
+    }
+}
+
+fn optional<'a, T>(
+    map: &'a Map<String, JSONValue>,
+    key: &str,
+    decode: impl FnOnce(&'a JSONValue) -> Result<T>,
+) -> Result<Option<T>> {
+    map.get(key)
+        .map(|value| decode(value).map_err(|err| err.key(key)))
+        .transpose()
+}
+
+/// Decodes every element of `values`, with errors located at its index.
+fn each<T>(values: &[JSONValue], decode: impl Fn(&JSONValue) -> Result<T>) -> Result<Vec<T>> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| decode(value).map_err(|err| err.index(i)))
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let special = |c: char| "!$%&-=^+*<>?_/".contains(c);
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || special(c) => {
+            chars.all(|c| c.is_ascii_alphanumeric() || special(c))
+        }
+        _ => false,
+    }
+}
+
+/// A name printed as a keyword: a table, column or map key.
+fn keyword(name: &str, what: &str) -> Result<String> {
+    if is_identifier(name) {
+        Ok(name.to_string())
+    } else {
+        Err(JsonError::new(format!(
+            "`{}` is not a valid {}",
+            name, what
+        )))
+    }
+}
+
+/// A name printed as a symbol, which must not read back as a literal.
+fn symbol(name: &str, what: &str) -> Result<String> {
+    let literal = matches!(
+        name,
+        "true" | "false" | "nil" | "NaN" | "Infinity" | "-Infinity"
+    ) || name
+        .trim_start_matches(['-', '+'])
+        .starts_with(|c: char| c.is_ascii_digit());
+    if is_identifier(name) && !literal {
+        Ok(name.to_string())
+    } else {
+        Err(JsonError::new(format!(
+            "`{}` is not a valid {}",
+            name, what
+        )))
+    }
+}
+
+fn query(value: &JSONValue) -> Result<Query> {
+    match value {
+        JSONValue::Array(ops) => {
+            let Some((first, rest)) = ops.split_first() else {
+                return Err(JsonError::new(
+                    "expected a pipeline starting with a source operator, found an empty array",
+                ));
+            };
+            let source = source(first).map_err(|err| err.index(0))?;
+            let tail = rest
+                .iter()
+                .enumerate()
+                .map(|(i, op)| tail_op(op).map_err(|err| err.index(i + 1)))
+                .collect::<Result<_>>()?;
+            Ok(Query {
+                source,
+                tail,
+                pipeline: true,
+                span: Span::default(),
+            })
+        }
+        JSONValue::Object(_) => Ok(Query {
+            source: source(value)?,
+            tail: vec![],
+            pipeline: false,
+            span: Span::default(),
+        }),
+        other => Err(expected(
+            "a query: an operator object, or an array of operators",
+            other,
+        )),
+    }
+}
+
+fn source(value: &JSONValue) -> Result<Source> {
+    let map = object(value, "a source operator")?;
+    if map.contains_key("from") {
+        Ok(Source::From(from(map)?))
+    } else if map.contains_key("rel") {
+        Ok(Source::Rel(rel(map)?))
+    } else if map.contains_key("unify") {
+        Ok(Source::Unify(unify(map)?))
+    } else if let Some(op) = TAIL_OPS.iter().find(|op| map.contains_key(**op)) {
+        Err(JsonError::new(format!(
+            "`{}` cannot start a query; expected a source operator: {}",
+            op,
+            one_of(SOURCE_OPS)
+        )))
+    } else {
+        Err(JsonError::new(format!(
+            "expected a source operator: {}",
+            one_of(SOURCE_OPS)
+        )))
+    }
+}
+
+fn from(map: &Map<String, JSONValue>) -> Result<From> {
+    only(
+        map,
+        &["from", "bind", "forValidTime", "forSystemTime"],
+        "`from`",
+    )?;
+    Ok(From {
+        table: field(map, "from", "`from`", table)?,
+        bind: field(map, "bind", "`from`", bind_specs)?,
+        for_valid_time: optional(map, "forValidTime", temporal_filter)?.map(Box::new),
+        for_system_time: optional(map, "forSystemTime", temporal_filter)?.map(Box::new),
+        span: Span::default(),
+    })
+}
+
+fn table(value: &JSONValue) -> Result<Ident> {
+    Ok(Ident {
+        name: keyword(string(value, "a table name")?, "table name")?,
+        span: Span::default(),
+    })
+}
+
+/// `[{"col": expr}, ...]`, where an object may bind several columns.
+fn bind_specs(value: &JSONValue) -> Result<Vec<BindSpec>> {
+    let specs = array(value, "an array of bind specs")?;
+    if specs.is_empty() {
+        return Err(JsonError::new("expected at least one bind spec"));
+    }
+    let specs = each(specs, |spec| {
+        let map = object(spec, "a bind spec: an object from columns to expressions")?;
+        let bindings = entries(map, "column name")?;
+        Ok(bindings
+            .into_iter()
+            .map(|binding| BindSpec::new(&binding.name, binding.expr))
+            .collect::<Vec<_>>())
+    })?;
+    Ok(specs.into_iter().flatten().collect())
+}
+
+fn temporal_filter(value: &JSONValue) -> Result<TemporalFilter> {
+    if value.as_str() == Some("allTime") {
+        return Ok(TemporalFilter::AllTime);
+    }
+    let map = object(
+        value,
+        "a temporal filter: `\"allTime\"`, or an object with `at`, `from`, `to` or `in`",
+    )?;
+    only(map, &["at", "from", "to", "in"], "a temporal filter")?;
+    let Some((key, value)) = map.iter().next().filter(|_| map.len() == 1) else {
+        return Err(JsonError::new(
+            "expected a temporal filter with exactly one of `at`, `from`, `to` or `in`",
+        ));
+    };
+    let filter = match key.as_str() {
+        "at" => timestamp(value).map(TemporalFilter::At),
+        "from" => timestamp(value).map(TemporalFilter::From),
+        "to" => timestamp(value).map(TemporalFilter::To),
+        _ => match array(value, "an array of two timestamps")?.as_slice() {
+            [from, to] => Ok(TemporalFilter::In(
+                timestamp(from).map_err(|err| err.index(0))?,
+                timestamp(to).map_err(|err| err.index(1))?,
+            )),
+            _ => Err(expected("an array of two timestamps", value)),
+        },
+    };
+    filter.map_err(|err| err.key(key))
+}
+
+fn timestamp(value: &JSONValue) -> Result<Expr> {
+    let expr = expr(value)?;
+    match expr.kind {
+        ExprKind::String(_)
+        | ExprKind::Tagged { .. }
+        | ExprKind::Param(_)
+        | ExprKind::Var(_)
+        | ExprKind::Nil => Ok(expr),
+        _ => Err(expected(
+            "a timestamp: a string, a typed value, a parameter, a logic variable or null",
+            value,
+        )),
+    }
+}
+
+fn rel(map: &Map<String, JSONValue>) -> Result<Rel> {
+    only(map, &["rel", "bind"], "`rel`")?;
+    Ok(Rel {
+        expr: field(map, "rel", "`rel`", expr)?,
+        bind: field(map, "bind", "`rel`", bind_specs)?,
+        span: Span::default(),
+    })
+}
+
+fn unify(map: &Map<String, JSONValue>) -> Result<Unify> {
+    only(map, &["unify"], "`unify`")?;
+    let clauses = field(map, "unify", "`unify`", |value| {
+        let clauses = array(value, "an array of unify clauses")?;
+        if clauses.is_empty() {
+            return Err(JsonError::new("expected at least one unify clause"));
+        }
+        each(clauses, unify_clause)
+    })?;
+    Ok(Unify {
+        clauses,
+        span: Span::default(),
+    })
+}
+
+fn unify_clause(value: &JSONValue) -> Result<UnifyClause> {
+    let what = "a unify clause";
+    let map = object(value, what)?;
+    let Some(op) = UNIFY_CLAUSES.iter().find(|op| map.contains_key(**op)) else {
+        return Err(JsonError::new(format!(
+            "expected a unify clause: {}",
+            one_of(UNIFY_CLAUSES)
+        )));
+    };
+    match *op {
+        "from" => return Ok(UnifyClause::From(from(map)?)),
+        "rel" => return Ok(UnifyClause::Rel(rel(map)?)),
+        "join" => return Ok(UnifyClause::Join(join(map, "join")?)),
+        "leftJoin" => return Ok(UnifyClause::LeftJoin(join(map, "leftJoin")?)),
+        _ => {}
+    }
+    only(map, &[op], what)?;
+    let value = &map[*op];
+    let clause = match *op {
+        "with" => bindings(value, "logic variable").and_then(|bindings| {
+            if bindings.is_empty() {
+                return Err(JsonError::new("expected at least one binding"));
+            }
+            Ok(UnifyClause::With(With {
+                bindings,
+                span: Span::default(),
+            }))
+        }),
+        "unnest" => unnest(value, "logic variable").map(UnifyClause::Unnest),
+        _ => exprs(value).map(|exprs| {
+            UnifyClause::Where(Where {
+                exprs,
+                span: Span::default(),
+            })
+        }),
+    };
+    clause.map_err(|err| err.key(op))
+}
+
+fn join(map: &Map<String, JSONValue>, op: &str) -> Result<Join> {
+    let what = format!("`{}`", op);
+    only(map, &[op, "bind", "args"], &what)?;
+    let query = field(map, op, &what, query)?;
+    let (bind, args) = match map.get("bind").and_then(legacy_join_opts) {
+        Some(opts) => join_opts(opts).map_err(|err| err.index(0).key("bind"))?,
+        None => join_opts(map)?,
+    };
+    if bind.is_empty() && args.is_empty() {
+        return Err(JsonError::new(format!(
+            "{} needs `bind`, `args` or both",
+            what
+        )));
+    }
+    Ok(Join {
+        query: Box::new(query),
+        args,
+        bind,
+        span: Span::default(),
+    })
+}
+
+/// The options of a join as older encoders wrote them: `"bind": [{"bind":
+/// [...], "args": [...]}]`.
+fn legacy_join_opts(bind: &JSONValue) -> Option<&Map<String, JSONValue>> {
+    match bind.as_array()?.as_slice() {
+        [JSONValue::Object(opts)]
+            if !opts.is_empty()
+                && opts.iter().all(|(key, value)| {
+                    matches!(key.as_str(), "bind" | "args") && value.is_array()
+                }) =>
+        {
+            Some(opts)
+        }
+        _ => None,
+    }
+}
+
+fn join_opts(opts: &Map<String, JSONValue>) -> Result<(Vec<BindSpec>, Vec<Binding>)> {
+    Ok((
+        optional(opts, "bind", bind_specs)?.unwrap_or_default(),
+        optional(opts, "args", args)?.unwrap_or_default(),
+    ))
+}
+
+/// `:args` specs: `"p"` for `p => p`, or objects from names to expressions.
+fn args(value: &JSONValue) -> Result<Vec<Binding>> {
+    let specs = array(value, "an array of arguments")?;
+    if specs.is_empty() {
+        return Err(JsonError::new("expected at least one argument"));
+    }
+    let specs = each(specs, |spec| match spec {
+        JSONValue::String(name) => {
+            let name = symbol(name, "logic variable")?;
+            Ok(vec![Binding::new(&name, Expr::var(&name))])
+        }
+        JSONValue::Object(map) => entries(map, "argument name"),
+        other => Err(expected(
+            "an argument: a logic variable name, or an object from names to expressions",
+            other,
+        )),
+    })?;
+    Ok(specs.into_iter().flatten().collect())
+}
+
+fn entries(map: &Map<String, JSONValue>, what: &str) -> Result<Vec<Binding>> {
+    map.iter()
+        .map(|(name, value)| {
+            let name = keyword(name, what).map_err(|err| err.key(name))?;
+            let expr = expr(value).map_err(|err| err.key(&name))?;
+            Ok(Binding::new(&name, expr))
+        })
+        .collect()
+}
+
+/// `[{"name": expr, ...}, ...]`, as `with` and `return` take them.
+fn bindings(value: &JSONValue, what: &str) -> Result<Vec<Binding>> {
+    let specs = array(value, "an array of bindings")?;
+    let specs = each(specs, |spec| {
+        entries(
+            object(spec, "a binding: an object from names to expressions")?,
+            what,
+        )
+    })?;
+    Ok(specs.into_iter().flatten().collect())
+}
+
+fn unnest(value: &JSONValue, what: &str) -> Result<Unnest> {
+    let map = object(value, "an object binding one name")?;
+    match entries(map, what)?.as_slice() {
+        [binding] => Ok(Unnest {
+            binding: binding.clone(),
+            span: Span::default(),
+        }),
+        _ => Err(JsonError::new(format!(
+            "expected exactly one binding, found {}",
+            map.len()
+        ))),
+    }
+}
+
+fn exprs(value: &JSONValue) -> Result<Vec<Expr>> {
+    each(array(value, "an array of expressions")?, expr)
+}
+
+fn tail_op(value: &JSONValue) -> Result<TailOp> {
+    let map = object(value, "a tail operator")?;
+    let Some(op) = TAIL_OPS.iter().find(|op| map.contains_key(**op)) else {
+        let message = match SOURCE_OPS.iter().find(|op| map.contains_key(**op)) {
+            Some(op) => format!("`{}` can only start a query", op),
+            None => format!("expected a tail operator: {}", one_of(TAIL_OPS)),
+        };
+        return Err(JsonError::new(message));
+    };
+    only(map, &[op], "a tail operator")?;
+    let value = &map[*op];
+    let span = Span::default();
+    let tail_op = match *op {
+        "aggregate" => {
+            aggregate(value).map(|bindings| TailOp::Aggregate(Aggregate { bindings, span }))
+        }
+        "limit" => limit(value).map(|value| TailOp::Limit(Limit { value, span })),
+        "offset" => limit(value).map(|value| TailOp::Offset(Limit { value, span })),
+        "orderBy" => order_by(value).map(|specs| TailOp::OrderBy(OrderBy { specs, span })),
+        "return" => {
+            bindings(value, "column name").map(|bindings| TailOp::Return(Return { bindings, span }))
+        }
+        "where" => exprs(value).map(|exprs| TailOp::Where(Where { exprs, span })),
+        "with" => {
+            bindings(value, "column name").map(|bindings| TailOp::With(With { bindings, span }))
+        }
+        "without" => without(value).map(|columns| TailOp::Without(Without { columns, span })),
+        _ => unnest(value, "column name").map(TailOp::Unnest),
+    };
+    tail_op.map_err(|err| err.key(op))
+}
+
+/// Grouping variables are written `{"xt:lvar": "x"}`, aggregates as
+/// objects from names to expressions.
+fn aggregate(value: &JSONValue) -> Result<Vec<Binding>> {
+    let specs = array(value, "an array of grouping variables and aggregates")?;
+    let specs = each(specs, |spec| {
+        let map = object(spec, "a grouping variable or an aggregate")?;
+        match map.get("xt:lvar") {
+            Some(var) if map.len() == 1 => {
+                let name = symbol(string(var, "a logic variable name")?, "logic variable")
+                    .map_err(|err| err.key("xt:lvar"))?;
+                Ok(vec![Binding::new(&name, Expr::var(&name))])
+            }
+            _ => entries(map, "column name"),
+        }
+    })?;
+    Ok(specs.into_iter().flatten().collect())
+}
+
+fn limit(value: &JSONValue) -> Result<u64> {
+    value
+        .as_u64()
+        .ok_or_else(|| expected("a non-negative integer", value))
+}
+
+fn order_by(value: &JSONValue) -> Result<Vec<OrderSpec>> {
+    let specs = array(value, "an array of order specs")?;
+    if specs.is_empty() {
+        return Err(JsonError::new("expected at least one order spec"));
+    }
+    each(specs, order_spec)
+}
+
+fn order_spec(value: &JSONValue) -> Result<OrderSpec> {
+    let mut spec = OrderSpec {
+        val: None,
+        dir: None,
+        nulls: None,
+        span: Span::default(),
+    };
+    match value {
+        JSONValue::String(var) => {
+            spec.val = Some(Expr::var(&symbol(var, "logic variable")?));
+        }
+        JSONValue::Object(map) => {
+            only(map, &["val", "dir", "nulls"], "an order spec")?;
+            spec.val = optional(map, "val", expr)?;
+            spec.dir = optional(map, "dir", |dir| match dir.as_str() {
+                Some("asc") => Ok(Direction::Asc),
+                Some("desc") => Ok(Direction::Desc),
+                _ => Err(expected("`asc` or `desc`", dir)),
+            })?;
+            spec.nulls = optional(map, "nulls", |nulls| match nulls.as_str() {
+                Some("first") => Ok(NullOrdering::First),
+                Some("last") => Ok(NullOrdering::Last),
+                _ => Err(expected("`first` or `last`", nulls)),
+            })?;
+        }
+        other => {
+            return Err(expected(
+                "an order spec: a logic variable name, or an object with `val`, `dir` and `nulls`",
+                other,
+            ))
+        }
+    }
+    Ok(spec)
+}
+
+fn without(value: &JSONValue) -> Result<Vec<Ident>> {
+    each(array(value, "an array of column names")?, |column| {
+        Ok(Ident {
+            name: keyword(string(column, "a column name")?, "column name")?,
+            span: Span::default(),
+        })
+    })
+}
+
+fn expr(value: &JSONValue) -> Result<Expr> {
+    let kind = match value {
+        JSONValue::Null => ExprKind::Nil,
+        JSONValue::Bool(b) => ExprKind::Bool(*b),
+        JSONValue::Number(n) => match n.as_i64() {
+            Some(i) => ExprKind::Int(i),
+            None => ExprKind::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JSONValue::String(s) => ExprKind::String(escape(s)),
+        JSONValue::Array(items) => ExprKind::Vector(each(items, expr)?),
+        JSONValue::Object(map) => return object_expr(map),
+    };
+    Ok(Expr::new(kind))
+}
+
+fn object_expr(map: &Map<String, JSONValue>) -> Result<Expr> {
+    if map.contains_key("xt:lvar") {
+        only(map, &["xt:lvar"], "a logic variable")?;
+        return field(map, "xt:lvar", "a logic variable", |name| {
+            Ok(Expr::var(&symbol(
+                string(name, "a logic variable name")?,
+                "logic variable",
+            )?))
+        });
+    }
+    if map.contains_key("xt:param") {
+        only(map, &["xt:param"], "a parameter")?;
+        return field(map, "xt:param", "a parameter", param);
+    }
+    if map.contains_key("xt:call") {
+        only(map, &["xt:call", "args"], "a call")?;
+        let function = field(map, "xt:call", "a call", |function| {
+            symbol(string(function, "a function name")?, "function name")
+        })?;
+        let args = optional(map, "args", exprs)?.unwrap_or_default();
+        return Ok(Expr::call(&function, args));
+    }
+    if map.contains_key("xt:get") {
+        only(map, &["xt:get", "field"], "a field access")?;
+        let expr = field(map, "xt:get", "a field access", expr)?;
+        let name = field(map, "field", "a field access", |name| {
+            symbol(string(name, "a field name")?, "field name")
+        })?;
+        return Ok(Expr::new(ExprKind::GetField {
+            expr: Box::new(expr),
+            field: name,
+        }));
+    }
+    if let Some((op, kind)) = SUBQUERIES.iter().find(|(op, _)| map.contains_key(*op)) {
+        return subquery(map, op, *kind);
+    }
+    if map.contains_key("@type") {
+        return typed(map);
+    }
+    if let Some(param) = legacy_param(map) {
+        return Ok(param);
+    }
+    // Keys like `xt:lvar` cannot be columns, so a misspelt one is a mistake
+    // rather than a map literal.
+    if let Some(key) = map
+        .keys()
+        .find(|key| key.starts_with("xt:") || key.starts_with('@'))
+    {
+        return Err(unknown_key(key, EXPR_KEYS, "an expression"));
+    }
+    Ok(Expr::new(ExprKind::Map(entries(map, "map key")?)))
+}
+
+fn param(value: &JSONValue) -> Result<Expr> {
+    let name = string(value, "a parameter name")?;
+    Ok(Expr::param(&symbol(
+        name.trim_start_matches('$'),
+        "parameter name",
+    )?))
+}
+
+/// `{"p": {"xt:param": "p"}}`, as older encoders wrote `$p`.
+fn legacy_param(map: &Map<String, JSONValue>) -> Option<Expr> {
+    let (name, value) = map.iter().next().filter(|_| map.len() == 1)?;
+    let inner = value.as_object().filter(|inner| inner.len() == 1)?;
+    match inner.get("xt:param")?.as_str()? {
+        param if param == name && is_identifier(param) => Some(Expr::param(param)),
+        _ => None,
+    }
+}
+
+fn subquery(map: &Map<String, JSONValue>, op: &str, kind: SubqueryKind) -> Result<Expr> {
+    let what = format!("`{}`", op);
+    only(map, &[op, "args", "bind"], &what)?;
+    let query = field(map, op, &what, query)?;
+    let mut args = optional(map, "args", self::args)?.unwrap_or_default();
+    // Older encoders wrote `{:args [...]}` as `"bind": [[...]]`, and no
+    // arguments as `"bind": []`.
+    let legacy = optional(map, "bind", |bind| {
+        match array(bind, "an array holding an array of arguments")?.as_slice() {
+            [] => Ok(vec![]),
+            [specs] => self::args(specs).map_err(|err| err.index(0)),
+            _ => Err(expected("an array holding an array of arguments", bind)),
+        }
+    })?;
+    args.extend(legacy.unwrap_or_default());
+    Ok(Expr::new(ExprKind::Subquery(Subquery {
+        kind,
+        query: Box::new(query),
+        args,
+    })))
+}
+
+/// The EDN reader tag for each of XTDB's typed JSON values.
+const TAGS: &[(&str, &str)] = &[
+    (value::INSTANT, "inst"),
+    (value::DATE, "time/date"),
+    (value::DATE_TIME, "time/date-time"),
+    (value::ZONED_DATE_TIME, "time/zoned-date-time"),
+    (value::DURATION, "time/duration"),
+    (value::PERIOD, "time/period"),
+    (value::TIME, "time/time"),
+    (value::TIME_ZONE, "time/zone"),
+    (value::UUID, "uuid"),
+    (value::DECIMAL, "bigdec"),
+];
+
+/// `{"@type": ..., "@value": ...}`: a set, a typed scalar, or a literal with
+/// a reader tag XTDB does not know.
+fn typed(map: &Map<String, JSONValue>) -> Result<Expr> {
+    let what = "a typed value";
+    only(map, &["@type", "@value"], what)?;
+    let tag = field(map, "@type", what, |tag| string(tag, "a type name"))?;
+    if tag == value::SET {
+        let items = field(map, "@value", what, exprs)?;
+        return Ok(Expr::new(ExprKind::Set(items)));
+    }
+    if let Some((_, edn_tag)) = TAGS.iter().find(|(json_tag, _)| *json_tag == tag) {
+        let value = field(map, "@value", what, |value| string(value, "a string"))?;
+        return Ok(Expr::tagged(edn_tag, Expr::string(&escape(value))));
+    }
+    if tag == value::KEYWORD || tag.starts_with("xt:") {
+        let message = format!("type `{}` cannot be used in a query", tag);
+        return Err(JsonError::new(message).key("@type"));
+    }
+    let tag = symbol(tag, "reader tag").map_err(|err| err.key("@type"))?;
+    let value = field(map, "@value", what, expr)?;
+    Ok(Expr::tagged(&tag, value))
+}
+
+/// Escapes `s` as the contents of an EDN string literal.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+    use serde_json::json;
+
+    /// Decodes the JSON encoding of `text` and prints it back as XTQL.
+    fn round_trip(text: &str) -> String {
+        let query = parse_query(text).unwrap();
+        query_from_json(&query.to_json().unwrap())
+            .unwrap()
+            .to_string()
+    }
+
+    fn error(value: JSONValue) -> String {
+        query_from_json(&value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn round_trips_the_ast_encoding() {
+        for text in [
+            "(from :t {:bind [x] :for-valid-time :all-time :for-system-time (at $t)})",
+            "(from :t {:bind [x t] :for-valid-time (in #inst \"2020-01-01T00:00:00Z\" t)})",
+            "(-> (from :t [x]) (return {:y (. x field)}))",
+            "(-> (rel $rows [x]) (where (exists? (from :u [{:x $x}]) {:args [x]})))",
+            "(-> (unify (from :t [x]) (left-join (from :u [x y]) [x y])) (order-by {:val y :dir :desc}))",
+        ] {
+            assert_eq!(round_trip(text), parse_query(text).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn round_trips_tpch() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/q-tpch");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let printed = parse_query(&text).unwrap().to_string();
+            assert_eq!(round_trip(&text), printed, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn decodes_temporal_filters() {
+        let query = query_from_json(&json!({
+            "from": "t",
+            "bind": [{"x": {"xt:lvar": "x"}}],
+            "forValidTime": "allTime",
+            "forSystemTime": {"to": {"xt:param": "$t"}}
+        }))
+        .unwrap();
+        assert_eq!(
+            query.to_string(),
+            "(from :t {:bind [x] :for-valid-time :all-time :for-system-time (to $t)})"
+        );
+        assert_eq!(
+            error(json!({"from": "t", "bind": [{"x": 1}], "forValidTime": "always"})),
+            "$.forValidTime: expected a temporal filter: `\"allTime\"`, or an object \
+             with `at`, `from`, `to` or `in`, found the string \"always\""
+        );
+        assert_eq!(
+            error(json!({"from": "t", "bind": [{"x": 1}], "forValidTime": {"at": 1}})),
+            "$.forValidTime.at: expected a timestamp: a string, a typed value, \
+             a parameter, a logic variable or null, found the number 1"
+        );
+    }
+
+    #[test]
+    fn round_trips_open_in_ranges() {
+        let text = "(from :t {:bind [x] :for-valid-time (in #inst \"2020-01-01\" nil)})";
+        let encoded = parse_query(text).unwrap().to_json().unwrap();
+        assert_eq!(
+            encoded["forValidTime"],
+            json!({"in": [{"@type": "xt:instant", "@value": "2020-01-01"}, null]})
+        );
+        assert_eq!(query_from_json(&encoded).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn decodes_legacy_encodings() {
+        let query = query_from_json(&json!({"unify": [
+            {"from": "t", "bind": [{"x": {"xt:lvar": "x"}, "p": {"p": {"xt:param": "p"}}}]},
+            {"join": {"from": "u", "bind": [{"x": {"xt:lvar": "x"}}]},
+             "bind": [{"args": ["x"], "bind": [{"x": {"xt:lvar": "x"}}]}]},
+            {"where": [{"xt:exists": {"from": "v", "bind": [{"x": {"xt:lvar": "x"}}]},
+                        "bind": [["x"]]}]}
+        ]}))
+        .unwrap();
+        assert_eq!(
+            query.to_string(),
+            "(unify (from :t [{:p $p} x]) (join (from :u [x]) {:bind [x] :args [x]}) \
+             (where (exists? (from :v [x]) {:args [x]})))"
+        );
+    }
+
+    #[test]
+    fn reports_the_path_of_errors() {
+        assert_eq!(
+            error(json!([{"from": "t", "bind": [{"x": 1}]}, {"limt": 1}])),
+            "$[1]: expected a tail operator: `aggregate`, `limit`, `offset`, `orderBy`, \
+             `return`, `where`, `with`, `without`, `unnest`"
+        );
+        assert_eq!(
+            error(json!({"unify": [{"where": [{"xt:get": {"xt:lvar": "x"}, "field": "1a"}]}]})),
+            "$.unify[0].where[0].field: `1a` is not a valid field name"
+        );
+        assert_eq!(
+            error(json!({"from": "t", "bind": []})),
+            "$.bind: expected at least one bind spec"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Normalisation: rewrites a query into a simpler equivalent, so that queries
+//! generated in different ways but meaning the same thing print the same.
+//!
+//! - Arithmetic and comparisons on literals are folded: `(+ 1 2)` becomes `3`
+//!   and `(< 1 2)` becomes `true`. Integer division is only folded when exact,
+//!   and nothing is folded if it would overflow.
+//! - `and` and `or` are flattened, `(and (and a b) c)` becoming `(and a b c)`,
+//!   and literal `true`/`false` arguments are absorbed.
+//! - Literal `true` conditions are dropped from `where`, and adjacent `where`
+//!   tail operators are merged.
+//! - `with` bindings of a variable to itself are dropped, as are duplicate
+//!   and empty `without`s; adjacent `without`s are merged.
+
+use crate::ast::*;
+use crate::visit::{fold_expr, fold_query, fold_unify, Fold};
+use std::collections::BTreeSet;
+
+/// Returns the normal form of `query`.
+pub fn normalise_query(query: &Query) -> Query {
+    Normaliser.fold_query(query.clone())
+}
+
+struct Normaliser;
+
+impl Fold for Normaliser {
+    fn fold_query(&mut self, query: Query) -> Query {
+        let query = fold_query(self, query);
+        Query {
+            tail: tail(query.tail),
+            ..query
+        }
+    }
+
+    fn fold_unify(&mut self, unify: Unify) -> Unify {
+        let mut unify = fold_unify(self, unify);
+        for clause in &mut unify.clauses {
+            if let UnifyClause::Where(w) = clause {
+                w.exprs.retain(|expr| !is_bool(expr, true));
+            }
+        }
+        if unify.clauses.len() > 1 {
+            unify
+                .clauses
+                .retain(|clause| !matches!(clause, UnifyClause::Where(w) if w.exprs.is_empty()));
+        }
+        unify
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let expr = fold_expr(self, expr);
+        match expr.kind {
+            ExprKind::Call { function, args } => {
+                let span = expr.span;
+                let mut expr = call(function, args);
+                expr.span = span;
+                expr
+            }
+            _ => expr,
+        }
+    }
+}
+
+fn tail(ops: Vec<TailOp>) -> Vec<TailOp> {
+    let mut tail: Vec<TailOp> = vec![];
+    for op in ops {
+        match op {
+            TailOp::Where(mut w) => {
+                w.exprs.retain(|expr| !is_bool(expr, true));
+                if w.exprs.is_empty() {
+                    continue;
+                }
+                if let Some(TailOp::Where(previous)) = tail.last_mut() {
+                    previous.exprs.append(&mut w.exprs);
+                } else {
+                    tail.push(TailOp::Where(w));
+                }
+            }
+            TailOp::With(mut with) => {
+                with.bindings.retain(|binding| !binding.is_var());
+                if !with.bindings.is_empty() {
+                    tail.push(TailOp::With(with));
+                }
+            }
+            TailOp::Without(mut without) => {
+                if let Some(TailOp::Without(previous)) = tail.last_mut() {
+                    previous.columns.append(&mut without.columns);
+                } else {
+                    tail.push(TailOp::Without(without));
+                }
+                if let Some(TailOp::Without(without)) = tail.last_mut() {
+                    let mut seen = BTreeSet::new();
+                    without
+                        .columns
+                        .retain(|column| seen.insert(column.name.clone()));
+                    if without.columns.is_empty() {
+                        tail.pop();
+                    }
+                }
+            }
+            op => tail.push(op),
+        }
+    }
+    tail
+}
+
+/// Simplifies a call whose arguments are already normalised.
+fn call(function: String, args: Vec<Expr>) -> Expr {
+    let folded = match function.as_str() {
+        "and" | "or" => return connective(function, args),
+        "not" => match args.as_slice() {
+            [Expr {
+                kind: ExprKind::Bool(b),
+                ..
+            }] => Some(ExprKind::Bool(!b)),
+            _ => None,
+        },
+        "+" | "-" | "*" | "/" => arithmetic(&function, &args),
+        "=" | "<>" | "<" | ">" | "<=" | ">=" => comparison(&function, &args),
+        _ => None,
+    };
+    match folded {
+        Some(kind) => Expr::new(kind),
+        None => Expr::new(ExprKind::Call { function, args }),
+    }
+}
+
+/// Flattens nested `and`s (or `or`s) and absorbs literal booleans.
+fn connective(function: String, args: Vec<Expr>) -> Expr {
+    // `true` for `and`, `false` for `or`: the value that leaves the result unchanged.
+    let identity = function == "and";
+    let mut flat = vec![];
+    for arg in args {
+        match arg.kind {
+            ExprKind::Call {
+                function: inner,
+                args,
+            } if inner == function => flat.extend(args),
+            _ => flat.push(arg),
+        }
+    }
+    if flat.iter().any(|arg| is_bool(arg, !identity)) {
+        return Expr::new(ExprKind::Bool(!identity));
+    }
+    flat.retain(|arg| !is_bool(arg, identity));
+    match flat.len() {
+        0 => Expr::new(ExprKind::Bool(identity)),
+        1 => flat.pop().unwrap(),
+        _ => Expr::new(ExprKind::Call {
+            function,
+            args: flat,
+        }),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn of(expr: &Expr) -> Option<Number> {
+        match expr.kind {
+            ExprKind::Int(i) => Some(Number::Int(i)),
+            ExprKind::Float(f) => Some(Number::Float(f)),
+            _ => None,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn apply(self, op: &str, other: Number) -> Option<Number> {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            return match op {
+                "+" => a.checked_add(b),
+                "-" => a.checked_sub(b),
+                "*" => a.checked_mul(b),
+                _ if b != 0 && a.checked_rem(b) == Some(0) => a.checked_div(b),
+                _ => None,
+            }
+            .map(Number::Int);
+        }
+        let (a, b) = (self.as_f64(), other.as_f64());
+        let result = match op {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            _ if b != 0.0 => a / b,
+            _ => return None,
+        };
+        result.is_finite().then_some(Number::Float(result))
+    }
+
+    fn into_kind(self) -> ExprKind {
+        match self {
+            Number::Int(i) => ExprKind::Int(i),
+            Number::Float(f) => ExprKind::Float(f),
+        }
+    }
+}
+
+fn arithmetic(op: &str, args: &[Expr]) -> Option<ExprKind> {
+    let numbers = args.iter().map(Number::of).collect::<Option<Vec<_>>>()?;
+    let result = match (op, numbers.as_slice()) {
+        ("-", [n]) => Number::Int(0).apply("-", *n)?,
+        (_, [first, rest @ ..]) if !rest.is_empty() => {
+            rest.iter().try_fold(*first, |acc, n| acc.apply(op, *n))?
+        }
+        _ => return None,
+    };
+    Some(result.into_kind())
+}
+
+fn comparison(op: &str, args: &[Expr]) -> Option<ExprKind> {
+    let [a, b] = args else {
+        return None;
+    };
+    let ordering = match (&a.kind, &b.kind) {
+        (ExprKind::String(a), ExprKind::String(b)) if !a.contains('\\') && !b.contains('\\') => {
+            a.cmp(b)
+        }
+        (ExprKind::Bool(a), ExprKind::Bool(b)) if matches!(op, "=" | "<>") => a.cmp(b),
+        (ExprKind::Int(a), ExprKind::Int(b)) => a.cmp(b),
+        _ => Number::of(a)?
+            .as_f64()
+            .partial_cmp(&Number::of(b)?.as_f64())?,
+    };
+    let result = match op {
+        "=" => ordering.is_eq(),
+        "<>" => ordering.is_ne(),
+        "<" => ordering.is_lt(),
+        ">" => ordering.is_gt(),
+        "<=" => ordering.is_le(),
+        _ => ordering.is_ge(),
+    };
+    Some(ExprKind::Bool(result))
+}
+
+fn is_bool(expr: &Expr, value: bool) -> bool {
+    matches!(expr.kind, ExprKind::Bool(b) if b == value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    fn normalised(query: &str) -> String {
+        normalise_query(&parse_query(query).unwrap()).to_string()
+    }
+
+    #[test]
+    fn merges_withouts() {
+        assert_eq!(
+            normalised("(-> (from :t [x y z]) (without :y) (without :z :y))"),
+            "(-> (from :t [x y z]) (without :y :z))"
+        );
+        assert_eq!(
+            normalised("(-> (from :t [x y]) (without) (limit 1))"),
+            "(-> (from :t [x y]) (limit 1))"
+        );
+        assert_eq!(
+            normalised("(-> (from :t [x y]) (without :y) (limit 1) (without :x))"),
+            "(-> (from :t [x y]) (without :y) (limit 1) (without :x))"
+        );
+    }
+
+    #[test]
+    fn merges_wheres() {
+        assert_eq!(
+            normalised("(-> (from :t [x]) (where (> x 1)) (where (< x 5) (= 1 1)))"),
+            "(-> (from :t [x]) (where (> x 1) (< x 5)))"
+        );
+        assert_eq!(
+            normalised("(unify (from :t [x]) (where (< 1 2)))"),
+            "(unify (from :t [x]))"
+        );
+    }
+
+    #[test]
+    fn drops_identity_withs() {
+        assert_eq!(
+            normalised("(-> (from :t [x]) (with x {:y (+ 1 2)}))"),
+            "(-> (from :t [x]) (with {:y 3}))"
+        );
+        assert_eq!(
+            normalised("(-> (from :t [x]) (with x))"),
+            "(-> (from :t [x]))"
+        );
+    }
+
+    #[test]
+    fn folds_literals() {
+        assert_eq!(
+            normalised(
+                "(-> (from :t [x]) (where (and (and (> x 1) true) (or false (< x (* 2 3))))))"
+            ),
+            "(-> (from :t [x]) (where (and (> x 1) (< x 6))))"
+        );
+        assert_eq!(
+            normalised("(-> (from :t [x]) (return {:a (- 3) :b (/ 6 3) :c (/ 1.0 4)}))"),
+            "(-> (from :t [x]) (return {:a -3} {:b 2} {:c 0.25}))"
+        );
+    }
+
+    #[test]
+    fn leaves_overflow_unfolded() {
+        assert_eq!(
+            normalised(
+                "(-> (from :t [x]) \
+                 (return {:a (+ 9223372036854775807 1) :b (- -9223372036854775808)}))"
+            ),
+            "(-> (from :t [x]) \
+             (return {:a (+ 9223372036854775807 1)} {:b (- -9223372036854775808)}))"
+        );
+        assert_eq!(
+            normalised("(-> (from :t [x]) (return {:a (* 1.0e308 10)}))"),
+            "(-> (from :t [x]) (return {:a (* 1.0e308 10)}))"
+        );
+    }
+
+    #[test]
+    fn leaves_division_by_zero_unfolded() {
+        assert_eq!(
+            normalised("(-> (from :t [x]) (return {:a (/ 1 0) :b (/ 1.0 0) :c (/ 7 2)}))"),
+            "(-> (from :t [x]) (return {:a (/ 1 0)} {:b (/ 1.0 0)} {:c (/ 7 2)}))"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use serde_json::Value as JSONValue;
+use std::collections::BTreeSet;
+
+/// Keys under which the encoder places subqueries that take `:args`.
+const SUBQUERY_KEYS: [&str; 6] = [
+    "xt:q",
+    "xt:exists",
+    "xt:pull",
+    "xt:pullMany",
+    "join",
+    "leftJoin",
+];
+
+/// Collects the names (without `$`) of the parameters an encoded query
+/// expects its caller to supply. Parameters a subquery receives through its
+/// own `:args` are bound by the enclosing query and therefore not included.
+pub fn collect_params(query: &JSONValue) -> BTreeSet<String> {
+    let mut params = BTreeSet::new();
+    collect(query, &mut params);
+    params
+}
+
+fn collect(value: &JSONValue, params: &mut BTreeSet<String>) {
+    match value {
+        JSONValue::Object(map) => {
+            if let Some(JSONValue::String(param)) = map.get("xt:param") {
+                params.insert(param.trim_start_matches('$').to_string());
+                return;
+            }
+            let subquery = SUBQUERY_KEYS.iter().find_map(|key| map.get(*key));
+            match (subquery, map.get("args")) {
+                (Some(subquery), Some(args)) => {
+                    let mut inner = BTreeSet::new();
+                    collect(subquery, &mut inner);
+                    for bound in arg_names(args) {
+                        inner.remove(&bound);
+                    }
+                    params.extend(inner);
+                    collect(args, params);
+                    for (key, value) in map {
+                        if key != "args" && !SUBQUERY_KEYS.contains(&key.as_str()) {
+                            collect(value, params);
+                        }
+                    }
+                }
+                _ => map.values().for_each(|value| collect(value, params)),
+            }
+        }
+        JSONValue::Array(values) => values.iter().for_each(|value| collect(value, params)),
+        _ => {}
+    }
+}
+
+/// Names introduced by an `:args` vector: bare symbols and the keys of maps.
+fn arg_names(args: &JSONValue) -> Vec<String> {
+    match args {
+        JSONValue::Array(specs) => specs
+            .iter()
+            .flat_map(|spec| match spec {
+                JSONValue::String(name) => vec![name.clone()],
+                JSONValue::Object(map) => map.keys().cloned().collect(),
+                _ => vec![],
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
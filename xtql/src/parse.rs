@@ -0,0 +1,799 @@
+//! Builds the typed [`ast`](crate::ast) from the pest parse tree.
+
+use crate::ast::*;
+use crate::{Error, Rule, XTQLParser};
+use pest::error::{Error as PestError, ErrorVariant};
+use pest::iterators::{Pair, Pairs};
+use pest::Parser;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Parses a query into its typed AST.
+pub fn parse_query(content: &str) -> Result<Query> {
+    let pair = XTQLParser::parse(Rule::Query, content)?.next().unwrap();
+    query(pair)
+}
+
+/// Parses every query and DML statement in `content`, in order. A form is
+/// named by the `:name` of a `^{...}` metadata map preceding it or, failing
+/// that, by a `;; name: ...` comment line between it and the previous form.
+///
+/// ```
+/// use xtql::ast::Statement;
+/// use xtql::parse_forms;
+///
+/// let forms = parse_forms(
+///     ";; name: active-users
+///      (from :users [{:active true} name])
+///
+///      ^{:name \"deactivate\"}
+///      (update :users {:bind [{:xt/id $id}] :set {:active false}})",
+/// )
+/// .unwrap();
+/// assert_eq!(forms[0].name.as_deref(), Some("active-users"));
+/// assert!(matches!(forms[1].statement, Statement::Dml(_)));
+/// assert_eq!(forms[1].name.as_deref(), Some("deactivate"));
+/// ```
+pub fn parse_forms(content: &str) -> Result<Vec<Form>> {
+    let forms = XTQLParser::parse(Rule::Forms, content)?.next().unwrap();
+    let mut previous_end = 0;
+    let mut result = vec![];
+    for pair in forms.into_inner() {
+        if pair.as_rule() != Rule::Form {
+            continue;
+        }
+        let form_span = span(&pair);
+        let annotated = annotated_name(&content[previous_end..form_span.start]);
+        previous_end = form_span.end;
+        let mut inner = pair.into_inner().peekable();
+        let metadata = match inner.peek().map(Pair::as_rule) {
+            Some(Rule::Metadata) => {
+                let map = inner.next().unwrap().into_inner().next().unwrap();
+                match expr(map)?.kind {
+                    ExprKind::Map(entries) => entries,
+                    _ => unreachable!("metadata is a map"),
+                }
+            }
+            _ => vec![],
+        };
+        let name = metadata
+            .iter()
+            .find(|entry| entry.name == "name")
+            .and_then(|entry| match &entry.expr.kind {
+                ExprKind::String(name) | ExprKind::Var(name) => Some(name.clone()),
+                _ => None,
+            })
+            .or(annotated);
+        result.push(Form {
+            name,
+            metadata,
+            statement: statement(inner.next().unwrap())?,
+            span: form_span,
+        });
+    }
+    Ok(result)
+}
+
+/// The name given by the last `;; name: ...` line of the text between forms.
+fn annotated_name(between: &str) -> Option<String> {
+    between.lines().rev().find_map(|line| {
+        let comment = line.trim().strip_prefix(';')?.trim_start_matches(';');
+        let name = comment.trim().strip_prefix("name:")?.trim();
+        (!name.is_empty()).then(|| name.to_string())
+    })
+}
+
+fn statement(pair: Pair<Rule>) -> Result<Statement> {
+    let statement_span = span(&pair);
+    let rule = pair.as_rule();
+    let dml = match rule {
+        Rule::InsertInto => {
+            let mut inner = pair.into_inner();
+            Dml::InsertInto(InsertInto {
+                table: ident(inner.next().unwrap()),
+                query: Box::new(query(inner.next().unwrap())?),
+                span: statement_span,
+            })
+        }
+        Rule::Update => {
+            let mut inner = pair.into_inner();
+            let mut update = Update {
+                table: ident(inner.next().unwrap()),
+                bind: vec![],
+                set: vec![],
+                for_valid_time: None,
+                clauses: vec![],
+                span: statement_span,
+            };
+            for opt in inner.next().unwrap().into_inner() {
+                match opt.as_rule() {
+                    Rule::BindKV => update.bind = bind_specs(opt.into_inner().next().unwrap())?,
+                    Rule::SetKV => {
+                        let set = opt.into_inner().next().unwrap();
+                        update.set = keyed_pairs(set.into_inner(), keyword)?;
+                    }
+                    Rule::ValidTimeKV => {
+                        update.for_valid_time =
+                            Some(Box::new(temporal_filter(opt.into_inner().next().unwrap())?))
+                    }
+                    rule => unreachable!("unexpected update option {:?}", rule),
+                }
+            }
+            update.clauses = unify_clauses(inner)?;
+            Dml::Update(update)
+        }
+        Rule::DeleteFrom | Rule::EraseFrom => {
+            let mut inner = pair.into_inner();
+            let mut delete = Delete {
+                table: ident(inner.next().unwrap()),
+                bind: vec![],
+                for_valid_time: None,
+                clauses: vec![],
+                span: statement_span,
+            };
+            for opt in inner.next().unwrap().into_inner() {
+                match opt.as_rule() {
+                    Rule::BindSpecs => delete.bind = bind_specs(opt)?,
+                    Rule::BindKV => delete.bind = bind_specs(opt.into_inner().next().unwrap())?,
+                    Rule::ValidTimeKV => {
+                        delete.for_valid_time =
+                            Some(Box::new(temporal_filter(opt.into_inner().next().unwrap())?))
+                    }
+                    rule => unreachable!("unexpected delete option {:?}", rule),
+                }
+            }
+            delete.clauses = unify_clauses(inner)?;
+            if rule == Rule::DeleteFrom {
+                Dml::DeleteFrom(delete)
+            } else {
+                Dml::EraseFrom(delete)
+            }
+        }
+        Rule::AssertExists | Rule::AssertNotExists => {
+            let assert = Assert {
+                query: Box::new(query(pair.into_inner().next().unwrap())?),
+                span: statement_span,
+            };
+            if rule == Rule::AssertExists {
+                Dml::AssertExists(assert)
+            } else {
+                Dml::AssertNotExists(assert)
+            }
+        }
+        _ => return Ok(Statement::Query(query(pair)?)),
+    };
+    Ok(Statement::Dml(dml))
+}
+
+fn span(pair: &Pair<Rule>) -> Span {
+    let s = pair.as_span();
+    let (line, col) = s.start_pos().line_col();
+    Span {
+        start: s.start(),
+        end: s.end(),
+        line,
+        col,
+    }
+}
+
+pub(crate) fn invalid(pair: &Pair<Rule>, message: String) -> Error {
+    PestError::new_from_span(ErrorVariant::CustomError { message }, pair.as_span()).into()
+}
+
+/// The name of a keyword-like pair (`Table`, `Column`, `MapKey`, `keyword`)
+/// without its leading `:`.
+fn keyword(pair: Pair<Rule>) -> String {
+    pair.as_str().trim().trim_start_matches(':').to_string()
+}
+
+fn symbol(pair: Pair<Rule>) -> String {
+    pair.as_str().trim().to_string()
+}
+
+fn ident(pair: Pair<Rule>) -> Ident {
+    Ident {
+        span: span(&pair),
+        name: keyword(pair),
+    }
+}
+
+pub(crate) fn query(pair: Pair<Rule>) -> Result<Query> {
+    let query_span = span(&pair);
+    if pair.as_rule() != Rule::Pipeline {
+        return Ok(Query {
+            source: source(pair)?,
+            tail: vec![],
+            pipeline: false,
+            span: query_span,
+        });
+    }
+    let mut inner = pair.into_inner();
+    let source = source(inner.next().unwrap())?;
+    let tail = inner.map(tail_op).collect::<Result<_>>()?;
+    Ok(Query {
+        source,
+        tail,
+        pipeline: true,
+        span: query_span,
+    })
+}
+
+fn source(pair: Pair<Rule>) -> Result<Source> {
+    match pair.as_rule() {
+        Rule::From => Ok(Source::From(from(pair)?)),
+        Rule::Rel => Ok(Source::Rel(rel(pair)?)),
+        Rule::Unify => Ok(Source::Unify(unify(pair)?)),
+        rule => unreachable!("unexpected source operator {:?}", rule),
+    }
+}
+
+fn from(pair: Pair<Rule>) -> Result<From> {
+    let from_span = span(&pair);
+    let mut inner = pair.into_inner();
+    let table = ident(inner.next().unwrap());
+    let opts = inner.next().unwrap().into_inner().next().unwrap();
+    let mut from = From {
+        table,
+        bind: vec![],
+        for_valid_time: None,
+        for_system_time: None,
+        span: from_span,
+    };
+    for opt in opts.into_inner() {
+        match opt.as_rule() {
+            Rule::BindSpecs => from.bind = bind_specs(opt)?,
+            Rule::BindKV => from.bind = bind_specs(opt.into_inner().next().unwrap())?,
+            Rule::ValidTimeKV => {
+                from.for_valid_time =
+                    Some(Box::new(temporal_filter(opt.into_inner().next().unwrap())?))
+            }
+            Rule::SystemTimeKV => {
+                from.for_system_time =
+                    Some(Box::new(temporal_filter(opt.into_inner().next().unwrap())?))
+            }
+            rule => unreachable!("unexpected from option {:?}", rule),
+        }
+    }
+    Ok(from)
+}
+
+fn temporal_filter(pair: Pair<Rule>) -> Result<TemporalFilter> {
+    let rule = pair.as_rule();
+    let mut inner = pair.into_inner();
+    let mut next = || expr(inner.next().unwrap());
+    Ok(match rule {
+        Rule::AtTempFilter => TemporalFilter::At(next()?),
+        Rule::FromTempFilter => TemporalFilter::From(next()?),
+        Rule::ToTempFilter => TemporalFilter::To(next()?),
+        Rule::InTempFilter => TemporalFilter::In(next()?, next()?),
+        Rule::AllTempFilter => TemporalFilter::AllTime,
+        rule => unreachable!("unexpected temporal filter {:?}", rule),
+    })
+}
+
+fn var_expr(pair: &Pair<Rule>) -> Expr {
+    Expr {
+        kind: ExprKind::Var(symbol(pair.clone())),
+        span: span(pair),
+    }
+}
+
+fn bind_specs(pair: Pair<Rule>) -> Result<Vec<BindSpec>> {
+    let mut specs = vec![];
+    for spec in pair.into_inner() {
+        match spec.as_rule() {
+            Rule::BindVar => {
+                let var = spec.into_inner().next().unwrap();
+                specs.push(BindSpec {
+                    column: symbol(var.clone()),
+                    expr: var_expr(&var),
+                    span: span(&var),
+                });
+            }
+            Rule::BindMap => {
+                for binding in keyed_pairs(spec.into_inner(), keyword)? {
+                    specs.push(BindSpec {
+                        column: binding.name,
+                        expr: binding.expr,
+                        span: binding.span,
+                    });
+                }
+            }
+            Rule::NamespacedBindMap => {
+                for binding in namespaced_map(spec)? {
+                    specs.push(BindSpec {
+                        column: binding.name,
+                        expr: binding.expr,
+                        span: binding.span,
+                    });
+                }
+            }
+            rule => unreachable!("unexpected bind spec {:?}", rule),
+        }
+    }
+    Ok(specs)
+}
+
+/// Alternating key/expression pairs, as found in maps and binding specs.
+fn keyed_pairs(mut pairs: Pairs<Rule>, key: fn(Pair<Rule>) -> String) -> Result<Vec<Binding>> {
+    let mut bindings = vec![];
+    while let Some(key_pair) = pairs.next() {
+        let value = pairs.next().unwrap();
+        bindings.push(Binding {
+            span: span(&key_pair),
+            name: key(key_pair),
+            expr: expr(value)?,
+        });
+    }
+    Ok(bindings)
+}
+
+fn namespaced_map(pair: Pair<Rule>) -> Result<Vec<Binding>> {
+    let mut inner = pair.into_inner();
+    let namespace = symbol(inner.next().unwrap());
+    let mut bindings = keyed_pairs(inner, keyword)?;
+    for binding in &mut bindings {
+        binding.name = format!("{}/{}", namespace, binding.name);
+    }
+    Ok(bindings)
+}
+
+fn var_binding(pair: Pair<Rule>) -> Binding {
+    let var = pair.into_inner().next().unwrap();
+    Binding {
+        name: symbol(var.clone()),
+        expr: var_expr(&var),
+        span: span(&var),
+    }
+}
+
+fn rel(pair: Pair<Rule>) -> Result<Rel> {
+    let rel_span = span(&pair);
+    let mut inner = pair.into_inner();
+    Ok(Rel {
+        expr: expr(inner.next().unwrap())?,
+        bind: bind_specs(inner.next().unwrap())?,
+        span: rel_span,
+    })
+}
+
+fn unify(pair: Pair<Rule>) -> Result<Unify> {
+    let unify_span = span(&pair);
+    Ok(Unify {
+        clauses: unify_clauses(pair.into_inner())?,
+        span: unify_span,
+    })
+}
+
+fn unify_clauses(pairs: Pairs<Rule>) -> Result<Vec<UnifyClause>> {
+    pairs
+        .map(|clause| {
+            Ok(match clause.as_rule() {
+                Rule::From => UnifyClause::From(from(clause)?),
+                Rule::Rel => UnifyClause::Rel(rel(clause)?),
+                Rule::WithUnify => UnifyClause::With(with(clause)?),
+                Rule::UnnestUnify => UnifyClause::Unnest(unnest(clause)?),
+                Rule::Where => UnifyClause::Where(where_(clause)?),
+                Rule::Join => UnifyClause::Join(join(clause)?),
+                Rule::LeftJoin => UnifyClause::LeftJoin(join(clause)?),
+                Rule::Fragment => UnifyClause::Fragment(fragment_ref(clause)?),
+                rule => unreachable!("unexpected unify clause {:?}", rule),
+            })
+        })
+        .collect()
+}
+
+fn fragment_ref(pair: Pair<Rule>) -> Result<FragmentRef> {
+    let fragment_span = span(&pair);
+    let mut inner = pair.into_inner();
+    Ok(FragmentRef {
+        name: symbol(inner.next().unwrap()),
+        args: inner.map(expr).collect::<Result<_>>()?,
+        span: fragment_span,
+    })
+}
+
+/// Parses a sequence of `deffragment` forms.
+pub(crate) fn fragment_defs(content: &str) -> Result<Vec<FragmentDef>> {
+    let defs = XTQLParser::parse(Rule::FragmentDefs, content)?
+        .next()
+        .unwrap();
+    defs.into_inner()
+        .filter(|pair| pair.as_rule() == Rule::FragmentDef)
+        .map(|pair| {
+            let def_span = span(&pair);
+            let mut inner = pair.into_inner();
+            let name = symbol(inner.next().unwrap());
+            let params = inner.next().unwrap().into_inner().map(symbol).collect();
+            Ok(FragmentDef {
+                name,
+                params,
+                clauses: unify_clauses(inner)?,
+                span: def_span,
+            })
+        })
+        .collect()
+}
+
+fn with(pair: Pair<Rule>) -> Result<With> {
+    let with_span = span(&pair);
+    let mut bindings = vec![];
+    for spec in pair.into_inner() {
+        match spec.as_rule() {
+            Rule::WithVar => bindings.push(var_binding(spec)),
+            Rule::WithTailMap => bindings.extend(keyed_pairs(spec.into_inner(), keyword)?),
+            Rule::WithUnifyMap => bindings.extend(keyed_pairs(spec.into_inner(), symbol)?),
+            rule => unreachable!("unexpected with spec {:?}", rule),
+        }
+    }
+    Ok(With {
+        bindings,
+        span: with_span,
+    })
+}
+
+fn unnest(pair: Pair<Rule>) -> Result<Unnest> {
+    let unnest_span = span(&pair);
+    let spec = pair.into_inner().next().unwrap();
+    let key = if spec.as_rule() == Rule::UnnestTailSpec {
+        keyword
+    } else {
+        symbol
+    };
+    let binding = keyed_pairs(spec.into_inner(), key)?.remove(0);
+    Ok(Unnest {
+        binding,
+        span: unnest_span,
+    })
+}
+
+fn where_(pair: Pair<Rule>) -> Result<Where> {
+    let where_span = span(&pair);
+    Ok(Where {
+        exprs: pair.into_inner().map(expr).collect::<Result<_>>()?,
+        span: where_span,
+    })
+}
+
+fn join(pair: Pair<Rule>) -> Result<Join> {
+    let join_span = span(&pair);
+    let mut inner = pair.into_inner();
+    let mut join = Join {
+        query: Box::new(query(inner.next().unwrap())?),
+        args: vec![],
+        bind: vec![],
+        span: join_span,
+    };
+    let opts = inner.next().unwrap();
+    for opt in opts.into_inner() {
+        match opt.as_rule() {
+            Rule::BindSpecs => join.bind = bind_specs(opt)?,
+            Rule::BindKV => join.bind = bind_specs(opt.into_inner().next().unwrap())?,
+            Rule::ArgsKV => join.args = arg_specs(opt.into_inner().next().unwrap())?,
+            rule => unreachable!("unexpected join option {:?}", rule),
+        }
+    }
+    Ok(join)
+}
+
+fn arg_specs(pair: Pair<Rule>) -> Result<Vec<Binding>> {
+    let mut args = vec![];
+    for spec in pair.into_inner() {
+        match spec.as_rule() {
+            Rule::symbol => args.push(Binding {
+                name: symbol(spec.clone()),
+                expr: var_expr(&spec),
+                span: span(&spec),
+            }),
+            Rule::EmptyMapExpr => {}
+            Rule::NonEmptyMapExpr => args.extend(keyed_pairs(spec.into_inner(), keyword)?),
+            Rule::NamespacedMapExpr => args.extend(namespaced_map(spec)?),
+            rule => unreachable!("unexpected arg spec {:?}", rule),
+        }
+    }
+    Ok(args)
+}
+
+fn tail_op(pair: Pair<Rule>) -> Result<TailOp> {
+    let op_span = span(&pair);
+    Ok(match pair.as_rule() {
+        Rule::Aggregate => TailOp::Aggregate(Aggregate {
+            bindings: specs(pair)?,
+            span: op_span,
+        }),
+        Rule::Return => TailOp::Return(Return {
+            bindings: specs(pair)?,
+            span: op_span,
+        }),
+        Rule::Limit => TailOp::Limit(limit(pair)?),
+        Rule::Offset => TailOp::Offset(limit(pair)?),
+        Rule::OrderBy => TailOp::OrderBy(OrderBy {
+            specs: pair.into_inner().map(order_spec).collect::<Result<_>>()?,
+            span: op_span,
+        }),
+        Rule::Where => TailOp::Where(where_(pair)?),
+        Rule::WithTail => TailOp::With(with(pair)?),
+        Rule::Without => TailOp::Without(Without {
+            columns: pair.into_inner().map(ident).collect(),
+            span: op_span,
+        }),
+        Rule::UnnestTail => TailOp::Unnest(unnest(pair)?),
+        rule => unreachable!("unexpected tail operator {:?}", rule),
+    })
+}
+
+/// Grouping and return specs: bare symbols or `{:col expr}` maps.
+fn specs(pair: Pair<Rule>) -> Result<Vec<Binding>> {
+    let mut bindings = vec![];
+    for spec in pair.into_inner() {
+        match spec.as_rule() {
+            Rule::GroupingVar | Rule::ReturnVar => bindings.push(var_binding(spec)),
+            Rule::GroupingMap | Rule::ReturnMap => {
+                bindings.extend(keyed_pairs(spec.into_inner(), keyword)?)
+            }
+            rule => unreachable!("unexpected spec {:?}", rule),
+        }
+    }
+    Ok(bindings)
+}
+
+fn limit(pair: Pair<Rule>) -> Result<Limit> {
+    let limit_span = span(&pair);
+    let n = pair.into_inner().next().unwrap();
+    let value = n
+        .as_str()
+        .parse()
+        .map_err(|e| invalid(&n, format!("invalid limit {}: {}", n.as_str(), e)))?;
+    Ok(Limit {
+        value,
+        span: limit_span,
+    })
+}
+
+fn order_spec(pair: Pair<Rule>) -> Result<OrderSpec> {
+    let mut spec = OrderSpec {
+        val: None,
+        dir: None,
+        nulls: None,
+        span: span(&pair),
+    };
+    if pair.as_rule() == Rule::OrderByCol {
+        spec.val = Some(var_expr(&pair.into_inner().next().unwrap()));
+        return Ok(spec);
+    }
+    for component in pair.into_inner() {
+        let rule = component.as_rule();
+        let value = component.into_inner().next().unwrap();
+        match rule {
+            Rule::OrderBySpecMapVal => spec.val = Some(expr(value)?),
+            Rule::OrderBySpecMapDir => {
+                spec.dir = Some(match value.as_str() {
+                    ":desc" => Direction::Desc,
+                    _ => Direction::Asc,
+                })
+            }
+            Rule::OrderBySpecMapNulls => {
+                spec.nulls = Some(match value.as_str() {
+                    ":first" => NullOrdering::First,
+                    _ => NullOrdering::Last,
+                })
+            }
+            rule => unreachable!("unexpected order-by component {:?}", rule),
+        }
+    }
+    Ok(spec)
+}
+
+pub(crate) fn expr(pair: Pair<Rule>) -> Result<Expr> {
+    let expr_span = span(&pair);
+    let kind = match pair.as_rule() {
+        Rule::I64 => ExprKind::Int(
+            pair.as_str()
+                .trim()
+                .parse()
+                .map_err(|e| invalid(&pair, format!("invalid integer {}: {}", pair.as_str(), e)))?,
+        ),
+        Rule::F64 => ExprKind::Float(
+            pair.as_str()
+                .parse()
+                .map_err(|e| invalid(&pair, format!("invalid float {}: {}", pair.as_str(), e)))?,
+        ),
+        Rule::String => ExprKind::String(pair.into_inner().next().unwrap().as_str().to_string()),
+        Rule::Bool => ExprKind::Bool(pair.as_str().trim() == "true"),
+        Rule::Nil => ExprKind::Nil,
+        Rule::EmptyVectorExpr | Rule::NonEmptyVectorExpr => {
+            ExprKind::Vector(pair.into_inner().map(expr).collect::<Result<_>>()?)
+        }
+        Rule::EmptyMapExpr => ExprKind::Map(vec![]),
+        Rule::NonEmptyMapExpr => ExprKind::Map(keyed_pairs(pair.into_inner(), keyword)?),
+        Rule::NamespacedMapExpr => ExprKind::Map(namespaced_map(pair)?),
+        Rule::EmptySetExpr | Rule::NonEmptySetExpr => {
+            ExprKind::Set(pair.into_inner().map(expr).collect::<Result<_>>()?)
+        }
+        Rule::ParamExpr => ExprKind::Param(symbol(pair.into_inner().next().unwrap())),
+        Rule::VariableExpr => ExprKind::Var(symbol(pair.into_inner().next().unwrap())),
+        Rule::CallExpr => {
+            let mut inner = pair.into_inner();
+            ExprKind::Call {
+                function: symbol(inner.next().unwrap()),
+                args: inner.map(expr).collect::<Result<_>>()?,
+            }
+        }
+        Rule::GetFieldExpr => {
+            let mut inner = pair.into_inner();
+            ExprKind::GetField {
+                expr: Box::new(expr(inner.next().unwrap())?),
+                field: symbol(inner.next().unwrap()),
+            }
+        }
+        Rule::SubqueryExpr | Rule::ExistsExpr | Rule::PullExpr | Rule::PullManyExpr => {
+            let kind = match pair.as_rule() {
+                Rule::SubqueryExpr => SubqueryKind::Q,
+                Rule::ExistsExpr => SubqueryKind::Exists,
+                Rule::PullExpr => SubqueryKind::Pull,
+                _ => SubqueryKind::PullMany,
+            };
+            let mut inner = pair.into_inner();
+            let query = query(inner.next().unwrap())?;
+            let args = match inner.next() {
+                Some(args) => arg_specs(args.into_inner().next().unwrap())?,
+                None => vec![],
+            };
+            ExprKind::Subquery(Subquery {
+                kind,
+                query: Box::new(query),
+                args,
+            })
+        }
+        Rule::TaggedValueExpr => {
+            let mut inner = pair.into_inner();
+            ExprKind::Tagged {
+                tag: symbol(inner.next().unwrap()),
+                value: Box::new(expr(inner.next().unwrap())?),
+            }
+        }
+        rule => unreachable!("unexpected expression {:?}", rule),
+    };
+    Ok(Expr {
+        kind,
+        span: expr_span,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every tail operator, printed the way `Display` prints it.
+    const TAIL_OPS: &[&str] = &[
+        "(aggregate x {:n (count y)})",
+        "(limit 10)",
+        "(offset 5)",
+        "(order-by x {:val y :dir :desc :nulls :last})",
+        "(return x {:z (+ x y)})",
+        "(where (> x 1))",
+        "(with {:x x :z (+ x y)})",
+        "(without :y)",
+        "(unnest {:z y})",
+    ];
+
+    #[test]
+    fn tail_ops_round_trip() {
+        for op in TAIL_OPS {
+            let text = format!("(-> (from :t [x y]) {})", op);
+            let query = parse_query(&text).unwrap();
+            assert_eq!(query.tail.len(), 1, "{}", text);
+            assert_eq!(query.to_string(), text);
+            let reparsed = parse_query(&query.to_string()).unwrap();
+            assert_eq!(reparsed.to_string(), text);
+            assert_eq!(
+                reparsed.to_json().unwrap(),
+                crate::parse_xtql(&text).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn subqueries_are_not_calls() {
+        let text = "(-> (from :t [x]) \
+                    (where (exists? (rel $rows [x])) (= x (q (rel [{:a 1}] [a]))) \
+                    (= x (pull (rel $rows [x])) (pull* (rel $rows [x])))))";
+        let query = parse_query(text).unwrap();
+        let TailOp::Where(where_) = &query.tail[0] else {
+            panic!("expected a where, got {}", query);
+        };
+        assert!(matches!(
+            &where_.exprs[0].kind,
+            ExprKind::Subquery(Subquery {
+                kind: SubqueryKind::Exists,
+                ..
+            })
+        ));
+        assert_eq!(
+            crate::parse_xtql(text).unwrap()[1]["where"]
+                .as_array()
+                .unwrap()[..2],
+            [
+                serde_json::json!({"xt:exists": {"rel": {"xt:param": "$rows"},
+                                                 "bind": [{"x": {"xt:lvar": "x"}}]}}),
+                serde_json::json!({"xt:call": "=", "args": [
+                    {"xt:lvar": "x"},
+                    {"xt:q": {"rel": [{"a": 1}], "bind": [{"a": {"xt:lvar": "a"}}]}}
+                ]}),
+            ]
+        );
+        assert_eq!(query.to_json().unwrap(), crate::parse_xtql(text).unwrap());
+    }
+
+    #[test]
+    fn numbers_are_atomic_and_signed() {
+        let args = |text: &str| {
+            let query = parse_query(&format!("(-> (from :t [x]) (where (= {})))", text)).unwrap();
+            let TailOp::Where(where_) = &query.tail[0] else {
+                panic!("expected a where, got {}", query);
+            };
+            let ExprKind::Call { args, .. } = &where_.exprs[0].kind else {
+                panic!("expected a call, got {}", where_.exprs[0]);
+            };
+            args.iter().map(|arg| arg.kind.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(args("-1 -2.5"), [ExprKind::Int(-1), ExprKind::Float(-2.5)]);
+        // Before I64 took a sign, `-1` was read as a logic variable; before
+        // F64 was atomic, `- 1.5` and `1. 5` were read as single floats.
+        assert_eq!(
+            args("- 1.5"),
+            [ExprKind::Var("-".to_string()), ExprKind::Float(1.5)]
+        );
+        assert_eq!(args("1. 5"), [ExprKind::Float(1.0), ExprKind::Int(5)]);
+    }
+
+    #[test]
+    fn encodes_subqueries_over_rel_and_unify() {
+        // With CallExpr first, each of these was encoded as nested calls, e.g.
+        // {"xt:call": "q", "args": [{"xt:call": "rel", ...}]}.
+        let rel =
+            serde_json::json!({"rel": {"xt:param": "$rows"}, "bind": [{"y": {"xt:lvar": "y"}}]});
+        for (subquery, key) in [
+            ("(q (rel $rows [y]))", "xt:q"),
+            ("(exists? (rel $rows [y]))", "xt:exists"),
+            ("(pull (rel $rows [y]))", "xt:pull"),
+            ("(pull* (rel $rows [y]))", "xt:pullMany"),
+        ] {
+            let text = format!("(-> (from :t [x]) (where {}))", subquery);
+            assert_eq!(
+                crate::parse_xtql(&text).unwrap()[1]["where"][0],
+                serde_json::json!({ key: rel }),
+                "{}",
+                subquery
+            );
+        }
+        assert_eq!(
+            crate::parse_xtql("(-> (from :t [x]) (where (exists? (unify (where (= 1 1))))))")
+                .unwrap()[1]["where"][0],
+            serde_json::json!({"xt:exists": {"unify": [
+                {"where": [{"xt:call": "=", "args": [1, 1]}]}
+            ]}})
+        );
+    }
+
+    #[test]
+    fn keywords_match_whole_words() {
+        // `with` used to match the start of `without`, reading these as
+        // `(with out)` and `(with out y)`.
+        let query = parse_query("(-> (from :t [x y]) (without))").unwrap();
+        assert!(matches!(&query.tail[0], TailOp::Without(w) if w.columns.is_empty()));
+        assert_eq!(
+            crate::parse_xtql("(-> (from :t [x y]) (without))").unwrap()[1],
+            serde_json::json!({"without": []})
+        );
+        assert!(parse_query("(-> (from :t [x y]) (without y))").is_err());
+        // `fromage` and `quot` are function calls, not `from` and `q`.
+        let query = parse_query("(-> (from :t [x]) (where (fromage x) (quot x 2)))").unwrap();
+        let TailOp::Where(where_) = &query.tail[0] else {
+            panic!("expected a where, got {}", query);
+        };
+        for expr in &where_.exprs {
+            assert!(matches!(expr.kind, ExprKind::Call { .. }), "{}", expr);
+        }
+    }
+}
@@ -0,0 +1,839 @@
+//! Guardrails for queries written by untrusted users.
+//!
+//! A [`Policy`] bounds how expensive a query may be and what it may touch.
+//! Every limit is off by default; enable the ones you need:
+//!
+//! ```
+//! use xtql::{parse_query, Policy};
+//!
+//! let policy = Policy::new()
+//!     .max_depth(2)
+//!     .require_limit(1000)
+//!     .allow_tables(["orders", "customer"]);
+//! let query = parse_query("(from :lineitem [l-orderkey])").unwrap();
+//! for violation in policy.check(&query) {
+//!     println!("{}", violation);
+//! }
+//! ```
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::schema::normalise;
+use crate::visit::{walk_expr, walk_from, walk_join, walk_query, walk_unify, Visitor};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    max_depth: Option<usize>,
+    max_joins: Option<usize>,
+    max_unify_clauses: Option<usize>,
+    max_subqueries: Option<usize>,
+    max_limit: Option<u64>,
+    tables: Option<BTreeSet<String>>,
+    functions: Option<BTreeSet<String>>,
+    forbid_all_time: bool,
+    forbid_open_ranges: bool,
+    earliest_time: Option<(String, Instant)>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Bounds how deeply queries nest. The top-level query has depth 1;
+    /// each join and subquery adds one.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Bounds the number of `join` and `left-join` clauses in the whole query.
+    pub fn max_joins(mut self, joins: usize) -> Self {
+        self.max_joins = Some(joins);
+        self
+    }
+
+    /// Bounds the number of clauses in each `unify`.
+    pub fn max_unify_clauses(mut self, clauses: usize) -> Self {
+        self.max_unify_clauses = Some(clauses);
+        self
+    }
+
+    /// Bounds the number of `q`, `exists`, `pull` and `pull*` subqueries.
+    pub fn max_subqueries(mut self, subqueries: usize) -> Self {
+        self.max_subqueries = Some(subqueries);
+        self
+    }
+
+    /// Requires the top-level query to end in a `limit` of at most `max` rows.
+    pub fn require_limit(mut self, max: u64) -> Self {
+        self.max_limit = Some(max);
+        self
+    }
+
+    /// Restricts `from` to these tables. Names are matched as XTDB matches
+    /// them, so `order-line` also allows `order_line`.
+    pub fn allow_tables<'a>(mut self, tables: impl IntoIterator<Item = &'a str>) -> Self {
+        self.tables = Some(tables.into_iter().map(normalise).collect());
+        self
+    }
+
+    /// Restricts calls, including aggregates, to these functions.
+    pub fn allow_functions<'a>(mut self, functions: impl IntoIterator<Item = &'a str>) -> Self {
+        self.functions = Some(functions.into_iter().map(str::to_string).collect());
+        self
+    }
+
+    /// Rejects `:all-time` on either time axis.
+    pub fn forbid_all_time(mut self) -> Self {
+        self.forbid_all_time = true;
+        self
+    }
+
+    /// Rejects time ranges without both bounds: `(from t)`, `(to t)`,
+    /// `:all-time` and `(in t nil)`.
+    pub fn forbid_open_ranges(mut self) -> Self {
+        self.forbid_open_ranges = true;
+        self
+    }
+
+    /// Rejects time bounds before `time`, an ISO 8601 date or timestamp
+    /// such as `"2020-01-01"` or `"2020-01-01T00:00+05:00"`, and ranges
+    /// without a lower bound. Dates are midnight and times without an offset
+    /// are UTC. Bounds given as parameters or expressions cannot be checked
+    /// before the query runs, so they are rejected too.
+    ///
+    /// # Panics
+    ///
+    /// If `time` is not an ISO 8601 date or timestamp.
+    pub fn earliest_time(mut self, time: &str) -> Self {
+        let instant = parse_instant(time)
+            .unwrap_or_else(|| panic!("earliest time {:?} is not a date or timestamp", time));
+        self.earliest_time = Some((time.to_string(), instant));
+        self
+    }
+
+    /// Returns every violation of the policy, in source order; the query is
+    /// allowed if there are none.
+    pub fn check(&self, query: &Query) -> Vec<Diagnostic> {
+        let mut checker = PolicyChecker {
+            policy: self,
+            depth: 0,
+            joins: 0,
+            subqueries: 0,
+            diagnostics: vec![],
+        };
+        checker.visit_query(query);
+        if let Some(max) = self.max_limit {
+            checker.limit(query, max);
+        }
+        checker.diagnostics.sort_by_key(|d| d.span.start);
+        checker.diagnostics
+    }
+}
+
+/// Checks `query` against `policy`; see [`Policy::check`].
+pub fn check_policy(query: &Query, policy: &Policy) -> Vec<Diagnostic> {
+    policy.check(query)
+}
+
+struct PolicyChecker<'a> {
+    policy: &'a Policy,
+    depth: usize,
+    joins: usize,
+    subqueries: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for PolicyChecker<'_> {
+    fn visit_query(&mut self, query: &Query) {
+        self.depth += 1;
+        match self.policy.max_depth {
+            Some(max) if self.depth == max + 1 => self.diagnostics.push(Diagnostic::error(
+                "nesting-too-deep",
+                query.span,
+                format!(
+                    "query is nested {} levels deep; at most {} allowed",
+                    self.depth, max
+                ),
+            )),
+            _ => {}
+        }
+        walk_query(self, query);
+        self.depth -= 1;
+    }
+
+    fn visit_unify(&mut self, unify: &Unify) {
+        if let Some(max) = self.policy.max_unify_clauses {
+            if unify.clauses.len() > max {
+                self.diagnostics.push(Diagnostic::error(
+                    "too-many-clauses",
+                    unify.span,
+                    format!(
+                        "unify has {} clauses; at most {} allowed",
+                        unify.clauses.len(),
+                        max
+                    ),
+                ));
+            }
+        }
+        walk_unify(self, unify);
+    }
+
+    fn visit_join(&mut self, join: &Join) {
+        self.joins += 1;
+        match self.policy.max_joins {
+            Some(max) if self.joins == max + 1 => self.diagnostics.push(Diagnostic::error(
+                "too-many-joins",
+                join.span,
+                format!("query has more than {} joins", max),
+            )),
+            _ => {}
+        }
+        walk_join(self, join);
+    }
+
+    fn visit_from(&mut self, from: &From) {
+        if let Some(tables) = &self.policy.tables {
+            if !tables.contains(&normalise(&from.table.name)) {
+                self.diagnostics.push(Diagnostic::error(
+                    "table-not-allowed",
+                    from.table.span,
+                    format!("table `{}` is not allowed", from.table.name),
+                ));
+            }
+        }
+        for (axis, filter) in [
+            ("valid", &from.for_valid_time),
+            ("system", &from.for_system_time),
+        ] {
+            if let Some(filter) = filter {
+                self.temporal(axis, filter, from.span);
+            }
+        }
+        walk_from(self, from);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Subquery(_) => {
+                self.subqueries += 1;
+                match self.policy.max_subqueries {
+                    Some(max) if self.subqueries == max + 1 => {
+                        self.diagnostics.push(Diagnostic::error(
+                            "too-many-subqueries",
+                            expr.span,
+                            format!("query has more than {} subqueries", max),
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            ExprKind::Call { function, .. } => {
+                if let Some(functions) = &self.policy.functions {
+                    if !function.starts_with(':') && !functions.contains(function) {
+                        self.diagnostics.push(Diagnostic::error(
+                            "function-not-allowed",
+                            expr.span,
+                            format!("function `{}` is not allowed", function),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+impl PolicyChecker<'_> {
+    fn temporal(&mut self, axis: &str, filter: &TemporalFilter, span: Span) {
+        let policy = self.policy;
+        let open = match filter {
+            TemporalFilter::AllTime => {
+                if policy.forbid_all_time {
+                    self.diagnostics.push(Diagnostic::error(
+                        "time-range-not-allowed",
+                        span,
+                        format!("`:all-time` is not allowed for {} time", axis),
+                    ));
+                    return;
+                }
+                true
+            }
+            TemporalFilter::From(_) | TemporalFilter::To(_) => true,
+            TemporalFilter::In(from, to) => {
+                matches!(from.kind, ExprKind::Nil) || matches!(to.kind, ExprKind::Nil)
+            }
+            TemporalFilter::At(_) => false,
+        };
+        if open && policy.forbid_open_ranges {
+            self.diagnostics.push(Diagnostic::error(
+                "time-range-not-allowed",
+                span,
+                format!("{} time range `{}` must have both bounds", axis, filter),
+            ));
+        }
+        let Some((earliest_text, earliest)) = &policy.earliest_time else {
+            return;
+        };
+        let unbounded_below = match filter {
+            TemporalFilter::AllTime | TemporalFilter::To(_) => true,
+            TemporalFilter::In(from, _) => matches!(from.kind, ExprKind::Nil),
+            TemporalFilter::At(_) | TemporalFilter::From(_) => false,
+        };
+        if unbounded_below {
+            self.diagnostics.push(Diagnostic::error(
+                "time-range-not-allowed",
+                span,
+                format!(
+                    "{} time range `{}` reaches back before the earliest allowed, {}",
+                    axis, filter, earliest_text
+                ),
+            ));
+        }
+        let bounds = match filter {
+            TemporalFilter::At(t) | TemporalFilter::From(t) | TemporalFilter::To(t) => vec![t],
+            TemporalFilter::In(from, to) => vec![from, to],
+            TemporalFilter::AllTime => vec![],
+        };
+        for bound in bounds {
+            if matches!(bound.kind, ExprKind::Nil) {
+                continue;
+            }
+            let message = match literal_time(bound) {
+                Some(text) => match parse_instant(text) {
+                    Some(time) if time < *earliest => format!(
+                        "{} time {} is before the earliest allowed, {}",
+                        axis, text, earliest_text
+                    ),
+                    Some(_) => continue,
+                    None => format!("{} time {} is not a date or timestamp", axis, text),
+                },
+                None => format!(
+                    "{} time `{}` cannot be checked against the earliest allowed, {}; \
+                     use a literal date or timestamp",
+                    axis, bound, earliest_text
+                ),
+            };
+            self.diagnostics.push(Diagnostic::error(
+                "time-range-not-allowed",
+                bound.span,
+                message,
+            ));
+        }
+    }
+
+    fn limit(&mut self, query: &Query, max: u64) {
+        let limit = query.tail.iter().rev().find_map(|op| match op {
+            TailOp::Limit(limit) => Some(limit),
+            _ => None,
+        });
+        match limit {
+            Some(limit) if limit.value > max => self.diagnostics.push(Diagnostic::error(
+                "limit-too-large",
+                limit.span,
+                format!("limit {} exceeds the maximum of {}", limit.value, max),
+            )),
+            Some(_) => {}
+            None => self.diagnostics.push(Diagnostic::error(
+                "missing-limit",
+                query.span,
+                format!("query must end with a limit of at most {} rows", max),
+            )),
+        }
+    }
+}
+
+/// The text of a literal time: `#inst "..."`, `#time/date "..."` and the
+/// like, or a plain string.
+fn literal_time(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::String(s) => Some(s),
+        ExprKind::Tagged { value, .. } => literal_time(value),
+        _ => None,
+    }
+}
+
+/// Seconds and nanoseconds since the Unix epoch.
+type Instant = (i64, u32);
+
+/// Reads an ISO 8601 date (`2020-01-01`) or date-time
+/// (`2020-01-01T10:00:00.5`), optionally with an offset (`Z`, `+05:00`) and a
+/// zone (`[Europe/Paris]`). Dates without a time are midnight; times without
+/// an offset are UTC.
+fn parse_instant(text: &str) -> Option<Instant> {
+    let text = match text.find('[') {
+        Some(zone) if text.ends_with(']') => &text[..zone],
+        _ => text,
+    };
+    // The date is `YYYY-MM-DD`; an offset follows the time, or the date.
+    let (date, rest) = (text.get(..10)?, &text[10..]);
+    let (rest, offset) = if let Some(rest) = rest.strip_suffix(['Z', 'z']) {
+        (rest, 0)
+    } else {
+        match rest.rfind(['+', '-']) {
+            Some(i) => (&rest[..i], parse_offset(&rest[i..])?),
+            None => (rest, 0),
+        }
+    };
+    let seconds = match rest.strip_prefix(['T', 't', ' ']) {
+        Some(time) => parse_time(time)?,
+        None if rest.is_empty() => (0, 0),
+        None => return None,
+    };
+
+    let mut parts = date.split('-');
+    let year: i64 = number(parts.next()?, 4)?;
+    let month: u32 = number(parts.next()?, 2)?;
+    let day: u32 = number(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    Some((days * 86_400 + seconds.0 - offset, seconds.1))
+}
+
+/// `HH:MM`, `HH:MM:SS` or `HH:MM:SS.fraction`, as seconds and nanoseconds.
+fn parse_time(time: &str) -> Option<(i64, u32)> {
+    let (time, fraction) = match time.split_once(['.', ',']) {
+        Some((time, fraction)) if (1..=9).contains(&fraction.len()) => (time, fraction),
+        Some(_) => return None,
+        None => (time, ""),
+    };
+    let mut parts = time.split(':');
+    let hours: i64 = number(parts.next()?, 2)?;
+    let minutes: i64 = number(parts.next()?, 2)?;
+    let seconds: i64 = match parts.next() {
+        Some(seconds) => number(seconds, 2)?,
+        None if fraction.is_empty() => 0,
+        None => return None,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    let nanos = match fraction {
+        "" => 0,
+        fraction => number::<u32>(fraction, fraction.len())? * 10u32.pow(9 - fraction.len() as u32),
+    };
+    Some((hours * 3600 + minutes * 60 + seconds, nanos))
+}
+
+/// `+HH:MM`, `+HHMM` or `+HH`, as seconds east of UTC.
+fn parse_offset(offset: &str) -> Option<i64> {
+    let (sign, offset) = match offset.split_at(1) {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let (hours, minutes) = match offset.len() {
+        2 => (offset, "00"),
+        4 => offset.split_at(2),
+        5 if &offset[2..3] == ":" => (&offset[..2], &offset[3..]),
+        _ => return None,
+    };
+    let hours: i64 = number(hours, 2)?;
+    let minutes: i64 = number(minutes, 2)?;
+    if hours > 18 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses exactly `digits` ASCII digits.
+fn number<T: std::str::FromStr>(text: &str, digits: usize) -> Option<T> {
+    if text.len() != digits || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    fn check<'a>(policy: &Policy, text: &'a str) -> Vec<(String, &'a str)> {
+        policy
+            .check(&parse_query(text).unwrap())
+            .iter()
+            .map(|d| (d.to_string(), &text[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    fn violation(at: &str, code: &str, message: &str) -> String {
+        format!("{}: error[{}]: {}", at, code, message)
+    }
+
+    #[test]
+    fn allows_everything_by_default() {
+        let text = "(-> (unify (from :t {:bind [x] :for-valid-time :all-time}) \
+                    (join (from :u [x]) [x])) (where (exists? (from :v [x]))))";
+        assert_eq!(check(&Policy::new(), text), vec![]);
+    }
+
+    #[test]
+    fn bounds_nesting_depth() {
+        let policy = Policy::new().max_depth(2);
+        assert_eq!(
+            check(&policy, "(unify (from :t [x]) (join (from :u [x]) [x]))"),
+            vec![]
+        );
+        assert_eq!(
+            check(
+                &policy,
+                "(-> (from :t [x]) (where (exists? (-> (from :u [y]) \
+                 (where (exists? (from :v [z])))))))"
+            ),
+            vec![(
+                violation(
+                    "1:69",
+                    "nesting-too-deep",
+                    "query is nested 3 levels deep; at most 2 allowed"
+                ),
+                "(from :v [z])"
+            )]
+        );
+    }
+
+    #[test]
+    fn bounds_joins_and_unify_clauses() {
+        assert_eq!(
+            check(
+                &Policy::new().max_joins(1),
+                "(unify (from :t [x]) (join (from :u [x]) [x]) (left-join (from :v [x]) [x]) \
+                 (join (from :w [x]) [x]))"
+            ),
+            vec![(
+                violation("1:47", "too-many-joins", "query has more than 1 joins"),
+                "(left-join (from :v [x]) [x])"
+            )]
+        );
+        let text = "(unify (from :t [x]) (from :u [x]) (where (> x 1)))";
+        assert_eq!(
+            check(&Policy::new().max_unify_clauses(2), text),
+            vec![(
+                violation(
+                    "1:1",
+                    "too-many-clauses",
+                    "unify has 3 clauses; at most 2 allowed"
+                ),
+                text
+            )]
+        );
+        assert_eq!(check(&Policy::new().max_unify_clauses(3), text), vec![]);
+    }
+
+    #[test]
+    fn bounds_subqueries() {
+        assert_eq!(
+            check(
+                &Policy::new().max_subqueries(1),
+                "(-> (from :t [x]) (where (exists? (from :u [x])) (exists? (from :v [x])) \
+                 (q (from :w [x]))))"
+            ),
+            vec![(
+                violation(
+                    "1:50",
+                    "too-many-subqueries",
+                    "query has more than 1 subqueries"
+                ),
+                "(exists? (from :v [x]))"
+            )]
+        );
+    }
+
+    #[test]
+    fn requires_a_final_limit() {
+        let policy = Policy::new().require_limit(100);
+        assert_eq!(check(&policy, "(-> (from :t [x]) (limit 10))"), vec![]);
+        assert_eq!(
+            check(&policy, "(-> (from :t [x]) (limit 10) (offset 5))"),
+            vec![]
+        );
+        assert_eq!(
+            check(&policy, "(-> (from :t [x]) (limit 1000))"),
+            vec![(
+                violation(
+                    "1:19",
+                    "limit-too-large",
+                    "limit 1000 exceeds the maximum of 100"
+                ),
+                "(limit 1000)"
+            )]
+        );
+        assert_eq!(
+            check(&policy, "(from :t [x])"),
+            vec![(
+                violation(
+                    "1:1",
+                    "missing-limit",
+                    "query must end with a limit of at most 100 rows"
+                ),
+                "(from :t [x])"
+            )]
+        );
+    }
+
+    #[test]
+    fn restricts_tables_and_functions() {
+        assert_eq!(
+            check(
+                &Policy::new().allow_tables(["order-line"]),
+                "(unify (from :order_line [x]) (from :secrets [x]))"
+            ),
+            vec![(
+                violation(
+                    "1:37",
+                    "table-not-allowed",
+                    "table `secrets` is not allowed"
+                ),
+                ":secrets"
+            )]
+        );
+        assert_eq!(
+            check(
+                &Policy::new().allow_functions(["=", "+"]),
+                "(-> (from :t [x]) (where (= (+ x 1) 2) (> x 1)) (return {:k (:k x)}))"
+            ),
+            vec![(
+                violation(
+                    "1:40",
+                    "function-not-allowed",
+                    "function `>` is not allowed"
+                ),
+                "(> x 1)"
+            )]
+        );
+    }
+
+    #[test]
+    fn forbids_all_time_and_open_ranges() {
+        let text = "(from :t {:bind [x] :for-valid-time :all-time \
+                    :for-system-time (at #inst \"2020-01-01\")})";
+        assert_eq!(
+            check(&Policy::new().forbid_all_time(), text),
+            vec![(
+                violation(
+                    "1:1",
+                    "time-range-not-allowed",
+                    "`:all-time` is not allowed for valid time"
+                ),
+                text
+            )]
+        );
+        assert_eq!(
+            check(
+                &Policy::new().forbid_open_ranges(),
+                "(unify (from :t {:bind [x] :for-valid-time (from #inst \"2020-01-01\")}) \
+                 (from :u {:bind [x] :for-system-time (in #inst \"2020-01-01\" nil)}) \
+                 (from :v {:bind [x] :for-valid-time (in #inst \"2020-01-01\" #inst \"2021-01-01\")}))"
+            ),
+            vec![
+                (
+                    violation(
+                        "1:8",
+                        "time-range-not-allowed",
+                        "valid time range `(from #inst \"2020-01-01\")` must have both bounds"
+                    ),
+                    "(from :t {:bind [x] :for-valid-time (from #inst \"2020-01-01\")})"
+                ),
+                (
+                    violation(
+                        "1:72",
+                        "time-range-not-allowed",
+                        "system time range `(in #inst \"2020-01-01\" nil)` must have both bounds"
+                    ),
+                    "(from :u {:bind [x] :for-system-time (in #inst \"2020-01-01\" nil)})"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_times_as_instants() {
+        let policy = Policy::new().earliest_time("2020-01-01");
+        assert_eq!(
+            check(
+                &policy,
+                "(unify (from :t {:bind [x] :for-valid-time (at #inst \"2019-12-31T23:59:59Z\")}) \
+                 (from :u {:bind [x] :for-valid-time (at #inst \"2020-01-01T00:00+05:00\")}) \
+                 (from :v {:bind [x] :for-valid-time \
+                 (in #time/date \"2020-01-01\" #inst \"2020-06-01T00:00:00.5Z\")}))"
+            ),
+            vec![
+                (
+                    violation(
+                        "1:48",
+                        "time-range-not-allowed",
+                        "valid time 2019-12-31T23:59:59Z is before the earliest allowed, 2020-01-01"
+                    ),
+                    "#inst \"2019-12-31T23:59:59Z\""
+                ),
+                (
+                    violation(
+                        "1:120",
+                        "time-range-not-allowed",
+                        "valid time 2020-01-01T00:00+05:00 is before the earliest allowed, \
+                         2020-01-01"
+                    ),
+                    "#inst \"2020-01-01T00:00+05:00\""
+                ),
+            ]
+        );
+        assert_eq!(
+            check(
+                &Policy::new().earliest_time("2020-01-01T00:00+05:00"),
+                "(from :t {:bind [x] :for-valid-time (at #inst \"2019-12-31T19:00Z\")})"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn earliest_time_rejects_bounds_it_cannot_check() {
+        let policy = Policy::new().earliest_time("2020-01-01");
+        let cannot_check = |bound: &str| {
+            format!(
+                "valid time `{}` cannot be checked against the earliest allowed, 2020-01-01; \
+                 use a literal date or timestamp",
+                bound
+            )
+        };
+        assert_eq!(
+            check(
+                &policy,
+                "(unify (from :t {:bind [x] :for-valid-time (at $t)}) \
+                 (from :u {:bind [x t] :for-valid-time (from t)}) \
+                 (from :w {:bind [x] :for-valid-time (at \"yesterday\")}))"
+            ),
+            vec![
+                (
+                    violation("1:48", "time-range-not-allowed", &cannot_check("$t")),
+                    "$t"
+                ),
+                (
+                    violation("1:98", "time-range-not-allowed", &cannot_check("t")),
+                    "t"
+                ),
+                (
+                    violation(
+                        "1:143",
+                        "time-range-not-allowed",
+                        "valid time yesterday is not a date or timestamp"
+                    ),
+                    "\"yesterday\""
+                ),
+            ]
+        );
+        let reaches_back = |axis: &str, range: &str| {
+            format!(
+                "{} time range `{}` reaches back before the earliest allowed, 2020-01-01",
+                axis, range
+            )
+        };
+        assert_eq!(
+            check(
+                &policy,
+                "(unify (from :t {:bind [x] :for-valid-time :all-time}) \
+                 (from :u {:bind [x] :for-system-time (to #inst \"2021-01-01\")}) \
+                 (from :v {:bind [x] :for-valid-time (in nil #inst \"2021-01-01\")}))"
+            ),
+            vec![
+                (
+                    violation(
+                        "1:8",
+                        "time-range-not-allowed",
+                        &reaches_back("valid", ":all-time")
+                    ),
+                    "(from :t {:bind [x] :for-valid-time :all-time})"
+                ),
+                (
+                    violation(
+                        "1:56",
+                        "time-range-not-allowed",
+                        &reaches_back("system", "(to #inst \"2021-01-01\")")
+                    ),
+                    "(from :u {:bind [x] :for-system-time (to #inst \"2021-01-01\")})"
+                ),
+                (
+                    violation(
+                        "1:119",
+                        "time-range-not-allowed",
+                        &reaches_back("valid", "(in nil #inst \"2021-01-01\")")
+                    ),
+                    "(from :v {:bind [x] :for-valid-time (in nil #inst \"2021-01-01\")})"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_iso_8601_instants() {
+        assert_eq!(parse_instant("1970-01-01"), Some((0, 0)));
+        assert_eq!(parse_instant("1970-01-01Z"), Some((0, 0)));
+        assert_eq!(parse_instant("2000-03-01"), Some((951_868_800, 0)));
+        assert_eq!(parse_instant("1969-12-31T23:59:59Z"), Some((-1, 0)));
+        assert_eq!(
+            parse_instant("2020-01-01T00:00"),
+            parse_instant("2020-01-01")
+        );
+        assert_eq!(
+            parse_instant("2020-01-01T05:30:00+05:30"),
+            parse_instant("2020-01-01T00:00:00Z")
+        );
+        assert_eq!(
+            parse_instant("2020-01-01T01:00+01:00[Europe/Paris]"),
+            parse_instant("2020-01-01")
+        );
+        assert_eq!(
+            parse_instant("2019-12-31T19:00-0500"),
+            parse_instant("2020-01-01")
+        );
+        assert_eq!(
+            parse_instant("2020-01-01T00:00:00.25Z"),
+            Some((1_577_836_800, 250_000_000))
+        );
+        for invalid in [
+            "",
+            "yesterday",
+            "2020-1-1",
+            "2020-02-30",
+            "2021-02-29",
+            "2020-13-01",
+            "2020-01-01T24:00",
+            "2020-01-01T00:00+25:00",
+            "2020-01-01x",
+        ] {
+            assert_eq!(parse_instant(invalid), None, "{}", invalid);
+        }
+        assert_eq!(parse_instant("2020-02-29"), Some((1_582_934_400, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "earliest time \"soon\" is not a date or timestamp")]
+    fn earliest_time_must_be_a_time() {
+        Policy::new().earliest_time("soon");
+    }
+}
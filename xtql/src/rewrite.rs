@@ -0,0 +1,448 @@
+//! Rewrites that derive a new query from a parsed one.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::schema::normalise;
+use crate::visit::{
+    walk_expr, walk_from_mut, walk_join_mut, walk_query_mut, walk_subquery_mut, Visitor, VisitorMut,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Sets `:for-valid-time` and `:for-system-time` on every `from` clause of a
+/// query, including those inside `unify`, `join`, `left-join` and subqueries.
+///
+/// ```
+/// use xtql::ast::{Expr, TemporalFilter};
+/// use xtql::{parse_query, TemporalRewrite};
+///
+/// let query = parse_query("(from :orders [o-orderkey])").unwrap();
+/// let audit = TemporalRewrite::new()
+///     .system_time(TemporalFilter::At(Expr::param("as-of")))
+///     .apply(&query);
+/// assert_eq!(
+///     audit.to_string(),
+///     "(from :orders {:bind [o-orderkey] :for-system-time (at $as-of)})"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemporalRewrite {
+    valid_time: Option<TemporalFilter>,
+    system_time: Option<TemporalFilter>,
+    keep_existing: bool,
+}
+
+impl TemporalRewrite {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn valid_time(mut self, filter: TemporalFilter) -> Self {
+        self.valid_time = Some(filter);
+        self
+    }
+
+    pub fn system_time(mut self, filter: TemporalFilter) -> Self {
+        self.system_time = Some(filter);
+        self
+    }
+
+    /// Leaves filters the query already sets alone, only adding missing ones.
+    /// By default they are overridden.
+    pub fn keep_existing(mut self) -> Self {
+        self.keep_existing = true;
+        self
+    }
+
+    pub fn apply(&self, query: &Query) -> Query {
+        let mut query = query.clone();
+        TemporalRewriter(self).visit_query_mut(&mut query);
+        query
+    }
+}
+
+struct TemporalRewriter<'a>(&'a TemporalRewrite);
+
+impl VisitorMut for TemporalRewriter<'_> {
+    fn visit_from_mut(&mut self, from: &mut From) {
+        let rewrite = self.0;
+        for (filter, existing) in [
+            (&rewrite.valid_time, &mut from.for_valid_time),
+            (&rewrite.system_time, &mut from.for_system_time),
+        ] {
+            if let Some(filter) = filter {
+                if existing.is_none() || !rewrite.keep_existing {
+                    *existing = Some(Box::new(filter.clone()));
+                }
+            }
+        }
+        walk_from_mut(self, from);
+    }
+}
+
+/// The query as it stood at `time`, on both axes: what was true then, as it
+/// was recorded then. Existing time filters are overridden.
+pub fn as_of(query: &Query, time: Expr) -> Query {
+    TemporalRewrite::new()
+        .valid_time(TemporalFilter::At(time.clone()))
+        .system_time(TemporalFilter::At(time))
+        .apply(query)
+}
+
+/// Mandatory filters on tables, for isolating tenants client-side.
+///
+/// [`apply`](Self::apply) rewrites every `from` on a filtered table, wherever
+/// it appears, so that its rows must have each required column equal to the
+/// required value, typically a `$param`:
+///
+/// ```
+/// use xtql::ast::Expr;
+/// use xtql::{parse_query, RowFilters};
+///
+/// let filters = RowFilters::new().require("orders", "tenant-id", Expr::param("tenant"));
+/// let query = parse_query("(from :orders [o-orderkey])").unwrap();
+/// assert_eq!(
+///     filters.apply(&query).unwrap().to_string(),
+///     "(from :orders [o-orderkey {:tenant-id $tenant}])"
+/// );
+/// ```
+///
+/// A column the query already binds to a logic variable is additionally
+/// constrained with `where`. Tables are filtered whether or not they are
+/// qualified with the `public` schema (`:public/orders`). Queries where that
+/// is not enough are refused: those binding the column to a different value,
+/// those reading a filtered table's name in another schema, and those whose
+/// `:args` rebind a parameter the filters use, which would let a subquery see
+/// rows for a value of the caller's choosing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RowFilters {
+    /// Required columns and values by table, keyed by [`table_key`].
+    tables: BTreeMap<String, Vec<(String, Expr)>>,
+}
+
+impl RowFilters {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Requires rows of `table` to have `column` equal to `value`.
+    pub fn require(mut self, table: &str, column: &str, value: Expr) -> Self {
+        self.tables
+            .entry(table_key(table))
+            .or_default()
+            .push((column.to_string(), value));
+        self
+    }
+
+    /// Returns the rewritten query, or the reasons it cannot be rewritten
+    /// safely.
+    pub fn apply(&self, query: &Query) -> Result<Query, Vec<Diagnostic>> {
+        let mut params = Params(BTreeSet::new());
+        for (_, value) in self.tables.values().flatten() {
+            params.visit_expr(value);
+        }
+        let mut rewriter = RowFilterRewriter {
+            filters: self,
+            params: params.0,
+            conditions: vec![],
+            diagnostics: vec![],
+        };
+        let mut query = query.clone();
+        rewriter.visit_query_mut(&mut query);
+        if rewriter.diagnostics.is_empty() {
+            Ok(query)
+        } else {
+            rewriter.diagnostics.sort_by_key(|d| d.span.start);
+            Err(rewriter.diagnostics)
+        }
+    }
+}
+
+struct RowFilterRewriter<'a> {
+    filters: &'a RowFilters,
+    /// Parameters the filters' values use.
+    params: BTreeSet<String>,
+    /// Conditions for the query being rewritten, from columns its `from`
+    /// clauses already bind to logic variables.
+    conditions: Vec<Expr>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl VisitorMut for RowFilterRewriter<'_> {
+    /// Attaches the conditions collected from the query's own `from`
+    /// clauses; nested queries collect theirs separately.
+    fn visit_query_mut(&mut self, query: &mut Query) {
+        let outer = std::mem::take(&mut self.conditions);
+        walk_query_mut(self, query);
+        let conditions = std::mem::replace(&mut self.conditions, outer);
+        if conditions.is_empty() {
+            return;
+        }
+        match &mut query.source {
+            Source::Unify(unify) => {
+                let span = unify.span;
+                unify.clauses.push(UnifyClause::Where(Where {
+                    exprs: conditions,
+                    span,
+                }));
+            }
+            _ => {
+                let span = query.span;
+                query.tail.insert(
+                    0,
+                    TailOp::Where(Where {
+                        exprs: conditions,
+                        span,
+                    }),
+                );
+                query.pipeline = true;
+            }
+        }
+    }
+
+    fn visit_from_mut(&mut self, from: &mut From) {
+        walk_from_mut(self, from);
+        self.bind_required(from);
+    }
+
+    fn visit_join_mut(&mut self, join: &mut Join) {
+        self.check_args(&join.args);
+        walk_join_mut(self, join);
+    }
+
+    fn visit_subquery_mut(&mut self, subquery: &mut Subquery) {
+        self.check_args(&subquery.args);
+        walk_subquery_mut(self, subquery);
+    }
+}
+
+impl RowFilterRewriter<'_> {
+    /// Binds the required columns of `from`, collecting conditions on the
+    /// columns it already binds to logic variables.
+    fn bind_required(&mut self, from: &mut From) {
+        let key = table_key(&from.table.name);
+        let Some(required) = self.filters.tables.get(&key) else {
+            // `other/orders` is not the filtered `orders`, but nothing here
+            // knows which schemas share its rows: refuse rather than guess.
+            if let Some((_, table)) = key.split_once('/') {
+                if self.filters.tables.contains_key(table) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "row-filter-namespaced-table",
+                        from.table.span,
+                        format!(
+                            "`{}` is outside the `public` schema, where row filters apply to `{}`",
+                            from.table.name, table
+                        ),
+                    ));
+                }
+            }
+            return;
+        };
+        for (column, value) in required {
+            let bound: Vec<&BindSpec> = from
+                .bind
+                .iter()
+                .filter(|spec| normalise(&spec.column) == normalise(column))
+                .collect();
+            if bound.is_empty() {
+                from.bind.push(BindSpec::new(column, value.clone()));
+                continue;
+            }
+            for spec in bound {
+                if let Some(var) = spec.expr.as_var() {
+                    self.conditions
+                        .push(Expr::call("=", vec![Expr::var(var), value.clone()]));
+                } else if spec.expr.to_string() != value.to_string() {
+                    self.diagnostics.push(Diagnostic::error(
+                        "row-filter-conflict",
+                        spec.span,
+                        format!(
+                            "`{}` binds `{}` to {}, but rows must have {}",
+                            from.table.name, spec.column, spec.expr, value
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Refuses `:args` that rebind a parameter the filters use.
+    fn check_args(&mut self, args: &[Binding]) {
+        for binding in args {
+            if self.params.contains(&binding.name) {
+                self.diagnostics.push(Diagnostic::error(
+                    "row-filter-param-shadowed",
+                    binding.span,
+                    format!(
+                        "`:args` rebinds `${}`, which row filters depend on",
+                        binding.name
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// The table a name refers to, for looking up its filters: `public/orders`
+/// and `orders` are the same table.
+fn table_key(table: &str) -> String {
+    normalise(table.strip_prefix("public/").unwrap_or(table))
+}
+
+/// Collects the names of the parameters an expression uses.
+struct Params(BTreeSet<String>);
+
+impl Visitor for Params {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Param(name) = &expr.kind {
+            self.0.insert(name.clone());
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_query, parse_xtql};
+    use serde_json::json;
+
+    /// The rewritten query encodes, and its printed form parses back to the
+    /// same encoding.
+    fn encodes(query: &Query) -> serde_json::Value {
+        let json = query.to_json().unwrap();
+        assert_eq!(parse_xtql(&query.to_string()).unwrap(), json, "{}", query);
+        json
+    }
+
+    #[test]
+    fn as_of_with_a_param_or_variable() {
+        let query = parse_query("(unify (from :t [x]) (join (from :u [x]) [x]))").unwrap();
+        let rewritten = as_of(&query, Expr::param("as-of"));
+        assert_eq!(
+            rewritten.to_string(),
+            "(unify (from :t {:bind [x] :for-valid-time (at $as-of) :for-system-time (at $as-of)}) \
+             (join (from :u {:bind [x] :for-valid-time (at $as-of) :for-system-time (at $as-of)}) [x]))"
+        );
+        assert_eq!(
+            encodes(&rewritten)["unify"][0]["forValidTime"],
+            json!({"at": {"xt:param": "$as-of"}})
+        );
+
+        let rewritten = as_of(&query, Expr::var("t"));
+        assert_eq!(
+            encodes(&rewritten)["unify"][0]["forSystemTime"],
+            json!({"at": {"xt:lvar": "t"}})
+        );
+    }
+
+    #[test]
+    fn all_time() {
+        let query =
+            parse_query("(from :t {:bind [x] :for-valid-time (at #inst \"2020-01-01\")})").unwrap();
+        let rewritten = TemporalRewrite::new()
+            .valid_time(TemporalFilter::AllTime)
+            .system_time(TemporalFilter::AllTime)
+            .apply(&query);
+        assert_eq!(
+            encodes(&rewritten),
+            json!({"from": "t", "bind": [{"x": {"xt:lvar": "x"}}],
+                   "forValidTime": "allTime", "forSystemTime": "allTime"})
+        );
+    }
+
+    fn tenants() -> RowFilters {
+        RowFilters::new().require("orders", "tenant-id", Expr::param("tenant"))
+    }
+
+    fn filtered(query: &str) -> Result<String, Vec<String>> {
+        tenants()
+            .apply(&parse_query(query).unwrap())
+            .map(|query| query.to_string())
+            .map_err(|diagnostics| diagnostics.iter().map(|d| d.message.clone()).collect())
+    }
+
+    #[test]
+    fn row_filters_bind_required_columns() {
+        assert_eq!(
+            filtered("(from :orders [o])"),
+            Ok("(from :orders [o {:tenant-id $tenant}])".to_string())
+        );
+        assert_eq!(
+            filtered("(from :public/orders [o])"),
+            Ok("(from :public/orders [o {:tenant-id $tenant}])".to_string())
+        );
+        assert_eq!(
+            filtered("(from :customers [c])"),
+            Ok("(from :customers [c])".to_string())
+        );
+        let filters = RowFilters::new().require("public/orders", "tenant-id", Expr::param("t"));
+        let query = parse_query("(from :orders [o])").unwrap();
+        assert_eq!(
+            filters.apply(&query).unwrap().to_string(),
+            "(from :orders [o {:tenant-id $t}])"
+        );
+    }
+
+    #[test]
+    fn row_filters_constrain_bound_columns() {
+        assert_eq!(
+            filtered("(from :orders [o {:tenant_id t}])"),
+            Ok("(-> (from :orders [o {:tenant_id t}]) (where (= t $tenant)))".to_string())
+        );
+        assert_eq!(
+            filtered("(unify (from :orders [o tenant-id]) (join (from :orders [tenant-id]) [tenant-id]))"),
+            Ok("(unify (from :orders [o tenant-id]) \
+                (join (-> (from :orders [tenant-id]) (where (= tenant-id $tenant))) [tenant-id]) \
+                (where (= tenant-id $tenant)))"
+                .to_string())
+        );
+        assert_eq!(
+            filtered("(from :orders [o {:tenant-id $tenant}])"),
+            Ok("(from :orders [o {:tenant-id $tenant}])".to_string())
+        );
+    }
+
+    #[test]
+    fn row_filters_refuse_unsafe_queries() {
+        assert_eq!(
+            filtered("(from :orders [o {:tenant-id 42}])"),
+            Err(vec![
+                "`orders` binds `tenant-id` to 42, but rows must have $tenant".to_string()
+            ])
+        );
+        assert_eq!(
+            filtered("(from :archive/orders [o])"),
+            Err(vec![
+                "`archive/orders` is outside the `public` schema, where row filters apply to `orders`"
+                    .to_string()
+            ])
+        );
+        assert_eq!(
+            filtered(
+                "(-> (from :t [x]) (where (exists? (from :orders [o]) {:args [{:tenant x}]})))"
+            ),
+            Err(vec![
+                "`:args` rebinds `$tenant`, which row filters depend on".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn keep_existing() {
+        let query = parse_query(
+            "(-> (from :t {:bind [x] :for-valid-time (from #inst \"2020-01-01\")}) \
+             (where (exists? (from :u [x]) {:args [x]})))",
+        )
+        .unwrap();
+        let rewritten = TemporalRewrite::new()
+            .valid_time(TemporalFilter::AllTime)
+            .keep_existing()
+            .apply(&query);
+        assert_eq!(
+            rewritten.to_string(),
+            "(-> (from :t {:bind [x] :for-valid-time (from #inst \"2020-01-01\")}) \
+             (where (exists? (from :u {:bind [x] :for-valid-time :all-time}) {:args [x]})))"
+        );
+        encodes(&rewritten);
+    }
+}
@@ -0,0 +1,409 @@
+//! Table and column definitions for validating queries offline.
+//!
+//! A schema can be fetched from a node's `information_schema` (see the
+//! client's `fetch_schema`) or loaded from a JSON or TOML file mapping each
+//! table to its columns, with or without types:
+//!
+//! ```toml
+//! lineitem = ["l_orderkey", "l_quantity"]
+//!
+//! [orders]
+//! o_orderkey = "int"
+//! o_orderdate = "date"
+//! o_comment = "string?"
+//! ```
+//!
+//! Names are matched the way XTDB matches them: `o-orderkey` and `o_orderkey`
+//! are the same column, and `xt/id` is the system column `_id`.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::suggest;
+use crate::types::{ColumnTypes, Type};
+use crate::visit::{walk_from, Visitor};
+use crate::Error;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Columns XTDB maintains on every table.
+const SYSTEM_COLUMNS: &[&str] = &[
+    "_id",
+    "_valid_from",
+    "_valid_to",
+    "_system_from",
+    "_system_to",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    /// Columns by table, both keyed by normalised name.
+    tables: BTreeMap<String, BTreeMap<String, Type>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColumnsFile {
+    Names(Vec<String>),
+    Typed(BTreeMap<String, String>),
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, Error> {
+        let tables: BTreeMap<String, ColumnsFile> = serde_json::from_str(content)?;
+        Ok(Schema::from_file(tables))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, Error> {
+        let tables: BTreeMap<String, ColumnsFile> = toml::from_str(content)?;
+        Ok(Schema::from_file(tables))
+    }
+
+    /// Loads a `.json` or `.toml` file, according to its extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Schema::from_toml(&content),
+            _ => Schema::from_json(&content),
+        }
+    }
+
+    fn from_file(tables: BTreeMap<String, ColumnsFile>) -> Self {
+        let mut schema = Schema::new();
+        for (table, columns) in tables {
+            schema.add_table(&table);
+            match columns {
+                ColumnsFile::Names(names) => {
+                    for column in names {
+                        schema.add_column(&table, &column, Type::Any);
+                    }
+                }
+                ColumnsFile::Typed(types) => {
+                    for (column, data_type) in types {
+                        schema.add_column(&table, &column, parse_type(&data_type));
+                    }
+                }
+            }
+        }
+        schema
+    }
+
+    pub fn add_table(&mut self, table: &str) {
+        self.tables.entry(normalise(table)).or_default();
+    }
+
+    pub fn add_column(&mut self, table: &str, column: &str, data_type: Type) {
+        self.tables
+            .entry(normalise(table))
+            .or_default()
+            .insert(normalise(column), data_type);
+    }
+
+    pub fn has_table(&self, table: &str) -> bool {
+        self.tables.contains_key(&normalise(table))
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        self.tables.keys().map(String::as_str)
+    }
+
+    /// The table's columns and their types, excluding system columns.
+    pub fn columns(&self, table: &str) -> Option<&BTreeMap<String, Type>> {
+        self.tables.get(&normalise(table))
+    }
+
+    /// Reports `from` clauses on unknown tables and bind specs on unknown
+    /// columns, suggesting the closest known name. Only `from` names table
+    /// columns: the bind specs of joins name the joined query's columns and
+    /// are checked by [`check_scope`](crate::check_scope).
+    pub fn check(&self, query: &Query) -> Vec<Diagnostic> {
+        let mut checker = SchemaChecker {
+            schema: self,
+            diagnostics: vec![],
+        };
+        checker.visit_query(query);
+        checker.diagnostics.sort_by_key(|d| d.span.start);
+        checker.diagnostics
+    }
+
+    fn from(&self, from: &From, diagnostics: &mut Vec<Diagnostic>) {
+        let table = &from.table;
+        let Some(columns) = self.columns(&table.name) else {
+            let mut message = format!("unknown table `{}`", table.name);
+            if let Some(suggestion) = suggest::closest(&normalise(&table.name), self.tables()) {
+                message += &format!("; did you mean `{}`?", denormalise(suggestion));
+            }
+            diagnostics.push(Diagnostic::error("unknown-table", table.span, message));
+            return;
+        };
+        for spec in &from.bind {
+            let column = normalise(&spec.column);
+            if columns.contains_key(&column) || SYSTEM_COLUMNS.contains(&column.as_str()) {
+                continue;
+            }
+            let mut message = format!("table `{}` has no column `{}`", table.name, spec.column);
+            let names = columns
+                .keys()
+                .map(String::as_str)
+                .chain(SYSTEM_COLUMNS.iter().copied());
+            if let Some(suggestion) = suggest::closest(&column, names) {
+                message += &format!("; did you mean `{}`?", denormalise(suggestion));
+            }
+            diagnostics.push(Diagnostic::error("unknown-column", spec.span, message));
+        }
+    }
+}
+
+struct SchemaChecker<'a> {
+    schema: &'a Schema,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for SchemaChecker<'_> {
+    fn visit_from(&mut self, from: &From) {
+        self.schema.from(from, &mut self.diagnostics);
+        walk_from(self, from);
+    }
+}
+
+impl ColumnTypes for Schema {
+    fn column_type(&self, table: &str, column: &str) -> Option<Type> {
+        self.columns(table)?.get(&normalise(column)).cloned()
+    }
+}
+
+/// Checks `query` against `schema`; see [`Schema::check`].
+pub fn check_schema(query: &Query, schema: &Schema) -> Vec<Diagnostic> {
+    schema.check(query)
+}
+
+/// The name as XTDB stores it: `xt/valid-from` becomes `_valid_from`.
+pub(crate) fn normalise(name: &str) -> String {
+    let name = match name.strip_prefix("xt/") {
+        Some(system) => format!("_{}", system),
+        None => name.to_string(),
+    };
+    name.replace('-', "_")
+}
+
+/// The name as written in XTQL: `_valid_from` becomes `xt/valid-from`.
+fn denormalise(name: &str) -> String {
+    match name.strip_prefix('_') {
+        Some(system) => format!("xt/{}", system.replace('_', "-")),
+        None => name.replace('_', "-"),
+    }
+}
+
+/// Reads a column type, either as written in a schema file (`int`, `date`,
+/// `string?`) or as reported by `information_schema.columns.data_type`
+/// (`:i64`, `[:timestamp-tz :micro "UTC"]`, `[:union #{:null :utf8}]`).
+/// Type names must match exactly; unrecognised types are [`Type::Any`].
+pub fn parse_type(data_type: &str) -> Type {
+    let lower = data_type.trim().to_lowercase();
+    let (lower, optional) = match lower.strip_suffix('?') {
+        Some(lower) => (lower, true),
+        None => (lower.as_str(), false),
+    };
+    let words: Vec<&str> = lower
+        .split(|c: char| c.is_whitespace() || "[]{}#".contains(c))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.trim_start_matches(':'))
+        .collect();
+    let t = match words.as_slice() {
+        // A union of several types, other than with null, is their common type.
+        ["union", members @ ..] => members
+            .iter()
+            .map(|member| named_type(member))
+            .reduce(|a, b| a.common(&b))
+            .unwrap_or(Type::Any),
+        // The type is named first, followed by its parameters.
+        [name, ..] => named_type(name),
+        [] => Type::Any,
+    };
+    if optional {
+        t.nullable()
+    } else {
+        t
+    }
+}
+
+fn named_type(name: &str) -> Type {
+    match name {
+        "null" => Type::Null,
+        "struct" => Type::Struct,
+        "list" => Type::List(Box::new(Type::Any)),
+        "set" => Type::Set(Box::new(Type::Any)),
+        "timestamp-tz" | "timestamptz" => Type::TimestampTz,
+        "timestamp" | "timestamp-local" => Type::Timestamp,
+        "date" => Type::Date,
+        "duration" => Type::Duration,
+        "interval" => Type::Interval,
+        "decimal" => Type::Decimal,
+        "uuid" => Type::Uuid,
+        "keyword" => Type::Keyword,
+        "utf8" | "string" | "varchar" | "text" => Type::String,
+        "bool" => Type::Bool,
+        "f64" | "f32" | "float" | "double" => Type::Float,
+        "i64" | "i32" | "i16" | "i8" | "int" => Type::Int,
+        _ => Type::Any,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    const TOML: &str = r#"
+        lineitem = ["l_orderkey", "l-quantity"]
+
+        [orders]
+        o_orderkey = "int"
+        o_orderdate = "date"
+        o_comment = "string?"
+    "#;
+
+    fn check<'a>(schema: &Schema, text: &'a str) -> Vec<(String, &'a str)> {
+        schema
+            .check(&parse_query(text).unwrap())
+            .iter()
+            .map(|d| (d.to_string(), &text[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn loads_json_and_toml() {
+        let json = r#"{
+            "lineitem": ["l_orderkey", "l-quantity"],
+            "orders": {"o_orderkey": "int", "o_orderdate": "date", "o_comment": "string?"}
+        }"#;
+        let schema = Schema::from_toml(TOML).unwrap();
+        assert_eq!(Schema::from_json(json).unwrap(), schema);
+        assert_eq!(schema.tables().collect::<Vec<_>>(), ["lineitem", "orders"]);
+        assert_eq!(
+            schema.column_type("lineitem", "l-quantity"),
+            Some(Type::Any)
+        );
+        assert_eq!(
+            schema.column_type("orders", "o-orderdate"),
+            Some(Type::Date)
+        );
+        assert_eq!(
+            schema.column_type("orders", "o_comment"),
+            Some(Type::String.nullable())
+        );
+        assert_eq!(schema.column_type("orders", "o-total"), None);
+        assert!(Schema::from_json("[1]").is_err());
+    }
+
+    #[test]
+    fn parses_type_names_exactly() {
+        let cases = [
+            ("int", Type::Int),
+            ("string?", Type::String.nullable()),
+            (":i64", Type::Int),
+            (":utf8", Type::String),
+            ("[:timestamp-tz :micro \"UTC\"]", Type::TimestampTz),
+            ("[:timestamp-local :micro]", Type::Timestamp),
+            ("[:date :day]", Type::Date),
+            ("[:interval :month-day-nano]", Type::Interval),
+            ("[:list :i64]", Type::List(Box::new(Type::Any))),
+            ("[:union #{:null :utf8}]", Type::String.nullable()),
+            ("[:union #{:i64 :f64}]", Type::Float),
+            ("[:union #{:utf8 :i64}]", Type::Any),
+            (":null", Type::Null),
+            // Names containing a known type are not that type.
+            ("point", Type::Any),
+            ("stringly", Type::Any),
+            ("", Type::Any),
+        ];
+        for (data_type, expected) in cases {
+            assert_eq!(parse_type(data_type), expected, "{}", data_type);
+        }
+    }
+
+    #[test]
+    fn normalises_names_like_xtdb() {
+        let mut schema = Schema::new();
+        schema.add_column("line-item", "l-order-key", Type::Int);
+        assert!(schema.has_table("line_item"));
+        assert_eq!(
+            schema.column_type("line_item", "l_order_key"),
+            Some(Type::Int)
+        );
+        assert_eq!(normalise("xt/valid-from"), "_valid_from");
+        assert_eq!(denormalise("_valid_from"), "xt/valid-from");
+        assert_eq!(denormalise("l_order_key"), "l-order-key");
+        assert_eq!(
+            check(
+                &schema,
+                "(from :line_item [l-order-key {:l_order_key k} xt/id xt/valid-from])"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn suggests_known_tables_and_columns() {
+        let schema = Schema::from_toml(TOML).unwrap();
+        assert_eq!(
+            check(&schema, "(from :order [o-orderkey])"),
+            vec![(
+                "1:7: error[unknown-table]: unknown table `order`; did you mean `orders`?"
+                    .to_string(),
+                ":order"
+            )]
+        );
+        assert_eq!(
+            check(&schema, "(from :orders [o-orderdat xt/valid-form o-total])"),
+            vec![
+                (
+                    "1:16: error[unknown-column]: table `orders` has no column `o-orderdat`; \
+                     did you mean `o-orderdate`?"
+                        .to_string(),
+                    "o-orderdat"
+                ),
+                (
+                    "1:27: error[unknown-column]: table `orders` has no column `xt/valid-form`; \
+                     did you mean `xt/valid-from`?"
+                        .to_string(),
+                    "xt/valid-form"
+                ),
+                (
+                    "1:41: error[unknown-column]: table `orders` has no column `o-total`"
+                        .to_string(),
+                    "o-total"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_from_clauses_in_subqueries_but_not_join_bindings() {
+        let schema = Schema::from_toml(TOML).unwrap();
+        assert_eq!(
+            check(
+                &schema,
+                "(unify (from :lineitem [l-orderkey]) \
+                 (join (from :orders [o-orderkey]) [{:o-orderkey l-orderkey} zzz]))"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            check(
+                &schema,
+                "(-> (from :lineitem [l-orderkey]) (where (exists? (from :lineitems [x]))))"
+            ),
+            vec![(
+                "1:57: error[unknown-table]: unknown table `lineitems`; did you mean `lineitem`?"
+                    .to_string(),
+                ":lineitems"
+            )]
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Scope analysis: checks that every logic variable is bound where it is used.
+//!
+//! In a pipeline the columns produced by the source are in scope for each tail
+//! operator in turn; `return` and `aggregate` replace them, `with` and `unnest`
+//! add to them and `without` removes them. Within `unify` every clause sees
+//! every variable bound by any clause. Subqueries start from an empty scope:
+//! outer variables only reach them as `$params` through `:args`.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use std::collections::BTreeSet;
+
+type Scope = BTreeSet<String>;
+
+/// Reports logic variables used without being bound (errors) and bindings
+/// that hide another variable of the same name (warnings).
+pub fn check_scope(query: &Query) -> Vec<Diagnostic> {
+    let mut checker = ScopeChecker::default();
+    checker.query(query, &Scope::new());
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+#[derive(Default)]
+struct ScopeChecker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ScopeChecker {
+    /// Checks `query` and returns the columns it outputs. `outer` holds the
+    /// variables of the enclosing query, which are not visible here.
+    fn query(&mut self, query: &Query, outer: &Scope) -> Scope {
+        let mut scope = match &query.source {
+            Source::From(from) => self.unify(&[UnifyClause::From(from.clone())], outer),
+            Source::Rel(rel) => self.unify(&[UnifyClause::Rel(rel.clone())], outer),
+            Source::Unify(unify) => self.unify(&unify.clauses, outer),
+        };
+        for op in &query.tail {
+            self.tail_op(op, &mut scope);
+        }
+        scope
+    }
+
+    fn unify(&mut self, clauses: &[UnifyClause], outer: &Scope) -> Scope {
+        let mut bound = Scope::new();
+        let mut bind = |name: &str, span: Span, diagnostics: &mut Vec<Diagnostic>| {
+            if bound.insert(name.to_string()) && outer.contains(name) {
+                diagnostics.push(Diagnostic::warning(
+                    "shadowed-variable",
+                    span,
+                    format!(
+                        "`{}` shadows the enclosing query's variable; pass it with :args to correlate",
+                        name
+                    ),
+                ));
+            }
+        };
+        for clause in clauses {
+            match clause {
+                UnifyClause::From(From { bind: specs, .. })
+                | UnifyClause::Rel(Rel { bind: specs, .. })
+                | UnifyClause::Join(Join { bind: specs, .. })
+                | UnifyClause::LeftJoin(Join { bind: specs, .. }) => {
+                    for spec in specs {
+                        if let Some(var) = spec.expr.as_var() {
+                            bind(var, spec.expr.span, &mut self.diagnostics);
+                        }
+                    }
+                }
+                UnifyClause::With(with) => {
+                    for binding in &with.bindings {
+                        bind(&binding.name, binding.span, &mut self.diagnostics);
+                    }
+                }
+                UnifyClause::Unnest(unnest) => bind(
+                    &unnest.binding.name,
+                    unnest.binding.span,
+                    &mut self.diagnostics,
+                ),
+                // Unexpanded, a fragment may bind any variable passed to it.
+                UnifyClause::Fragment(fragment) => {
+                    for arg in &fragment.args {
+                        if let Some(var) = arg.as_var() {
+                            bind(var, arg.span, &mut self.diagnostics);
+                        }
+                    }
+                }
+                UnifyClause::Where(_) => {}
+            }
+        }
+
+        for clause in clauses {
+            match clause {
+                UnifyClause::From(from) => {
+                    self.bind_specs(&from.bind, &bound);
+                    for filter in [&from.for_valid_time, &from.for_system_time]
+                        .into_iter()
+                        .flatten()
+                    {
+                        self.temporal_filter(filter, &bound);
+                    }
+                }
+                UnifyClause::Rel(rel) => {
+                    self.expr(&rel.expr, &bound);
+                    self.bind_specs(&rel.bind, &bound);
+                }
+                UnifyClause::With(with) => {
+                    for binding in &with.bindings {
+                        self.expr(&binding.expr, &bound);
+                    }
+                }
+                UnifyClause::Unnest(unnest) => self.expr(&unnest.binding.expr, &bound),
+                UnifyClause::Where(w) => self.exprs(&w.exprs, &bound),
+                UnifyClause::Join(join) | UnifyClause::LeftJoin(join) => self.join(join, &bound),
+                UnifyClause::Fragment(fragment) => {
+                    for arg in &fragment.args {
+                        if arg.as_var().is_none() {
+                            self.expr(arg, &bound);
+                        }
+                    }
+                }
+            }
+        }
+        bound
+    }
+
+    /// Checks the expressions of non-variable bind specs, e.g. `{:age (+ a 1)}`.
+    fn bind_specs(&mut self, specs: &[BindSpec], scope: &Scope) {
+        for spec in specs {
+            if spec.expr.as_var().is_none() {
+                self.expr(&spec.expr, scope);
+            }
+        }
+    }
+
+    fn temporal_filter(&mut self, filter: &TemporalFilter, scope: &Scope) {
+        match filter {
+            TemporalFilter::At(t) | TemporalFilter::From(t) | TemporalFilter::To(t) => {
+                self.expr(t, scope)
+            }
+            TemporalFilter::In(from, to) => {
+                self.expr(from, scope);
+                self.expr(to, scope);
+            }
+            TemporalFilter::AllTime => {}
+        }
+    }
+
+    /// The joined query's columns are unified with the enclosing variables
+    /// through its bind specs, so unlike subqueries it shadows nothing.
+    fn join(&mut self, join: &Join, scope: &Scope) {
+        for arg in &join.args {
+            self.expr(&arg.expr, scope);
+        }
+        let columns = self.query(&join.query, &Scope::new());
+        for spec in &join.bind {
+            if !columns.contains(&spec.column) {
+                self.diagnostics.push(Diagnostic::error(
+                    "unknown-column",
+                    spec.span,
+                    format!("the joined query does not return `{}`", spec.column),
+                ));
+            }
+        }
+        self.bind_specs(&join.bind, scope);
+    }
+
+    fn tail_op(&mut self, op: &TailOp, scope: &mut Scope) {
+        match op {
+            TailOp::Where(w) => self.exprs(&w.exprs, scope),
+            TailOp::With(with) => {
+                for binding in &with.bindings {
+                    self.expr(&binding.expr, scope);
+                }
+                for binding in &with.bindings {
+                    if !scope.insert(binding.name.clone()) && !binding.is_var() {
+                        self.diagnostics.push(Diagnostic::warning(
+                            "shadowed-variable",
+                            binding.span,
+                            format!("`{}` is already bound; `with` replaces it", binding.name),
+                        ));
+                    }
+                }
+            }
+            TailOp::Unnest(unnest) => {
+                let binding = &unnest.binding;
+                self.expr(&binding.expr, scope);
+                if !scope.insert(binding.name.clone()) {
+                    self.diagnostics.push(Diagnostic::warning(
+                        "shadowed-variable",
+                        binding.span,
+                        format!("`{}` is already bound; `unnest` replaces it", binding.name),
+                    ));
+                }
+            }
+            TailOp::Without(without) => {
+                for column in &without.columns {
+                    if !scope.remove(&column.name) {
+                        self.diagnostics.push(Diagnostic::warning(
+                            "unknown-column",
+                            column.span,
+                            format!("`{}` is not bound, so `without` has no effect", column.name),
+                        ));
+                    }
+                }
+            }
+            TailOp::Return(Return { bindings, .. })
+            | TailOp::Aggregate(Aggregate { bindings, .. }) => {
+                for binding in bindings {
+                    self.expr(&binding.expr, scope);
+                }
+                *scope = bindings.iter().map(|b| b.name.clone()).collect();
+            }
+            TailOp::OrderBy(order_by) => {
+                for spec in &order_by.specs {
+                    if let Some(val) = &spec.val {
+                        self.expr(val, scope);
+                    }
+                }
+            }
+            TailOp::Limit(_) | TailOp::Offset(_) => {}
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr], scope: &Scope) {
+        for expr in exprs {
+            self.expr(expr, scope);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, scope: &Scope) {
+        match &expr.kind {
+            ExprKind::Var(name) => {
+                if !scope.contains(name) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "unbound-variable",
+                        expr.span,
+                        format!("logic variable `{}` is not bound", name),
+                    ));
+                }
+            }
+            ExprKind::Vector(items) | ExprKind::Set(items) => self.exprs(items, scope),
+            ExprKind::Map(entries) => {
+                for entry in entries {
+                    self.expr(&entry.expr, scope);
+                }
+            }
+            ExprKind::Call { args, .. } => self.exprs(args, scope),
+            ExprKind::GetField { expr, .. } => self.expr(expr, scope),
+            ExprKind::Tagged { value, .. } => self.expr(value, scope),
+            ExprKind::Subquery(subquery) => {
+                for arg in &subquery.args {
+                    self.expr(&arg.expr, scope);
+                }
+                let outer = scope
+                    .iter()
+                    .filter(|var| !subquery.args.iter().any(|arg| &arg.name == *var))
+                    .cloned()
+                    .collect();
+                self.query(&subquery.query, &outer);
+            }
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Nil
+            | ExprKind::Param(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    /// Each diagnostic with the source text its span covers.
+    fn check(text: &str) -> Vec<(String, &str)> {
+        check_scope(&parse_query(text).unwrap())
+            .iter()
+            .map(|d| (d.to_string(), &text[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    fn unbound(at: &str, name: &str) -> String {
+        format!(
+            "{}: error[unbound-variable]: logic variable `{}` is not bound",
+            at, name
+        )
+    }
+
+    #[test]
+    fn tracks_columns_through_a_pipeline() {
+        assert_eq!(
+            check(
+                "(-> (from :t [x y]) (with {:z (+ x y)}) (without :y) (return x z) (order-by z))"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            check("(-> (from :t [x]) (where (> y 1)))"),
+            vec![(unbound("1:29", "y"), "y")]
+        );
+        assert_eq!(
+            check("(-> (from :t [x y]) (return x) (where (> y 1)))"),
+            vec![(unbound("1:42", "y"), "y")]
+        );
+        assert_eq!(
+            check("(-> (from :t [x y]) (with {:x 1}) (unnest {:y x}) (without :w))"),
+            vec![
+                (
+                    "1:28: warning[shadowed-variable]: `x` is already bound; `with` replaces it"
+                        .to_string(),
+                    ":x"
+                ),
+                (
+                    "1:44: warning[shadowed-variable]: `y` is already bound; `unnest` replaces it"
+                        .to_string(),
+                    ":y"
+                ),
+                (
+                    "1:60: warning[unknown-column]: `w` is not bound, so `without` has no effect"
+                        .to_string(),
+                    ":w"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unify_clauses_see_each_others_variables() {
+        assert_eq!(
+            check("(unify (where (> x 1)) (with {y (* x 2)}) (from :t [x]))"),
+            vec![]
+        );
+        assert_eq!(
+            check("(from :t {:bind [x] :for-valid-time (at t)})"),
+            vec![(unbound("1:41", "t"), "t")]
+        );
+    }
+
+    #[test]
+    fn subqueries_only_see_outer_variables_through_args() {
+        assert_eq!(
+            check("(-> (from :t [x]) (where (exists? (-> (from :u [a]) (where (= a x))))))"),
+            vec![(unbound("1:65", "x"), "x")]
+        );
+        assert_eq!(
+            check(
+                "(-> (from :t [x]) (where (exists? (-> (from :u [a]) (where (= a $x))) {:args [x]})))"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            check("(-> (from :t [x]) (where (exists? (from :u [x]))))"),
+            vec![(
+                "1:45: warning[shadowed-variable]: `x` shadows the enclosing query's variable; \
+                 pass it with :args to correlate"
+                    .to_string(),
+                "x"
+            )]
+        );
+        assert_eq!(
+            check("(-> (from :t [x]) (where (exists? (from :u [x]) {:args [x]})))"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn joins_bind_the_columns_they_return() {
+        assert_eq!(
+            check("(unify (from :t [x]) (join (from :u [x y]) [x z]))"),
+            vec![(
+                "1:47: error[unknown-column]: the joined query does not return `z`".to_string(),
+                "z"
+            )]
+        );
+        assert_eq!(
+            check("(unify (from :t [x]) (join (-> (from :u [a]) (where (= a x))) [a]))"),
+            vec![(unbound("1:58", "x"), "x")]
+        );
+        assert_eq!(
+            check(
+                "(unify (from :t [x]) (left-join (from :u [{:a $x} b]) {:bind [b] :args [x y]}))"
+            ),
+            vec![(unbound("1:75", "y"), "y")]
+        );
+    }
+
+    #[test]
+    fn fragments_bind_their_variable_arguments() {
+        assert_eq!(check("(unify (fragment f x) (where (> x 1)))"), vec![]);
+        assert_eq!(
+            check("(unify (from :t [x]) (fragment f (+ y 1)))"),
+            vec![(unbound("1:37", "y"), "y")]
+        );
+    }
+}
@@ -0,0 +1,837 @@
+//! Static type inference over the typed AST.
+//!
+//! Types come from literals, the [function catalogue](crate::functions) and,
+//! when one is supplied, the column types of a schema. Anything that cannot be
+//! determined is [`Type::Any`], which is compatible with everything, so the
+//! pass only reports mismatches it is sure about.
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::functions::{Catalogue, Returns, ValueKind};
+use crate::value::XtValue;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// Unknown: a parameter, an unknown column or a dynamic result.
+    Any,
+    /// The type of `nil`.
+    Null,
+    Bool,
+    /// Some numeric type, e.g. the result of `count` or `+` on unknowns.
+    Number,
+    Int,
+    Float,
+    Decimal,
+    String,
+    Keyword,
+    Uuid,
+    /// Some temporal type.
+    Temporal,
+    Date,
+    Timestamp,
+    TimestampTz,
+    Duration,
+    Interval,
+    List(Box<Type>),
+    Set(Box<Type>),
+    Struct,
+    Nullable(Box<Type>),
+}
+
+impl Type {
+    pub fn nullable(self) -> Type {
+        match self {
+            Type::Any | Type::Null | Type::Nullable(_) => self,
+            t => Type::Nullable(Box::new(t)),
+        }
+    }
+
+    /// The type without its nullability.
+    pub fn non_null(&self) -> &Type {
+        match self {
+            Type::Nullable(t) => t,
+            t => t,
+        }
+    }
+
+    /// True when nothing is known about the value, so anything goes.
+    pub fn is_unknown(&self) -> bool {
+        matches!(self.non_null(), Type::Any | Type::Null)
+    }
+
+    /// The catalogue kind this type belongs to, if any.
+    pub fn kind(&self) -> Option<ValueKind> {
+        Some(match self.non_null() {
+            Type::Bool => ValueKind::Boolean,
+            Type::Number | Type::Int | Type::Float | Type::Decimal => ValueKind::Numeric,
+            Type::String => ValueKind::String,
+            Type::Temporal | Type::Date | Type::Timestamp | Type::TimestampTz => {
+                ValueKind::Temporal
+            }
+            Type::Duration | Type::Interval => ValueKind::Interval,
+            Type::List(_) | Type::Set(_) => ValueKind::Collection,
+            _ => return None,
+        })
+    }
+
+    /// Whether a value of this type may be passed where `kind` is expected.
+    pub fn fits(&self, kind: ValueKind) -> bool {
+        kind == ValueKind::Any || self.is_unknown() || self.kind() == Some(kind)
+    }
+
+    /// Whether values of the two types can be compared with `=`, `<` etc.
+    pub fn comparable(&self, other: &Type) -> bool {
+        if self.is_unknown() || other.is_unknown() {
+            return true;
+        }
+        match (self.kind(), other.kind()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.non_null() == other.non_null(),
+        }
+    }
+
+    /// The narrowest type covering both, e.g. `int` and `float` give `float`.
+    pub fn common(&self, other: &Type) -> Type {
+        use Type::*;
+        match (self, other) {
+            (a, b) if a == b => a.clone(),
+            (Any, _) | (_, Any) => Any,
+            (Null, t) | (t, Null) => t.clone().nullable(),
+            (Nullable(a), b) | (b, Nullable(a)) => a.common(b).nullable(),
+            (Int, Float) | (Float, Int) => Float,
+            (Int, Decimal) | (Decimal, Int) => Decimal,
+            (List(a), List(b)) => List(Box::new(a.common(b))),
+            (Set(a), Set(b)) => Set(Box::new(a.common(b))),
+            (a, b) => match (a.kind(), b.kind()) {
+                (Some(ValueKind::Numeric), Some(ValueKind::Numeric)) => Number,
+                (Some(ValueKind::Temporal), Some(ValueKind::Temporal)) => Temporal,
+                (Some(ValueKind::Interval), Some(ValueKind::Interval)) => Interval,
+                _ => Any,
+            },
+        }
+    }
+
+    fn of_kind(kind: ValueKind) -> Type {
+        match kind {
+            ValueKind::Any => Type::Any,
+            ValueKind::Numeric => Type::Number,
+            ValueKind::String => Type::String,
+            ValueKind::Boolean => Type::Bool,
+            ValueKind::Temporal => Type::Temporal,
+            ValueKind::Interval => Type::Interval,
+            ValueKind::Collection => Type::List(Box::new(Type::Any)),
+        }
+    }
+
+    /// The element type of a collection.
+    fn element(&self) -> Option<Type> {
+        match self.non_null() {
+            Type::List(t) | Type::Set(t) => Some((**t).clone()),
+            t if t.is_unknown() => Some(Type::Any),
+            _ => None,
+        }
+    }
+}
+
+impl std::convert::From<&XtValue> for Type {
+    fn from(value: &XtValue) -> Self {
+        let common = |items: &[XtValue]| {
+            items
+                .iter()
+                .map(Type::from)
+                .reduce(|a, b| a.common(&b))
+                .unwrap_or(Type::Any)
+        };
+        match value {
+            XtValue::Null => Type::Null,
+            XtValue::Bool(_) => Type::Bool,
+            XtValue::Int(_) => Type::Int,
+            XtValue::Double(_) => Type::Float,
+            XtValue::Decimal(_) => Type::Decimal,
+            XtValue::String(_) => Type::String,
+            XtValue::Keyword(_) => Type::Keyword,
+            XtValue::Uuid(_) => Type::Uuid,
+            XtValue::Instant(_) | XtValue::ZonedDateTime(_) => Type::TimestampTz,
+            XtValue::Date(_) => Type::Date,
+            XtValue::DateTime(_) => Type::Timestamp,
+            XtValue::Duration(_) => Type::Duration,
+            XtValue::Period(_) => Type::Interval,
+            XtValue::Time(_) => Type::Temporal,
+            XtValue::TimeZone(_) => Type::String,
+            XtValue::Set(items) => Type::Set(Box::new(common(items))),
+            XtValue::List(items) => Type::List(Box::new(common(items))),
+            XtValue::Map(_) => Type::Struct,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => f.write_str("any"),
+            Type::Null => f.write_str("null"),
+            Type::Bool => f.write_str("boolean"),
+            Type::Number => f.write_str("number"),
+            Type::Int => f.write_str("int"),
+            Type::Float => f.write_str("float"),
+            Type::Decimal => f.write_str("decimal"),
+            Type::String => f.write_str("string"),
+            Type::Keyword => f.write_str("keyword"),
+            Type::Uuid => f.write_str("uuid"),
+            Type::Temporal => f.write_str("temporal"),
+            Type::Date => f.write_str("date"),
+            Type::Timestamp => f.write_str("timestamp"),
+            Type::TimestampTz => f.write_str("timestamptz"),
+            Type::Duration => f.write_str("duration"),
+            Type::Interval => f.write_str("interval"),
+            Type::List(t) => write!(f, "[{}]", t),
+            Type::Set(t) => write!(f, "#{{{}}}", t),
+            Type::Struct => f.write_str("struct"),
+            Type::Nullable(t) => write!(f, "{}?", t),
+        }
+    }
+}
+
+/// Supplies the types of table columns to the inference.
+pub trait ColumnTypes {
+    fn column_type(&self, table: &str, column: &str) -> Option<Type>;
+}
+
+/// The result of [`infer_types`].
+#[derive(Debug, Clone, Default)]
+pub struct Typing {
+    /// The columns the query returns.
+    pub columns: BTreeMap<String, Type>,
+    /// The type of each expression, in the order they were visited.
+    pub exprs: Vec<(Span, Type)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Infers the type of every expression in `query`, reporting arguments of
+/// the wrong kind, incomparable comparisons, non-boolean `where` conditions
+/// and variables bound to columns of conflicting types.
+pub fn infer_types(query: &Query, schema: Option<&dyn ColumnTypes>) -> Typing {
+    let mut inference = Inference {
+        schema,
+        catalogue: Catalogue::standard(),
+        typing: Typing::default(),
+    };
+    let columns = inference.query(query);
+    let mut typing = inference.typing;
+    typing.columns = columns;
+    typing.diagnostics.sort_by_key(|d| d.span.start);
+    typing
+}
+
+/// The diagnostics of [`infer_types`].
+pub fn check_types(query: &Query, schema: Option<&dyn ColumnTypes>) -> Vec<Diagnostic> {
+    infer_types(query, schema).diagnostics
+}
+
+type Env = BTreeMap<String, Type>;
+
+const COMPARISONS: &[&str] = &[
+    "=", "<>", "<", ">", "<=", ">=", "between", "coalesce", "nullif", "greatest", "least",
+];
+
+const INT_RESULTS: &[&str] = &[
+    "row-count",
+    "count",
+    "count-distinct",
+    "char-length",
+    "octet-length",
+    "length",
+    "position",
+    "cardinality",
+    "extract",
+];
+
+struct Inference<'a> {
+    schema: Option<&'a dyn ColumnTypes>,
+    catalogue: &'a Catalogue,
+    typing: Typing,
+}
+
+impl Inference<'_> {
+    fn mismatch(&mut self, span: Span, message: String) {
+        self.typing
+            .diagnostics
+            .push(Diagnostic::error("type-mismatch", span, message));
+    }
+
+    /// Infers the query's output columns.
+    fn query(&mut self, query: &Query) -> Env {
+        let mut env = match &query.source {
+            Source::From(from) => self.unify(&[UnifyClause::From(from.clone())]),
+            Source::Rel(rel) => self.unify(&[UnifyClause::Rel(rel.clone())]),
+            Source::Unify(unify) => self.unify(&unify.clauses),
+        };
+        for op in &query.tail {
+            match op {
+                TailOp::Where(w) => self.conditions(&w.exprs, &env),
+                TailOp::With(with) => {
+                    let types: Vec<Type> = with
+                        .bindings
+                        .iter()
+                        .map(|binding| self.expr(&binding.expr, &env))
+                        .collect();
+                    for (binding, t) in with.bindings.iter().zip(types) {
+                        env.insert(binding.name.clone(), t);
+                    }
+                }
+                TailOp::Return(Return { bindings, .. })
+                | TailOp::Aggregate(Aggregate { bindings, .. }) => {
+                    env = bindings
+                        .iter()
+                        .map(|binding| (binding.name.clone(), self.expr(&binding.expr, &env)))
+                        .collect();
+                }
+                TailOp::Unnest(unnest) => {
+                    let t = self.unnest(&unnest.binding, &env);
+                    env.insert(unnest.binding.name.clone(), t);
+                }
+                TailOp::Without(without) => {
+                    for column in &without.columns {
+                        env.remove(&column.name);
+                    }
+                }
+                TailOp::OrderBy(order_by) => {
+                    for val in order_by.specs.iter().filter_map(|spec| spec.val.as_ref()) {
+                        self.expr(val, &env);
+                    }
+                }
+                TailOp::Limit(_) | TailOp::Offset(_) => {}
+            }
+        }
+        env
+    }
+
+    /// Binds data sources first, then `with` and `unnest`, then checks the
+    /// conditions, so each clause sees the types of all variables.
+    fn unify(&mut self, clauses: &[UnifyClause]) -> Env {
+        let mut env = Env::new();
+        for clause in clauses {
+            match clause {
+                UnifyClause::From(from) => {
+                    for spec in &from.bind {
+                        let t = self
+                            .schema
+                            .and_then(|schema| schema.column_type(&from.table.name, &spec.column))
+                            .unwrap_or(Type::Any);
+                        self.bind(&mut env, spec, t);
+                    }
+                }
+                UnifyClause::Rel(rel) => {
+                    let rows = self.expr(&rel.expr, &Env::new());
+                    for spec in &rel.bind {
+                        let t = rel_column_type(&rel.expr, &spec.column);
+                        self.bind(&mut env, spec, t);
+                    }
+                    if !rows.fits(ValueKind::Collection) {
+                        self.mismatch(
+                            rel.expr.span,
+                            format!("`rel` expects a collection of rows, got {}", rows),
+                        );
+                    }
+                }
+                UnifyClause::Join(join) | UnifyClause::LeftJoin(join) => {
+                    let columns = self.query(&join.query);
+                    let left = matches!(clause, UnifyClause::LeftJoin(_));
+                    for spec in &join.bind {
+                        let t = columns.get(&spec.column).cloned().unwrap_or(Type::Any);
+                        self.bind(&mut env, spec, if left { t.nullable() } else { t });
+                    }
+                }
+                _ => {}
+            }
+        }
+        for clause in clauses {
+            match clause {
+                UnifyClause::With(with) => {
+                    for binding in &with.bindings {
+                        let t = self.expr(&binding.expr, &env);
+                        env.entry(binding.name.clone()).or_insert(t);
+                    }
+                }
+                UnifyClause::Unnest(unnest) => {
+                    let t = self.unnest(&unnest.binding, &env);
+                    env.entry(unnest.binding.name.clone()).or_insert(t);
+                }
+                _ => {}
+            }
+        }
+        for clause in clauses {
+            match clause {
+                UnifyClause::From(from) => {
+                    for filter in [&from.for_valid_time, &from.for_system_time]
+                        .into_iter()
+                        .flatten()
+                    {
+                        self.temporal_filter(filter, &env);
+                    }
+                    self.filters(&from.bind, &env);
+                }
+                UnifyClause::Rel(rel) => self.filters(&rel.bind, &env),
+                UnifyClause::Join(join) | UnifyClause::LeftJoin(join) => {
+                    for arg in &join.args {
+                        self.expr(&arg.expr, &env);
+                    }
+                    self.filters(&join.bind, &env);
+                }
+                UnifyClause::Where(w) => self.conditions(&w.exprs, &env),
+                UnifyClause::With(_) | UnifyClause::Unnest(_) | UnifyClause::Fragment(_) => {}
+            }
+        }
+        env
+    }
+
+    /// Binds a variable to a column of type `t`, reporting variables that
+    /// unify columns of incomparable types.
+    fn bind(&mut self, env: &mut Env, spec: &BindSpec, t: Type) {
+        let Some(var) = spec.expr.as_var() else {
+            return;
+        };
+        match env.get(var) {
+            Some(existing) if !existing.comparable(&t) => {
+                let message = format!(
+                    "`{}` is bound to both {} and {} (column `{}`)",
+                    var, existing, t, spec.column
+                );
+                self.mismatch(spec.span, message);
+            }
+            Some(existing) if !existing.is_unknown() => {}
+            _ => {
+                env.insert(var.to_string(), t);
+            }
+        }
+    }
+
+    /// Checks bind specs that filter a column on a value rather than bind it.
+    fn filters(&mut self, specs: &[BindSpec], env: &Env) {
+        for spec in specs {
+            if spec.expr.as_var().is_some() {
+                continue;
+            }
+            self.expr(&spec.expr, env);
+        }
+    }
+
+    fn temporal_filter(&mut self, filter: &TemporalFilter, env: &Env) {
+        let bounds = match filter {
+            TemporalFilter::At(t) | TemporalFilter::From(t) | TemporalFilter::To(t) => vec![t],
+            TemporalFilter::In(from, to) => vec![from, to],
+            TemporalFilter::AllTime => vec![],
+        };
+        for bound in bounds {
+            let t = self.expr(bound, env);
+            // Temporal literals may also be written as ISO strings.
+            if !t.fits(ValueKind::Temporal) && t.non_null() != &Type::String {
+                self.mismatch(
+                    bound.span,
+                    format!("temporal filters expect a timestamp, got {}", t),
+                );
+            }
+        }
+    }
+
+    fn conditions(&mut self, exprs: &[Expr], env: &Env) {
+        for expr in exprs {
+            let t = self.expr(expr, env);
+            if !t.fits(ValueKind::Boolean) {
+                self.mismatch(
+                    expr.span,
+                    format!("`where` conditions must be boolean, got {}", t),
+                );
+            }
+        }
+    }
+
+    fn unnest(&mut self, binding: &Binding, env: &Env) -> Type {
+        let t = self.expr(&binding.expr, env);
+        t.element().unwrap_or_else(|| {
+            self.mismatch(
+                binding.expr.span,
+                format!("`unnest` expects a collection, got {}", t),
+            );
+            Type::Any
+        })
+    }
+
+    fn expr(&mut self, expr: &Expr, env: &Env) -> Type {
+        let t = match &expr.kind {
+            ExprKind::Int(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::String(_) => Type::String,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Nil => Type::Null,
+            ExprKind::Vector(items) => Type::List(Box::new(self.common(items, env))),
+            ExprKind::Set(items) => Type::Set(Box::new(self.common(items, env))),
+            ExprKind::Map(entries) => {
+                for entry in entries {
+                    self.expr(&entry.expr, env);
+                }
+                Type::Struct
+            }
+            ExprKind::Param(_) => Type::Any,
+            ExprKind::Var(name) => env.get(name).cloned().unwrap_or(Type::Any),
+            ExprKind::Tagged { value, .. } => {
+                self.expr(value, env);
+                literal_type(expr)
+            }
+            ExprKind::GetField { expr, .. } => {
+                self.expr(expr, env);
+                Type::Any
+            }
+            ExprKind::Subquery(subquery) => {
+                for arg in &subquery.args {
+                    self.expr(&arg.expr, env);
+                }
+                let columns = self.query(&subquery.query);
+                match subquery.kind {
+                    SubqueryKind::Q if columns.len() == 1 => {
+                        columns.into_values().next().unwrap().nullable()
+                    }
+                    SubqueryKind::Q => Type::Any,
+                    SubqueryKind::Exists => Type::Bool,
+                    SubqueryKind::Pull => Type::Struct.nullable(),
+                    SubqueryKind::PullMany => Type::List(Box::new(Type::Struct)),
+                }
+            }
+            ExprKind::Call { function, args } => {
+                let types: Vec<Type> = args.iter().map(|arg| self.expr(arg, env)).collect();
+                self.call(function, args, &types)
+            }
+        };
+        self.typing.exprs.push((expr.span, t.clone()));
+        t
+    }
+
+    fn common(&mut self, exprs: &[Expr], env: &Env) -> Type {
+        exprs
+            .iter()
+            .map(|expr| self.expr(expr, env))
+            .reduce(|a, b| a.common(&b))
+            .unwrap_or(Type::Any)
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], types: &[Type]) -> Type {
+        let Some(function) = self.catalogue.get(name) else {
+            return Type::Any;
+        };
+        if name == "+" || name == "-" {
+            return self.arithmetic(name, args, types);
+        }
+        if COMPARISONS.contains(&name) {
+            self.comparable(name, args, types);
+        }
+        if name == "in?" && types.len() == 2 {
+            if let Some(element) = types[1].element() {
+                if !types[0].comparable(&element) {
+                    let message = format!("cannot look for {} in {}", types[0], types[1]);
+                    self.mismatch(args[0].span, message);
+                }
+            }
+        }
+        for (i, (arg, t)) in args.iter().zip(types).enumerate() {
+            let kind = function.param(i);
+            if !t.fits(kind) {
+                let message = format!(
+                    "argument {} of `{}` must be {}, got {}",
+                    i + 1,
+                    name,
+                    kind,
+                    t
+                );
+                self.mismatch(arg.span, message);
+            }
+        }
+        match function.returns {
+            Returns::Kind(ValueKind::Numeric) if INT_RESULTS.contains(&name) => Type::Int,
+            Returns::Kind(ValueKind::Numeric) if name.starts_with("avg") => Type::Float,
+            Returns::Kind(kind) => Type::of_kind(kind),
+            Returns::Args => types
+                .iter()
+                .cloned()
+                .reduce(|a, b| a.common(&b))
+                .unwrap_or(Type::Any),
+        }
+    }
+
+    /// Reports the first argument not comparable with the ones before it.
+    fn comparable(&mut self, name: &str, args: &[Expr], types: &[Type]) {
+        let mut known: Option<&Type> = None;
+        for (arg, t) in args.iter().zip(types) {
+            match known {
+                Some(k) if !k.comparable(t) => {
+                    let message = format!("`{}` cannot compare {} with {}", name, k, t);
+                    self.mismatch(arg.span, message);
+                    return;
+                }
+                None if !t.is_unknown() => known = Some(t),
+                _ => {}
+            }
+        }
+    }
+
+    /// `+` and `-` apply to numbers, and to temporal values and intervals:
+    /// `date + interval` is a date and `date - date` an interval.
+    fn arithmetic(&mut self, name: &str, args: &[Expr], types: &[Type]) -> Type {
+        let mut result: Option<Type> = None;
+        for (arg, t) in args.iter().zip(types) {
+            if t.is_unknown() {
+                continue;
+            }
+            let Some(kind @ (ValueKind::Numeric | ValueKind::Temporal | ValueKind::Interval)) =
+                t.kind()
+            else {
+                self.mismatch(arg.span, format!("`{}` cannot be applied to {}", name, t));
+                return Type::Any;
+            };
+            let combined = match result.as_ref().map(|r| (r, r.kind())) {
+                None => t.clone(),
+                Some((r, Some(k))) if k == kind && kind != ValueKind::Temporal => r.common(t),
+                Some((_, Some(ValueKind::Temporal)))
+                    if kind == ValueKind::Temporal && name == "-" =>
+                {
+                    Type::Interval
+                }
+                Some((r, Some(ValueKind::Temporal))) if kind == ValueKind::Interval => r.clone(),
+                Some((_, Some(ValueKind::Interval)))
+                    if kind == ValueKind::Temporal && name == "+" =>
+                {
+                    t.clone()
+                }
+                Some((r, _)) => {
+                    let message = format!("`{}` cannot combine {} with {}", name, r, t);
+                    self.mismatch(arg.span, message);
+                    return Type::Any;
+                }
+            };
+            result = Some(combined);
+        }
+        match result {
+            Some(t) if types.iter().all(|t| !t.is_unknown()) => t,
+            Some(t) => match t.kind() {
+                Some(ValueKind::Numeric) => Type::Number,
+                _ => Type::Any,
+            },
+            None => Type::Any,
+        }
+    }
+}
+
+/// The type of `column` in a literal `rel`, e.g. `(rel [{:a 1} {:a 2}] [a])`.
+fn rel_column_type(rows: &Expr, column: &str) -> Type {
+    let ExprKind::Vector(rows) = &rows.kind else {
+        return Type::Any;
+    };
+    let mut t: Option<Type> = None;
+    for row in rows {
+        let ExprKind::Map(entries) = &row.kind else {
+            return Type::Any;
+        };
+        let value = entries
+            .iter()
+            .find(|entry| entry.name == column)
+            .map(|entry| literal_type(&entry.expr))
+            .unwrap_or(Type::Null);
+        t = Some(match t {
+            Some(t) => t.common(&value),
+            None => value,
+        });
+    }
+    t.unwrap_or(Type::Any)
+}
+
+fn literal_type(expr: &Expr) -> Type {
+    match &expr.kind {
+        ExprKind::Int(_) => Type::Int,
+        ExprKind::Float(_) => Type::Float,
+        ExprKind::String(_) => Type::String,
+        ExprKind::Bool(_) => Type::Bool,
+        ExprKind::Nil => Type::Null,
+        ExprKind::Tagged { tag, value } => match &value.kind {
+            ExprKind::String(s) => XtValue::from_edn_tag(tag, s)
+                .map(|value| Type::from(&value))
+                .unwrap_or(Type::Any),
+            _ => Type::Any,
+        },
+        _ => Type::Any,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    struct Columns;
+
+    impl ColumnTypes for Columns {
+        fn column_type(&self, table: &str, column: &str) -> Option<Type> {
+            match (table, column) {
+                ("orders", "placed") => Some(Type::Date),
+                ("orders", "total") => Some(Type::Decimal),
+                ("orders", "note") => Some(Type::String.nullable()),
+                ("users", "total") => Some(Type::String),
+                _ => None,
+            }
+        }
+    }
+
+    fn check(text: &str) -> Vec<(String, &str)> {
+        check_types(&parse_query(text).unwrap(), Some(&Columns))
+            .iter()
+            .map(|d| (d.to_string(), &text[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    fn columns(text: &str) -> Vec<String> {
+        infer_types(&parse_query(text).unwrap(), Some(&Columns))
+            .columns
+            .iter()
+            .map(|(name, t)| format!("{}: {}", name, t))
+            .collect()
+    }
+
+    fn mismatch(at: &str, message: &str) -> String {
+        format!("{}: error[type-mismatch]: {}", at, message)
+    }
+
+    #[test]
+    fn reports_arguments_of_the_wrong_kind() {
+        assert_eq!(
+            check("(-> (from :t [x]) (return {:a (+ \"a\" 1)}))"),
+            vec![(mismatch("1:34", "`+` cannot be applied to string"), "\"a\"")]
+        );
+        assert_eq!(
+            check("(-> (from :orders [total]) (return {:s (* total 2)} {:k (+ 1 2.5)}))"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn reports_incomparable_comparisons() {
+        assert_eq!(
+            check("(-> (from :orders [placed]) (where (< placed \"2020-01-01\")))"),
+            vec![(
+                mismatch("1:46", "`<` cannot compare date with string"),
+                "\"2020-01-01\""
+            )]
+        );
+        assert_eq!(
+            check("(-> (from :orders [placed]) (where (< placed #time/date \"2020-01-01\")))"),
+            vec![]
+        );
+        assert_eq!(
+            check(
+                "(-> (from :t [x]) (where (= 1 \"a\") (> true 2) (between 1 2 \"c\") (= x \"a\")))"
+            ),
+            vec![
+                (
+                    mismatch("1:31", "`=` cannot compare int with string"),
+                    "\"a\""
+                ),
+                (mismatch("1:44", "`>` cannot compare boolean with int"), "2"),
+                (
+                    mismatch("1:60", "`between` cannot compare int with string"),
+                    "\"c\""
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_membership() {
+        assert_eq!(
+            check("(-> (from :t [x]) (where (in? x [1 2]) (in? 1 \"abc\") (in? 1 [\"a\"])))"),
+            vec![
+                (
+                    mismatch(
+                        "1:47",
+                        "argument 2 of `in?` must be a collection, got string"
+                    ),
+                    "\"abc\""
+                ),
+                (mismatch("1:59", "cannot look for int in [string]"), "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn where_conditions_must_be_boolean() {
+        assert_eq!(
+            check("(-> (from :t [x]) (where 1 (+ x 1) \"s\" x (= x 1)))"),
+            vec![
+                (
+                    mismatch("1:26", "`where` conditions must be boolean, got int"),
+                    "1"
+                ),
+                (
+                    mismatch("1:28", "`where` conditions must be boolean, got number"),
+                    "(+ x 1)"
+                ),
+                (
+                    mismatch("1:36", "`where` conditions must be boolean, got string"),
+                    "\"s\""
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn types_rel_columns_from_their_rows() {
+        assert_eq!(
+            check("(-> (rel [{:a 1} {:a 2.5}] [a]) (where (= a \"x\")))"),
+            vec![(
+                mismatch("1:45", "`=` cannot compare float with string"),
+                "\"x\""
+            )]
+        );
+        assert_eq!(columns("(rel [{:a 1} {:a \"b\"}] [a])"), ["a: any"]);
+        assert_eq!(
+            check("(rel 1 [a])"),
+            vec![(
+                mismatch("1:6", "`rel` expects a collection of rows, got int"),
+                "1"
+            )]
+        );
+    }
+
+    #[test]
+    fn uses_column_types() {
+        assert_eq!(
+            check("(unify (from :orders [total]) (from :users [total]))"),
+            vec![(
+                mismatch(
+                    "1:45",
+                    "`total` is bound to both decimal and string (column `total`)"
+                ),
+                "total"
+            )]
+        );
+        assert_eq!(
+            columns(
+                "(-> (from :orders [placed total note]) (return {:n (count total)} \
+                 {:s (* total 2)} {:d placed} {:l (lower note)} {:p $p}))"
+            ),
+            ["d: date", "l: any", "n: int", "p: any", "s: decimal"]
+        );
+        assert_eq!(
+            columns(
+                "(-> (from :orders [total]) (aggregate {:s (sum total)} {:a (avg total)} \
+                 {:m (max total)}))"
+            ),
+            ["a: float", "m: decimal", "s: decimal"]
+        );
+    }
+
+    #[test]
+    fn columns_are_unknown_without_a_schema() {
+        let query = parse_query("(-> (from :orders [placed]) (where (< placed \"x\")))").unwrap();
+        assert_eq!(check_types(&query, None), vec![]);
+        assert_eq!(infer_types(&query, None).columns["placed"], Type::Any);
+    }
+}
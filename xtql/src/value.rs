@@ -0,0 +1,372 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Map, Value as JSONValue};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const KEYWORD: &str = "xt:keyword";
+pub const SET: &str = "xt:set";
+pub const UUID: &str = "xt:uuid";
+pub const DECIMAL: &str = "xt:decimal";
+pub const INSTANT: &str = "xt:instant";
+pub const DATE: &str = "xt:date";
+pub const DATE_TIME: &str = "xt:timestamp";
+pub const ZONED_DATE_TIME: &str = "xt:timestamptz";
+pub const DURATION: &str = "xt:duration";
+pub const PERIOD: &str = "xt:period";
+pub const TIME: &str = "xt:time";
+pub const TIME_ZONE: &str = "xt:timeZone";
+
+/// A value as XTDB encodes it in JSON-LD. Scalars XTDB cannot represent
+/// natively in JSON travel as `{"@type": ..., "@value": ...}` objects, with
+/// temporal values, decimals and uuids kept in their ISO/lexical string form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum XtValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Decimal(String),
+    String(String),
+    Keyword(String),
+    Uuid(String),
+    Instant(String),
+    Date(String),
+    DateTime(String),
+    ZonedDateTime(String),
+    Duration(String),
+    Period(String),
+    Time(String),
+    TimeZone(String),
+    Set(Vec<XtValue>),
+    List(Vec<XtValue>),
+    Map(BTreeMap<String, XtValue>),
+}
+
+/// Builds a `{"@type": tag, "@value": value}` object.
+pub fn tagged(tag: &str, value: JSONValue) -> JSONValue {
+    json!({ "@type": tag, "@value": value })
+}
+
+impl XtValue {
+    /// Maps an EDN reader tag (`#inst`, `#time/date`, `#uuid`, ...) and its
+    /// string payload to the corresponding value, if the tag is known.
+    pub fn from_edn_tag(tag: &str, value: &str) -> Option<XtValue> {
+        let value = value.to_string();
+        match tag {
+            "inst" | "instant" | "time/instant" => Some(XtValue::Instant(value)),
+            "time/date" => Some(XtValue::Date(value)),
+            "time/date-time" => Some(XtValue::DateTime(value)),
+            "time/zoned-date-time" => Some(XtValue::ZonedDateTime(value)),
+            "time/duration" => Some(XtValue::Duration(value)),
+            "time/period" => Some(XtValue::Period(value)),
+            "time/time" => Some(XtValue::Time(value)),
+            "time/zone" => Some(XtValue::TimeZone(value)),
+            "uuid" => Some(XtValue::Uuid(value)),
+            "bigdec" => Some(XtValue::Decimal(value)),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> JSONValue {
+        match self {
+            XtValue::Null => JSONValue::Null,
+            XtValue::Bool(b) => json!(b),
+            XtValue::Int(i) => json!(i),
+            XtValue::Double(d) => json!(d),
+            XtValue::String(s) => json!(s),
+            XtValue::Decimal(s) => tagged(DECIMAL, json!(s)),
+            XtValue::Keyword(s) => tagged(KEYWORD, json!(s)),
+            XtValue::Uuid(s) => tagged(UUID, json!(s)),
+            XtValue::Instant(s) => tagged(INSTANT, json!(s)),
+            XtValue::Date(s) => tagged(DATE, json!(s)),
+            XtValue::DateTime(s) => tagged(DATE_TIME, json!(s)),
+            XtValue::ZonedDateTime(s) => tagged(ZONED_DATE_TIME, json!(s)),
+            XtValue::Duration(s) => tagged(DURATION, json!(s)),
+            XtValue::Period(s) => tagged(PERIOD, json!(s)),
+            XtValue::Time(s) => tagged(TIME, json!(s)),
+            XtValue::TimeZone(s) => tagged(TIME_ZONE, json!(s)),
+            XtValue::Set(values) => tagged(SET, values.iter().map(XtValue::to_json).collect()),
+            XtValue::List(values) => values.iter().map(XtValue::to_json).collect(),
+            XtValue::Map(map) => JSONValue::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), v.to_json()))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+/// Error returned when JSON does not follow XTDB's typed value encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XtValueError(String);
+
+impl fmt::Display for XtValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for XtValueError {}
+
+fn expect_string(tag: &str, value: JSONValue) -> Result<String, XtValueError> {
+    match value {
+        JSONValue::String(s) => Ok(s),
+        other => Err(XtValueError(format!(
+            "expected a string @value for {}, found {}",
+            tag, other
+        ))),
+    }
+}
+
+impl TryFrom<JSONValue> for XtValue {
+    type Error = XtValueError;
+
+    fn try_from(value: JSONValue) -> Result<Self, Self::Error> {
+        match value {
+            JSONValue::Null => Ok(XtValue::Null),
+            JSONValue::Bool(b) => Ok(XtValue::Bool(b)),
+            JSONValue::Number(n) => match n.as_i64() {
+                Some(i) => Ok(XtValue::Int(i)),
+                None if n.is_f64() => Ok(XtValue::Double(n.as_f64().unwrap_or(f64::NAN))),
+                None => Err(XtValueError(format!(
+                    "integer {} does not fit in an i64",
+                    n
+                ))),
+            },
+            JSONValue::String(s) => Ok(XtValue::String(s)),
+            JSONValue::Array(values) => Ok(XtValue::List(
+                values
+                    .into_iter()
+                    .map(XtValue::try_from)
+                    .collect::<Result<_, _>>()?,
+            )),
+            JSONValue::Object(map) if map.contains_key("@type") || map.contains_key("@value") => {
+                typed(map)
+            }
+            JSONValue::Object(map) => Ok(XtValue::Map(
+                map.into_iter()
+                    .map(|(k, v)| XtValue::try_from(v).map(|v| (k, v)))
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+}
+
+/// Decodes a `{"@type": ..., "@value": ...}` object.
+fn typed(mut map: Map<String, JSONValue>) -> Result<XtValue, XtValueError> {
+    if map.len() != 2 || !map.contains_key("@value") {
+        let keys: Vec<&str> = map.keys().map(String::as_str).collect();
+        return Err(XtValueError(format!(
+            "expected a typed value with exactly @type and @value, found the keys {:?}",
+            keys
+        )));
+    }
+    let tag = match map.remove("@type") {
+        Some(JSONValue::String(tag)) => tag,
+        other => {
+            return Err(XtValueError(format!(
+                "expected a string @type, found {:?}",
+                other
+            )))
+        }
+    };
+    let value = map.remove("@value").unwrap_or_default();
+    match tag.as_str() {
+        SET => match XtValue::try_from(value)? {
+            XtValue::List(values) => Ok(XtValue::Set(values)),
+            other => Err(XtValueError(format!(
+                "expected an array @value for {}, found {:?}",
+                SET, other
+            ))),
+        },
+        KEYWORD => Ok(XtValue::Keyword(expect_string(&tag, value)?)),
+        UUID => Ok(XtValue::Uuid(expect_string(&tag, value)?)),
+        DECIMAL => Ok(XtValue::Decimal(expect_string(&tag, value)?)),
+        INSTANT => Ok(XtValue::Instant(expect_string(&tag, value)?)),
+        DATE => Ok(XtValue::Date(expect_string(&tag, value)?)),
+        DATE_TIME => Ok(XtValue::DateTime(expect_string(&tag, value)?)),
+        ZONED_DATE_TIME => Ok(XtValue::ZonedDateTime(expect_string(&tag, value)?)),
+        DURATION => Ok(XtValue::Duration(expect_string(&tag, value)?)),
+        PERIOD => Ok(XtValue::Period(expect_string(&tag, value)?)),
+        TIME => Ok(XtValue::Time(expect_string(&tag, value)?)),
+        TIME_ZONE => Ok(XtValue::TimeZone(expect_string(&tag, value)?)),
+        _ => Err(XtValueError(format!("unknown XTDB type {}", tag))),
+    }
+}
+
+impl From<XtValue> for JSONValue {
+    fn from(value: XtValue) -> Self {
+        value.to_json()
+    }
+}
+
+impl Serialize for XtValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for XtValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = JSONValue::deserialize(deserializer)?;
+        XtValue::try_from(value).map_err(de::Error::custom)
+    }
+}
+
+impl From<bool> for XtValue {
+    fn from(b: bool) -> Self {
+        XtValue::Bool(b)
+    }
+}
+
+impl From<i64> for XtValue {
+    fn from(i: i64) -> Self {
+        XtValue::Int(i)
+    }
+}
+
+impl From<i32> for XtValue {
+    fn from(i: i32) -> Self {
+        XtValue::Int(i.into())
+    }
+}
+
+impl From<f64> for XtValue {
+    fn from(d: f64) -> Self {
+        XtValue::Double(d)
+    }
+}
+
+impl From<&str> for XtValue {
+    fn from(s: &str) -> Self {
+        XtValue::String(s.to_string())
+    }
+}
+
+impl From<String> for XtValue {
+    fn from(s: String) -> Self {
+        XtValue::String(s)
+    }
+}
+
+impl<T: Into<XtValue>> From<Option<T>> for XtValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(XtValue::Null, Into::into)
+    }
+}
+
+impl<T: Into<XtValue>> From<Vec<T>> for XtValue {
+    fn from(values: Vec<T>) -> Self {
+        XtValue::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(value: JSONValue) -> Result<XtValue, String> {
+        XtValue::try_from(value).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn round_trips_every_tag() {
+        let text = |s: &str| s.to_string();
+        let values = [
+            (XtValue::Decimal(text("1.50")), DECIMAL),
+            (XtValue::Keyword(text("a/b")), KEYWORD),
+            (
+                XtValue::Uuid(text("97ce5b8a-1ba5-4ab5-8a1f-e3ab7b71a3b7")),
+                UUID,
+            ),
+            (XtValue::Instant(text("2020-01-01T00:00:00Z")), INSTANT),
+            (XtValue::Date(text("2020-01-01")), DATE),
+            (XtValue::DateTime(text("2020-01-01T12:30")), DATE_TIME),
+            (
+                XtValue::ZonedDateTime(text("2020-01-01T12:30+01:00[Europe/Paris]")),
+                ZONED_DATE_TIME,
+            ),
+            (XtValue::Duration(text("PT1H")), DURATION),
+            (XtValue::Period(text("P1Y2M")), PERIOD),
+            (XtValue::Time(text("12:30:00")), TIME),
+            (XtValue::TimeZone(text("Europe/Paris")), TIME_ZONE),
+        ];
+        for (value, tag) in values {
+            let json = value.to_json();
+            assert_eq!(json["@type"], tag);
+            assert_eq!(decode(json.clone()), Ok(value.clone()));
+            let deserialized: XtValue = serde_json::from_value(json).unwrap();
+            assert_eq!(
+                serde_json::to_value(&deserialized).unwrap(),
+                value.to_json()
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_collections() {
+        let value = XtValue::Map(BTreeMap::from([
+            (
+                "tags".to_string(),
+                XtValue::Set(vec![XtValue::Keyword("a".to_string()), XtValue::Int(1)]),
+            ),
+            (
+                "items".to_string(),
+                XtValue::List(vec![
+                    XtValue::Null,
+                    XtValue::Bool(true),
+                    XtValue::Double(0.5),
+                ]),
+            ),
+            ("name".to_string(), XtValue::String("x".to_string())),
+        ]));
+        assert_eq!(
+            value.to_json(),
+            json!({
+                "tags": {"@type": "xt:set", "@value": [{"@type": "xt:keyword", "@value": "a"}, 1]},
+                "items": [null, true, 0.5],
+                "name": "x"
+            })
+        );
+        assert_eq!(decode(value.to_json()), Ok(value));
+    }
+
+    #[test]
+    fn rejects_malformed_typed_values() {
+        assert_eq!(
+            decode(json!({"@type": 1, "@value": "x"})),
+            Err("expected a string @type, found Some(Number(1))".to_string())
+        );
+        assert_eq!(
+            decode(json!({"@type": "xt:date", "@value": 1})),
+            Err("expected a string @value for xt:date, found 1".to_string())
+        );
+        assert_eq!(
+            decode(json!({"@type": "xt:set", "@value": "a"})),
+            Err("expected an array @value for xt:set, found String(\"a\")".to_string())
+        );
+        assert_eq!(
+            decode(json!({"@type": "xt:nope", "@value": "a"})),
+            Err("unknown XTDB type xt:nope".to_string())
+        );
+        assert_eq!(
+            decode(json!({"@type": "xt:date"})),
+            Err(
+                "expected a typed value with exactly @type and @value, found the keys [\"@type\"]"
+                    .to_string()
+            )
+        );
+        assert!(decode(json!({"@type": "xt:date", "@value": "2020-01-01", "x": 1})).is_err());
+        assert!(decode(json!({"@value": "2020-01-01"})).is_err());
+    }
+
+    #[test]
+    fn rejects_integers_outside_i64() {
+        assert_eq!(decode(json!(i64::MIN)), Ok(XtValue::Int(i64::MIN)));
+        assert_eq!(
+            decode(json!(u64::MAX)),
+            Err("integer 18446744073709551615 does not fit in an i64".to_string())
+        );
+        assert_eq!(decode(json!(1e300)), Ok(XtValue::Double(1e300)));
+    }
+}
@@ -0,0 +1,807 @@
+//! Traversal of the query AST.
+//!
+//! [`Visitor`] walks a query by reference, [`VisitorMut`] edits it in place
+//! and [`Fold`] rebuilds it by value. Every method defaults to visiting the
+//! node's children through the matching `walk_*`, `walk_*_mut` or `fold_*`
+//! function, so an implementation only overrides the nodes it cares about and
+//! calls that function to keep descending:
+//!
+//! ```
+//! use xtql::ast::{Expr, ExprKind};
+//! use xtql::parse_query;
+//! use xtql::visit::{walk_expr, Visitor};
+//!
+//! #[derive(Default)]
+//! struct Calls(Vec<String>);
+//!
+//! impl Visitor for Calls {
+//!     fn visit_expr(&mut self, expr: &Expr) {
+//!         if let ExprKind::Call { function, .. } = &expr.kind {
+//!             self.0.push(function.clone());
+//!         }
+//!         walk_expr(self, expr);
+//!     }
+//! }
+//!
+//! let query = parse_query("(-> (from :t [x]) (where (> (+ x 1) 2)))").unwrap();
+//! let mut calls = Calls::default();
+//! calls.visit_query(&query);
+//! assert_eq!(calls.0, [">", "+"]);
+//! ```
+//!
+//! Children are visited in source order. Subqueries and the queries of
+//! `join` and `left-join` are visited through [`Visitor::visit_query`] like
+//! the top-level query.
+
+use crate::ast::*;
+
+pub trait Visitor {
+    fn visit_query(&mut self, query: &Query) {
+        walk_query(self, query)
+    }
+
+    fn visit_source(&mut self, source: &Source) {
+        walk_source(self, source)
+    }
+
+    fn visit_from(&mut self, from: &From) {
+        walk_from(self, from)
+    }
+
+    fn visit_temporal_filter(&mut self, filter: &TemporalFilter) {
+        walk_temporal_filter(self, filter)
+    }
+
+    fn visit_rel(&mut self, rel: &Rel) {
+        walk_rel(self, rel)
+    }
+
+    fn visit_unify(&mut self, unify: &Unify) {
+        walk_unify(self, unify)
+    }
+
+    fn visit_unify_clause(&mut self, clause: &UnifyClause) {
+        walk_unify_clause(self, clause)
+    }
+
+    /// Visits both `join` and `left-join` clauses.
+    fn visit_join(&mut self, join: &Join) {
+        walk_join(self, join)
+    }
+
+    fn visit_bind_spec(&mut self, spec: &BindSpec) {
+        walk_bind_spec(self, spec)
+    }
+
+    fn visit_binding(&mut self, binding: &Binding) {
+        walk_binding(self, binding)
+    }
+
+    fn visit_tail_op(&mut self, op: &TailOp) {
+        walk_tail_op(self, op)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_subquery(&mut self, subquery: &Subquery) {
+        walk_subquery(self, subquery)
+    }
+}
+
+pub fn walk_query<V: Visitor + ?Sized>(v: &mut V, query: &Query) {
+    v.visit_source(&query.source);
+    for op in &query.tail {
+        v.visit_tail_op(op);
+    }
+}
+
+pub fn walk_source<V: Visitor + ?Sized>(v: &mut V, source: &Source) {
+    match source {
+        Source::From(from) => v.visit_from(from),
+        Source::Rel(rel) => v.visit_rel(rel),
+        Source::Unify(unify) => v.visit_unify(unify),
+    }
+}
+
+pub fn walk_from<V: Visitor + ?Sized>(v: &mut V, from: &From) {
+    for spec in &from.bind {
+        v.visit_bind_spec(spec);
+    }
+    if let Some(filter) = &from.for_valid_time {
+        v.visit_temporal_filter(filter);
+    }
+    if let Some(filter) = &from.for_system_time {
+        v.visit_temporal_filter(filter);
+    }
+}
+
+pub fn walk_temporal_filter<V: Visitor + ?Sized>(v: &mut V, filter: &TemporalFilter) {
+    match filter {
+        TemporalFilter::At(t) | TemporalFilter::From(t) | TemporalFilter::To(t) => v.visit_expr(t),
+        TemporalFilter::In(from, to) => {
+            v.visit_expr(from);
+            v.visit_expr(to);
+        }
+        TemporalFilter::AllTime => {}
+    }
+}
+
+pub fn walk_rel<V: Visitor + ?Sized>(v: &mut V, rel: &Rel) {
+    v.visit_expr(&rel.expr);
+    for spec in &rel.bind {
+        v.visit_bind_spec(spec);
+    }
+}
+
+pub fn walk_unify<V: Visitor + ?Sized>(v: &mut V, unify: &Unify) {
+    for clause in &unify.clauses {
+        v.visit_unify_clause(clause);
+    }
+}
+
+pub fn walk_unify_clause<V: Visitor + ?Sized>(v: &mut V, clause: &UnifyClause) {
+    match clause {
+        UnifyClause::From(from) => v.visit_from(from),
+        UnifyClause::Rel(rel) => v.visit_rel(rel),
+        UnifyClause::With(with) => {
+            for binding in &with.bindings {
+                v.visit_binding(binding);
+            }
+        }
+        UnifyClause::Unnest(unnest) => v.visit_binding(&unnest.binding),
+        UnifyClause::Where(w) => {
+            for expr in &w.exprs {
+                v.visit_expr(expr);
+            }
+        }
+        UnifyClause::Join(join) | UnifyClause::LeftJoin(join) => v.visit_join(join),
+        UnifyClause::Fragment(fragment) => {
+            for expr in &fragment.args {
+                v.visit_expr(expr);
+            }
+        }
+    }
+}
+
+pub fn walk_join<V: Visitor + ?Sized>(v: &mut V, join: &Join) {
+    v.visit_query(&join.query);
+    for binding in &join.args {
+        v.visit_binding(binding);
+    }
+    for spec in &join.bind {
+        v.visit_bind_spec(spec);
+    }
+}
+
+pub fn walk_bind_spec<V: Visitor + ?Sized>(v: &mut V, spec: &BindSpec) {
+    v.visit_expr(&spec.expr)
+}
+
+pub fn walk_binding<V: Visitor + ?Sized>(v: &mut V, binding: &Binding) {
+    v.visit_expr(&binding.expr)
+}
+
+pub fn walk_tail_op<V: Visitor + ?Sized>(v: &mut V, op: &TailOp) {
+    match op {
+        TailOp::Where(w) => {
+            for expr in &w.exprs {
+                v.visit_expr(expr);
+            }
+        }
+        TailOp::With(With { bindings, .. })
+        | TailOp::Return(Return { bindings, .. })
+        | TailOp::Aggregate(Aggregate { bindings, .. }) => {
+            for binding in bindings {
+                v.visit_binding(binding);
+            }
+        }
+        TailOp::Unnest(unnest) => v.visit_binding(&unnest.binding),
+        TailOp::OrderBy(order_by) => {
+            for val in order_by.specs.iter().filter_map(|spec| spec.val.as_ref()) {
+                v.visit_expr(val);
+            }
+        }
+        TailOp::Limit(_) | TailOp::Offset(_) | TailOp::Without(_) => {}
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Vector(items) | ExprKind::Set(items) => {
+            for item in items {
+                v.visit_expr(item);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for entry in entries {
+                v.visit_binding(entry);
+            }
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+        ExprKind::GetField { expr, .. } => v.visit_expr(expr),
+        ExprKind::Subquery(subquery) => v.visit_subquery(subquery),
+        ExprKind::Tagged { value, .. } => v.visit_expr(value),
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Nil
+        | ExprKind::Param(_)
+        | ExprKind::Var(_) => {}
+    }
+}
+
+pub fn walk_subquery<V: Visitor + ?Sized>(v: &mut V, subquery: &Subquery) {
+    v.visit_query(&subquery.query);
+    for binding in &subquery.args {
+        v.visit_binding(binding);
+    }
+}
+
+/// Like [`Visitor`], with mutable access to every node.
+pub trait VisitorMut {
+    fn visit_query_mut(&mut self, query: &mut Query) {
+        walk_query_mut(self, query)
+    }
+
+    fn visit_source_mut(&mut self, source: &mut Source) {
+        walk_source_mut(self, source)
+    }
+
+    fn visit_from_mut(&mut self, from: &mut From) {
+        walk_from_mut(self, from)
+    }
+
+    fn visit_temporal_filter_mut(&mut self, filter: &mut TemporalFilter) {
+        walk_temporal_filter_mut(self, filter)
+    }
+
+    fn visit_rel_mut(&mut self, rel: &mut Rel) {
+        walk_rel_mut(self, rel)
+    }
+
+    fn visit_unify_mut(&mut self, unify: &mut Unify) {
+        walk_unify_mut(self, unify)
+    }
+
+    fn visit_unify_clause_mut(&mut self, clause: &mut UnifyClause) {
+        walk_unify_clause_mut(self, clause)
+    }
+
+    fn visit_join_mut(&mut self, join: &mut Join) {
+        walk_join_mut(self, join)
+    }
+
+    fn visit_bind_spec_mut(&mut self, spec: &mut BindSpec) {
+        walk_bind_spec_mut(self, spec)
+    }
+
+    fn visit_binding_mut(&mut self, binding: &mut Binding) {
+        walk_binding_mut(self, binding)
+    }
+
+    fn visit_tail_op_mut(&mut self, op: &mut TailOp) {
+        walk_tail_op_mut(self, op)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_subquery_mut(&mut self, subquery: &mut Subquery) {
+        walk_subquery_mut(self, subquery)
+    }
+}
+
+pub fn walk_query_mut<V: VisitorMut + ?Sized>(v: &mut V, query: &mut Query) {
+    v.visit_source_mut(&mut query.source);
+    for op in &mut query.tail {
+        v.visit_tail_op_mut(op);
+    }
+}
+
+pub fn walk_source_mut<V: VisitorMut + ?Sized>(v: &mut V, source: &mut Source) {
+    match source {
+        Source::From(from) => v.visit_from_mut(from),
+        Source::Rel(rel) => v.visit_rel_mut(rel),
+        Source::Unify(unify) => v.visit_unify_mut(unify),
+    }
+}
+
+pub fn walk_from_mut<V: VisitorMut + ?Sized>(v: &mut V, from: &mut From) {
+    for spec in &mut from.bind {
+        v.visit_bind_spec_mut(spec);
+    }
+    if let Some(filter) = &mut from.for_valid_time {
+        v.visit_temporal_filter_mut(filter);
+    }
+    if let Some(filter) = &mut from.for_system_time {
+        v.visit_temporal_filter_mut(filter);
+    }
+}
+
+pub fn walk_temporal_filter_mut<V: VisitorMut + ?Sized>(v: &mut V, filter: &mut TemporalFilter) {
+    match filter {
+        TemporalFilter::At(t) | TemporalFilter::From(t) | TemporalFilter::To(t) => {
+            v.visit_expr_mut(t)
+        }
+        TemporalFilter::In(from, to) => {
+            v.visit_expr_mut(from);
+            v.visit_expr_mut(to);
+        }
+        TemporalFilter::AllTime => {}
+    }
+}
+
+pub fn walk_rel_mut<V: VisitorMut + ?Sized>(v: &mut V, rel: &mut Rel) {
+    v.visit_expr_mut(&mut rel.expr);
+    for spec in &mut rel.bind {
+        v.visit_bind_spec_mut(spec);
+    }
+}
+
+pub fn walk_unify_mut<V: VisitorMut + ?Sized>(v: &mut V, unify: &mut Unify) {
+    for clause in &mut unify.clauses {
+        v.visit_unify_clause_mut(clause);
+    }
+}
+
+pub fn walk_unify_clause_mut<V: VisitorMut + ?Sized>(v: &mut V, clause: &mut UnifyClause) {
+    match clause {
+        UnifyClause::From(from) => v.visit_from_mut(from),
+        UnifyClause::Rel(rel) => v.visit_rel_mut(rel),
+        UnifyClause::With(with) => {
+            for binding in &mut with.bindings {
+                v.visit_binding_mut(binding);
+            }
+        }
+        UnifyClause::Unnest(unnest) => v.visit_binding_mut(&mut unnest.binding),
+        UnifyClause::Where(w) => {
+            for expr in &mut w.exprs {
+                v.visit_expr_mut(expr);
+            }
+        }
+        UnifyClause::Join(join) | UnifyClause::LeftJoin(join) => v.visit_join_mut(join),
+        UnifyClause::Fragment(fragment) => {
+            for expr in &mut fragment.args {
+                v.visit_expr_mut(expr);
+            }
+        }
+    }
+}
+
+pub fn walk_join_mut<V: VisitorMut + ?Sized>(v: &mut V, join: &mut Join) {
+    v.visit_query_mut(&mut join.query);
+    for binding in &mut join.args {
+        v.visit_binding_mut(binding);
+    }
+    for spec in &mut join.bind {
+        v.visit_bind_spec_mut(spec);
+    }
+}
+
+pub fn walk_bind_spec_mut<V: VisitorMut + ?Sized>(v: &mut V, spec: &mut BindSpec) {
+    v.visit_expr_mut(&mut spec.expr)
+}
+
+pub fn walk_binding_mut<V: VisitorMut + ?Sized>(v: &mut V, binding: &mut Binding) {
+    v.visit_expr_mut(&mut binding.expr)
+}
+
+pub fn walk_tail_op_mut<V: VisitorMut + ?Sized>(v: &mut V, op: &mut TailOp) {
+    match op {
+        TailOp::Where(w) => {
+            for expr in &mut w.exprs {
+                v.visit_expr_mut(expr);
+            }
+        }
+        TailOp::With(With { bindings, .. })
+        | TailOp::Return(Return { bindings, .. })
+        | TailOp::Aggregate(Aggregate { bindings, .. }) => {
+            for binding in bindings {
+                v.visit_binding_mut(binding);
+            }
+        }
+        TailOp::Unnest(unnest) => v.visit_binding_mut(&mut unnest.binding),
+        TailOp::OrderBy(order_by) => {
+            for val in order_by
+                .specs
+                .iter_mut()
+                .filter_map(|spec| spec.val.as_mut())
+            {
+                v.visit_expr_mut(val);
+            }
+        }
+        TailOp::Limit(_) | TailOp::Offset(_) | TailOp::Without(_) => {}
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Vector(items) | ExprKind::Set(items) => {
+            for item in items {
+                v.visit_expr_mut(item);
+            }
+        }
+        ExprKind::Map(entries) => {
+            for entry in entries {
+                v.visit_binding_mut(entry);
+            }
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                v.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::GetField { expr, .. } => v.visit_expr_mut(expr),
+        ExprKind::Subquery(subquery) => v.visit_subquery_mut(subquery),
+        ExprKind::Tagged { value, .. } => v.visit_expr_mut(value),
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Nil
+        | ExprKind::Param(_)
+        | ExprKind::Var(_) => {}
+    }
+}
+
+pub fn walk_subquery_mut<V: VisitorMut + ?Sized>(v: &mut V, subquery: &mut Subquery) {
+    v.visit_query_mut(&mut subquery.query);
+    for binding in &mut subquery.args {
+        v.visit_binding_mut(binding);
+    }
+}
+
+/// Rebuilds a query node by node. Unlike [`VisitorMut`], a method may return
+/// a different node than it was given, e.g. replace a call with a literal.
+pub trait Fold {
+    fn fold_query(&mut self, query: Query) -> Query {
+        fold_query(self, query)
+    }
+
+    fn fold_source(&mut self, source: Source) -> Source {
+        fold_source(self, source)
+    }
+
+    fn fold_from(&mut self, from: From) -> From {
+        fold_from(self, from)
+    }
+
+    fn fold_temporal_filter(&mut self, filter: TemporalFilter) -> TemporalFilter {
+        fold_temporal_filter(self, filter)
+    }
+
+    fn fold_rel(&mut self, rel: Rel) -> Rel {
+        fold_rel(self, rel)
+    }
+
+    fn fold_unify(&mut self, unify: Unify) -> Unify {
+        fold_unify(self, unify)
+    }
+
+    fn fold_unify_clause(&mut self, clause: UnifyClause) -> UnifyClause {
+        fold_unify_clause(self, clause)
+    }
+
+    fn fold_join(&mut self, join: Join) -> Join {
+        fold_join(self, join)
+    }
+
+    fn fold_bind_spec(&mut self, spec: BindSpec) -> BindSpec {
+        fold_bind_spec(self, spec)
+    }
+
+    fn fold_binding(&mut self, binding: Binding) -> Binding {
+        fold_binding(self, binding)
+    }
+
+    fn fold_tail_op(&mut self, op: TailOp) -> TailOp {
+        fold_tail_op(self, op)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_subquery(&mut self, subquery: Subquery) -> Subquery {
+        fold_subquery(self, subquery)
+    }
+}
+
+pub fn fold_query<F: Fold + ?Sized>(f: &mut F, query: Query) -> Query {
+    Query {
+        source: f.fold_source(query.source),
+        tail: query
+            .tail
+            .into_iter()
+            .map(|op| f.fold_tail_op(op))
+            .collect(),
+        ..query
+    }
+}
+
+pub fn fold_source<F: Fold + ?Sized>(f: &mut F, source: Source) -> Source {
+    match source {
+        Source::From(from) => Source::From(f.fold_from(from)),
+        Source::Rel(rel) => Source::Rel(f.fold_rel(rel)),
+        Source::Unify(unify) => Source::Unify(f.fold_unify(unify)),
+    }
+}
+
+pub fn fold_from<F: Fold + ?Sized>(f: &mut F, from: From) -> From {
+    From {
+        bind: fold_bind_specs(f, from.bind),
+        for_valid_time: from
+            .for_valid_time
+            .map(|filter| Box::new(f.fold_temporal_filter(*filter))),
+        for_system_time: from
+            .for_system_time
+            .map(|filter| Box::new(f.fold_temporal_filter(*filter))),
+        ..from
+    }
+}
+
+pub fn fold_temporal_filter<F: Fold + ?Sized>(f: &mut F, filter: TemporalFilter) -> TemporalFilter {
+    match filter {
+        TemporalFilter::At(t) => TemporalFilter::At(f.fold_expr(t)),
+        TemporalFilter::From(t) => TemporalFilter::From(f.fold_expr(t)),
+        TemporalFilter::To(t) => TemporalFilter::To(f.fold_expr(t)),
+        TemporalFilter::In(from, to) => TemporalFilter::In(f.fold_expr(from), f.fold_expr(to)),
+        TemporalFilter::AllTime => TemporalFilter::AllTime,
+    }
+}
+
+pub fn fold_rel<F: Fold + ?Sized>(f: &mut F, rel: Rel) -> Rel {
+    Rel {
+        expr: f.fold_expr(rel.expr),
+        bind: fold_bind_specs(f, rel.bind),
+        ..rel
+    }
+}
+
+pub fn fold_unify<F: Fold + ?Sized>(f: &mut F, unify: Unify) -> Unify {
+    Unify {
+        clauses: unify
+            .clauses
+            .into_iter()
+            .map(|clause| f.fold_unify_clause(clause))
+            .collect(),
+        ..unify
+    }
+}
+
+pub fn fold_unify_clause<F: Fold + ?Sized>(f: &mut F, clause: UnifyClause) -> UnifyClause {
+    match clause {
+        UnifyClause::From(from) => UnifyClause::From(f.fold_from(from)),
+        UnifyClause::Rel(rel) => UnifyClause::Rel(f.fold_rel(rel)),
+        UnifyClause::With(with) => UnifyClause::With(With {
+            bindings: fold_bindings(f, with.bindings),
+            ..with
+        }),
+        UnifyClause::Unnest(unnest) => UnifyClause::Unnest(Unnest {
+            binding: f.fold_binding(unnest.binding),
+            ..unnest
+        }),
+        UnifyClause::Where(w) => UnifyClause::Where(Where {
+            exprs: fold_exprs(f, w.exprs),
+            ..w
+        }),
+        UnifyClause::Join(join) => UnifyClause::Join(f.fold_join(join)),
+        UnifyClause::LeftJoin(join) => UnifyClause::LeftJoin(f.fold_join(join)),
+        UnifyClause::Fragment(fragment) => UnifyClause::Fragment(FragmentRef {
+            args: fold_exprs(f, fragment.args),
+            ..fragment
+        }),
+    }
+}
+
+pub fn fold_join<F: Fold + ?Sized>(f: &mut F, join: Join) -> Join {
+    Join {
+        query: Box::new(f.fold_query(*join.query)),
+        args: fold_bindings(f, join.args),
+        bind: fold_bind_specs(f, join.bind),
+        ..join
+    }
+}
+
+pub fn fold_bind_spec<F: Fold + ?Sized>(f: &mut F, spec: BindSpec) -> BindSpec {
+    BindSpec {
+        expr: f.fold_expr(spec.expr),
+        ..spec
+    }
+}
+
+pub fn fold_binding<F: Fold + ?Sized>(f: &mut F, binding: Binding) -> Binding {
+    Binding {
+        expr: f.fold_expr(binding.expr),
+        ..binding
+    }
+}
+
+pub fn fold_tail_op<F: Fold + ?Sized>(f: &mut F, op: TailOp) -> TailOp {
+    match op {
+        TailOp::Where(w) => TailOp::Where(Where {
+            exprs: fold_exprs(f, w.exprs),
+            ..w
+        }),
+        TailOp::With(with) => TailOp::With(With {
+            bindings: fold_bindings(f, with.bindings),
+            ..with
+        }),
+        TailOp::Return(ret) => TailOp::Return(Return {
+            bindings: fold_bindings(f, ret.bindings),
+            ..ret
+        }),
+        TailOp::Aggregate(aggregate) => TailOp::Aggregate(Aggregate {
+            bindings: fold_bindings(f, aggregate.bindings),
+            ..aggregate
+        }),
+        TailOp::Unnest(unnest) => TailOp::Unnest(Unnest {
+            binding: f.fold_binding(unnest.binding),
+            ..unnest
+        }),
+        TailOp::OrderBy(order_by) => TailOp::OrderBy(OrderBy {
+            specs: order_by
+                .specs
+                .into_iter()
+                .map(|spec| OrderSpec {
+                    val: spec.val.map(|val| f.fold_expr(val)),
+                    ..spec
+                })
+                .collect(),
+            ..order_by
+        }),
+        op @ (TailOp::Limit(_) | TailOp::Offset(_) | TailOp::Without(_)) => op,
+    }
+}
+
+pub fn fold_expr<F: Fold + ?Sized>(f: &mut F, expr: Expr) -> Expr {
+    let kind = match expr.kind {
+        ExprKind::Vector(items) => ExprKind::Vector(fold_exprs(f, items)),
+        ExprKind::Set(items) => ExprKind::Set(fold_exprs(f, items)),
+        ExprKind::Map(entries) => ExprKind::Map(fold_bindings(f, entries)),
+        ExprKind::Call { function, args } => ExprKind::Call {
+            function,
+            args: fold_exprs(f, args),
+        },
+        ExprKind::GetField { expr, field } => ExprKind::GetField {
+            expr: Box::new(f.fold_expr(*expr)),
+            field,
+        },
+        ExprKind::Subquery(subquery) => ExprKind::Subquery(f.fold_subquery(subquery)),
+        ExprKind::Tagged { tag, value } => ExprKind::Tagged {
+            tag,
+            value: Box::new(f.fold_expr(*value)),
+        },
+        kind @ (ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Nil
+        | ExprKind::Param(_)
+        | ExprKind::Var(_)) => kind,
+    };
+    Expr { kind, ..expr }
+}
+
+pub fn fold_subquery<F: Fold + ?Sized>(f: &mut F, subquery: Subquery) -> Subquery {
+    Subquery {
+        query: Box::new(f.fold_query(*subquery.query)),
+        args: fold_bindings(f, subquery.args),
+        ..subquery
+    }
+}
+
+fn fold_bind_specs<F: Fold + ?Sized>(f: &mut F, specs: Vec<BindSpec>) -> Vec<BindSpec> {
+    specs
+        .into_iter()
+        .map(|spec| f.fold_bind_spec(spec))
+        .collect()
+}
+
+fn fold_bindings<F: Fold + ?Sized>(f: &mut F, bindings: Vec<Binding>) -> Vec<Binding> {
+    bindings
+        .into_iter()
+        .map(|binding| f.fold_binding(binding))
+        .collect()
+}
+
+fn fold_exprs<F: Fold + ?Sized>(f: &mut F, exprs: Vec<Expr>) -> Vec<Expr> {
+    exprs.into_iter().map(|expr| f.fold_expr(expr)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_query;
+
+    const QUERY: &str = "(-> (unify (from :t {:bind [a {:b $p}] :for-valid-time (at t)}) \
+                         (left-join (from :u [a c]) [a c]) \
+                         (where (exists? (from :v [{:a $a} d]) {:args [a]}))) \
+                         (with {:e (. c f)}) \
+                         (unnest {:g #{e h}}) \
+                         (order-by {:val (- a) :dir :desc}))";
+
+    /// The names of variables and parameters, in the order they are visited.
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_expr(&mut self, expr: &Expr) {
+            match &expr.kind {
+                ExprKind::Var(name) => self.0.push(name.clone()),
+                ExprKind::Param(name) => self.0.push(format!("${}", name)),
+                _ => {}
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let ExprKind::Var(name) = &mut expr.kind {
+                name.push('2');
+            }
+            walk_expr_mut(self, expr);
+        }
+    }
+
+    impl Fold for Rename {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            match fold_expr(self, expr) {
+                Expr {
+                    kind: ExprKind::Var(name),
+                    span,
+                } => Expr {
+                    kind: ExprKind::Var(format!("{}2", name)),
+                    span,
+                },
+                expr => expr,
+            }
+        }
+    }
+
+    struct Identity;
+
+    impl Fold for Identity {}
+
+    #[test]
+    fn visits_in_source_order() {
+        let mut names = Names::default();
+        names.visit_query(&parse_query(QUERY).unwrap());
+        assert_eq!(
+            names.0,
+            ["a", "$p", "t", "a", "c", "a", "c", "$a", "d", "a", "c", "e", "h", "a"]
+        );
+    }
+
+    #[test]
+    fn visitor_mut_and_fold_agree() {
+        let query = parse_query(QUERY).unwrap();
+        let mut edited = query.clone();
+        Rename.visit_query_mut(&mut edited);
+        let folded = Rename.fold_query(query.clone());
+        assert_eq!(edited, folded);
+        let mut names = Names::default();
+        names.visit_query(&folded);
+        assert!(names
+            .0
+            .iter()
+            .all(|name| name.starts_with('$') || name.ends_with('2')));
+    }
+
+    #[test]
+    fn default_fold_is_the_identity() {
+        let query = parse_query(QUERY).unwrap();
+        assert_eq!(Identity.fold_query(query.clone()), query);
+    }
+}